@@ -1,207 +1,327 @@
+use crate::device::DeviceSelector;
 use crate::error::{SdpPlayerError, SdpPlayerResult};
+use crate::jitter::JitterBuffer;
+use crate::loudness::{channel_weights, LoudnessMeter};
+use crate::meter::Meter;
+use crate::monitor::PlaybackMonitor;
+use crate::resample::Resampler;
+use crate::routing::{ChannelRouting, RoutingMatrix};
+use crate::sink::{open_sink, Render, SinkSelector};
 use crate::stream::Stream;
+use crate::volume::{GainStage, Volume};
 use crate::BitDepth;
-use cpal::traits::{DeviceTrait, StreamTrait};
-use cpal::{traits::HostTrait, FromSample, SizedSample};
-use cpal::{SampleRate, StreamConfig};
-use std::fmt::Debug;
-use std::{env, thread};
-use tokio::sync::broadcast;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::sync::{broadcast, oneshot};
 use tokio::time::Instant;
 use tokio::{select, spawn};
 
-pub async fn play(mut stream: Stream, stop: broadcast::Sender<()>) -> SdpPlayerResult<()> {
-    let host = cpal::default_host();
-    let descriptor = stream.descriptor.clone();
+/// Jitter buffer latency used when none is configured.
+pub const DEFAULT_LATENCY_MS: f32 = 20.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaybackConfig {
+    /// time in milliseconds received packets are buffered before they are played
+    pub latency_ms: f32,
+    /// which stream channels are played on which device outputs
+    #[serde(default)]
+    pub routing: ChannelRouting,
+    /// only play this channel group (starting at 1) of the stream's channel layout, on
+    /// consecutive device outputs; overrides `routing`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub group: Option<usize>,
+    /// the output device to play on, if the sink is the output device
+    #[serde(default)]
+    pub device: DeviceSelector,
+    /// where to play to
+    #[serde(default)]
+    pub sink: SinkSelector,
+}
 
-    let mut stream_rx = stream.play(stop.clone()).await?;
+impl Default for PlaybackConfig {
+    fn default() -> Self {
+        PlaybackConfig {
+            latency_ms: DEFAULT_LATENCY_MS,
+            routing: ChannelRouting::default(),
+            group: None,
+            device: DeviceSelector::default(),
+            sink: SinkSelector::default(),
+        }
+    }
+}
+
+/// Plays `stream` until `stop` is signalled or playback fails. `started` is notified once the
+/// sink is running. Returns only after the receiver and the sink have been shut down, with the
+/// first error that ended the playback, if any.
+pub async fn play(
+    mut stream: Stream,
+    stop: broadcast::Sender<()>,
+    config: PlaybackConfig,
+    volume: Volume,
+    monitor: PlaybackMonitor,
+    started: Option<oneshot::Sender<()>>,
+) -> SdpPlayerResult<()> {
+    let descriptor = stream.descriptor.clone();
 
-    if let Some(device) = host.default_output_device() {
-        log::info!("Output device: {}", device.name()?);
-
-        let default_config = device.default_output_config().unwrap();
-        log::info!("Default output config: {:?}", default_config);
-
-        let buffer_multiplier: u32 = env::var("BUFFER_MULTIPLIER")
-            .ok()
-            .and_then(|m| m.parse::<u32>().ok())
-            .unwrap_or(45 * descriptor.channels as u32);
-
-        let packet_size = descriptor.buffer_size();
-        let buffer_frames = (packet_size as f32
-            / descriptor.channels as f32
-            / descriptor.bit_depth.bits() as f32) as u32;
-        let receiver_buffer_frames = buffer_frames * buffer_multiplier;
-
-        log::debug!(
-            "Buffer size: {} frames / {} ms; requested receiver buffer size: {} frames / {} ms)",
-            buffer_frames,
-            descriptor.packet_time,
-            receiver_buffer_frames,
-            descriptor.packet_time * buffer_multiplier as f32
+    let routing = match config.group {
+        Some(group) => descriptor
+            .channel_layout
+            .as_ref()
+            .and_then(|layout| layout.group_routing(group))
+            .ok_or(SdpPlayerError::NoSuchChannelGroup(group))?,
+        None => config.routing.clone(),
+    };
+    if let Some(layout) = &descriptor.channel_layout {
+        log::info!(
+            "Channel layout: {layout} [{}]",
+            layout.channel_labels().join(", ")
         );
+    }
 
-        let config = StreamConfig {
-            buffer_size: cpal::BufferSize::Fixed(receiver_buffer_frames),
-            channels: descriptor.channels,
-            sample_rate: SampleRate(descriptor.sample_rate),
-        };
-
-        log::info!("Output config: {:?}", config);
-
-        let (tx, rx) = std::sync::mpsc::channel();
-        let (meter_tx, meter_rx) = std::sync::mpsc::channel();
-
-        let converter = match descriptor.bit_depth {
-            BitDepth::L16 => l16_samples,
-            BitDepth::L24 => l24_samples,
-            BitDepth::L32 => l32_samples,
-            BitDepth::FloatingPoint => f32_samples,
-        };
-
-        let (tx_stop, rx_stop) = std::sync::mpsc::channel();
-        let mut stop_run = stop.subscribe();
-        spawn(async move {
-            stop_run.recv().await.ok();
-            tx_stop.send(()).ok();
-        });
-        thread::spawn(move || {
-            match default_config.sample_format() {
-                cpal::SampleFormat::I8 => {
-                    run::<i8>(&device, &config, rx, converter, meter_tx, rx_stop)
-                }
-                cpal::SampleFormat::I16 => {
-                    run::<i16>(&device, &config, rx, converter, meter_tx, rx_stop)
-                }
-                // cpal::SampleFormat::I24 => run::<I24>(&device, &config),
-                cpal::SampleFormat::I32 => {
-                    run::<i32>(&device, &config, rx, converter, meter_tx, rx_stop)
-                }
-                // cpal::SampleFormat::I48 => run::<I48>(&device, &config),
-                cpal::SampleFormat::I64 => {
-                    run::<i64>(&device, &config, rx, converter, meter_tx, rx_stop)
-                }
-                cpal::SampleFormat::U8 => {
-                    run::<u8>(&device, &config, rx, converter, meter_tx, rx_stop)
-                }
-                cpal::SampleFormat::U16 => {
-                    run::<u16>(&device, &config, rx, converter, meter_tx, rx_stop)
-                }
-                // cpal::SampleFormat::U24 => run::<U24>(&device, &config),
-                cpal::SampleFormat::U32 => {
-                    run::<u32>(&device, &config, rx, converter, meter_tx, rx_stop)
-                }
-                // cpal::SampleFormat::U48 => run::<U48>(&device, &config),
-                cpal::SampleFormat::U64 => {
-                    run::<u64>(&device, &config, rx, converter, meter_tx, rx_stop)
-                }
-                cpal::SampleFormat::F32 => {
-                    run::<f32>(&device, &config, rx, converter, meter_tx, rx_stop)
-                }
-                cpal::SampleFormat::F64 => {
-                    run::<f64>(&device, &config, rx, converter, meter_tx, rx_stop)
-                }
-                sample_format => panic!("Unsupported sample format '{sample_format}'"),
+    let leg_stats = stream.leg_stats();
+    let stream_stats = stream.stats();
+
+    let mut sink = open_sink(&config.sink, &config.device)?;
+    let sink_name = sink.name();
+    log::info!("Output: {sink_name}");
+
+    let format = sink.open(
+        routing.output_channels().unwrap_or(descriptor.channels),
+        descriptor.sample_rate,
+    )?;
+
+    log::debug!(
+        "Packet time: {} ms; jitter buffer latency: {} ms",
+        descriptor.packet_time,
+        config.latency_ms
+    );
+
+    monitor.update(|stats| {
+        stats.device = Some(sink_name);
+        stats.device_sample_rate = Some(format.sample_rate);
+    });
+
+    let mut chain = OutputChain::new(
+        descriptor.sample_rate,
+        format.sample_rate,
+        routing.matrix(descriptor.channels, format.channels),
+        volume,
+    );
+
+    let jitter_buffer = Arc::new(Mutex::new(JitterBuffer::new(
+        descriptor.channels,
+        descriptor.sample_rate,
+        config.latency_ms,
+    )));
+    let (meter_tx, meter_rx) = std::sync::mpsc::channel();
+    let mut meter = Meter::new(descriptor.channels, format.sample_rate)
+        .with_channel_layout(descriptor.channel_layout.as_ref());
+    let mut loudness = LoudnessMeter::new(
+        channel_weights(
+            descriptor.channel_layout.as_ref(),
+            descriptor.channels,
+            config.group,
+        ),
+        format.sample_rate,
+    );
+
+    let converter = converter(&descriptor.bit_depth);
+
+    let mut stream_rx = stream.play(stop.clone()).await?;
+    let recorder = monitor.recorder().clone();
+    recorder.set_stream(Some(descriptor.clone()));
+    let incidents = monitor.incidents().clone();
+    incidents.set_stream(Some(descriptor.clone()));
+    // a single leg reports loss as soon as it sees a gap, the merged legs of a redundant stream
+    // only once a missing packet can no longer arrive on the other leg
+    let loss_stats = match leg_stats.as_slice() {
+        [leg] => leg.clone(),
+        _ => stream_stats.clone(),
+    };
+    let mut lost = loss_stats.lost();
+
+    let (tx_stop, rx_stop) = std::sync::mpsc::channel();
+    let mut stop_run = stop.subscribe();
+    spawn(async move {
+        stop_run.recv().await.ok();
+        tx_stop.send(()).ok();
+    });
+    let render_jitter_buffer = jitter_buffer.clone();
+    let render: Render = Box::new(move |out: &mut [f32]| {
+        let ready_samples = chain.process(&render_jitter_buffer, out);
+        if let Err(e) = meter_tx.send(ready_samples.to_vec()) {
+            log::error!("Error forwarding meter values: {e}");
+        }
+    });
+    let (output_tx, mut output_rx) = oneshot::channel();
+    thread::spawn(move || {
+        output_tx.send(sink.run(render, rx_stop, started)).ok();
+    });
+
+    let sample_rate = descriptor.sample_rate;
+    let meter_jitter_buffer = jitter_buffer.clone();
+    thread::spawn(move || {
+        let mut start = Instant::now();
+        let mut level = f32::MIN;
+        let mut packets_received = 0;
+
+        while let Ok(samples) = meter_rx.recv() {
+            if monitor.take_loudness_reset() {
+                loudness.reset();
             }
-        });
-
-        let sample_rate = descriptor.sample_rate;
-        let channels = descriptor.channels as usize;
-        thread::spawn(move || {
-            let mut start = Instant::now();
-            let mut level = 0.0;
-
-            while let Ok(samples) = meter_rx.recv() {
-                let buffer_size = samples.len();
-                for s in samples {
-                    let l = s.abs();
-                    if l > level {
-                        level = l;
-                    }
+            loudness.process(&samples);
+
+            let mut published = false;
+            meter.process(&samples, |levels| {
+                for channel in &levels.channels {
+                    level = level.max(channel.peak_db);
                 }
-                if start.elapsed().as_secs_f32() >= 1.0 {
-                    let db = 20.0 * level.log10();
-                    let actual_buffer_frames = buffer_size / channels;
-                    log::debug!("Audio level: {db:.2} dB");
+                monitor.meters().publish(levels);
+                published = true;
+            });
+            if published {
+                monitor.update(|stats| stats.loudness = loudness.loudness());
+            }
+            if start.elapsed().as_secs_f32() >= 1.0 {
+                log::debug!("Audio level: {level:.2} dB");
+                let elapsed = start.elapsed().as_secs_f32();
+                let received: u64 = leg_stats.iter().map(|leg| leg.received()).sum();
+                monitor.update(|stats| {
+                    stats.packet_rate = (received - packets_received) as f32 / elapsed;
+                    stats.packets_received = received;
+                    stats.packets_lost = stream_stats.lost();
+                    stats.rtp = leg_stats.iter().map(|leg| leg.rtp()).collect();
+                });
+                packets_received = received;
+                if let Ok(jitter_buffer) = meter_jitter_buffer.lock() {
+                    let fill = jitter_buffer.fill();
+                    monitor.update(|stats| {
+                        stats.buffer_fill_ms = (fill * 1000) as f32 / sample_rate as f32;
+                        stats.drift_ppm = jitter_buffer.drift().ppm();
+                        stats.jitter_buffer = jitter_buffer.stats();
+                    });
+                    log::debug!(
+                        "Jitter buffer fill: {} frames / {} ms; {:?}",
+                        fill,
+                        (fill * 1000) / sample_rate as u64,
+                        jitter_buffer.stats()
+                    );
                     log::debug!(
-                        "Actual receiver buffer size: {} frames / {} ms",
-                        actual_buffer_frames,
-                        (actual_buffer_frames * 1000) / sample_rate as usize
+                        "Clock drift: {:.1} ppm; correction: {:.6}",
+                        jitter_buffer.drift().ppm(),
+                        jitter_buffer.drift().correction()
                     );
-                    start = Instant::now();
-                    level = 0.0;
                 }
+                start = Instant::now();
+                level = f32::MIN;
             }
-        });
-
-        let mut stop = stop.subscribe();
-
-        loop {
-            select! {
-                recv = stream_rx.recv() => {
-                    if let Some(packet) = recv {
-                        tx.send(packet)?;
-                    } else {
-                        break;
+        }
+    });
+
+    let mut stop_rx = stop.subscribe();
+    let mut output_result = None;
+
+    loop {
+        select! {
+            recv = stream_rx.recv() => {
+                if let Some(packet) = recv {
+                    recorder.record(&packet);
+                    let samples = converter(&packet.payload);
+                    let now_lost = loss_stats.lost();
+                    incidents.process(packet.timestamp, &samples, now_lost.saturating_sub(lost));
+                    lost = now_lost;
+                    if let Ok(mut jitter_buffer) = jitter_buffer.lock() {
+                        jitter_buffer.push(packet.timestamp, samples);
                     }
+                } else {
+                    break;
                 }
-                _ = stop.recv() => { break; }
             }
+            result = &mut output_rx => {
+                output_result = Some(result.unwrap_or(Err(SdpPlayerError::OutputFailed)));
+                break;
+            }
+            _ = stop_rx.recv() => { break; }
         }
+    }
+
+    // make sure both receiver and output are shut down, whichever of them ended the playback
+    stop.send(()).ok();
+    let receiver_result = stream.join().await;
+    recorder.set_stream(None);
+    incidents.set_stream(None);
+    let output_result = match output_result {
+        Some(result) => result,
+        None => output_rx.await.unwrap_or(Err(SdpPlayerError::OutputFailed)),
+    };
 
-        log::info!("Playback stopped.");
+    log::info!("Playback stopped.");
 
-        Ok(())
-    } else {
-        Err(SdpPlayerError::NoDefaultDevice)
-    }
+    output_result.and(receiver_result)
 }
 
-pub fn run<T>(
-    device: &cpal::Device,
-    config: &cpal::StreamConfig,
-    rx: std::sync::mpsc::Receiver<Vec<u8>>,
-    converter: fn(&[u8]) -> Vec<f32>,
-    meter_tx: std::sync::mpsc::Sender<Vec<f32>>,
-    stop: std::sync::mpsc::Receiver<()>,
-) -> SdpPlayerResult<()>
-where
-    T: SizedSample + FromSample<f32> + Send + Debug + 'static,
-{
-    let err_fn = |err| log::error!("an error occurred on stream: {}", err);
-
-    let mut ready_samples = Vec::new();
-
-    let data_callback = move |buf: &mut [T], _: &cpal::OutputCallbackInfo| {
-        let buffer_size = buf.len();
-
-        while ready_samples.len() < buffer_size {
-            if let Ok(new_data) = rx.recv() {
-                let new_samples = converter(&new_data);
-                ready_samples.extend(new_samples);
-            } else {
-                break;
-            }
-        }
+/// The processing applied to the audio between the jitter buffer and the sink.
+pub struct OutputChain {
+    resampler: Resampler,
+    routing: RoutingMatrix,
+    gain: GainStage,
+    /// resampled frames in stream channel layout
+    ready_samples: Vec<f32>,
+}
 
-        if let Err(e) = meter_tx.send((&ready_samples[0..buffer_size]).to_owned()) {
-            log::error!("Error forwarding meter values: {e}");
+impl OutputChain {
+    pub fn new(
+        stream_sample_rate: u32,
+        device_sample_rate: u32,
+        routing: RoutingMatrix,
+        volume: Volume,
+    ) -> Self {
+        OutputChain {
+            resampler: Resampler::new(
+                routing.inputs() as u16,
+                stream_sample_rate,
+                device_sample_rate,
+            ),
+            gain: GainStage::new(volume, routing.outputs() as u16, device_sample_rate),
+            routing,
+            ready_samples: Vec::new(),
         }
+    }
 
-        let mut output = buf.iter_mut();
-
-        for s in ready_samples.drain(0..buffer_size.min(ready_samples.len())) {
-            let sample = output.next().expect("buffer overflow");
-            *sample = T::from_sample::<f32>(s);
+    /// Fills `out` with device frames pulled from `jitter_buffer` and returns the frames before
+    /// routing and gain were applied.
+    fn process(&mut self, jitter_buffer: &Mutex<JitterBuffer>, out: &mut [f32]) -> &[f32] {
+        let frames = out.len() / self.routing.outputs();
+        self.ready_samples
+            .resize(frames * self.routing.inputs(), 0.0);
+
+        match jitter_buffer.lock() {
+            Ok(mut jitter_buffer) => {
+                let correction = jitter_buffer.drift().correction();
+                self.resampler
+                    .process(correction, &mut self.ready_samples, |input| {
+                        jitter_buffer.pull(input)
+                    });
+            }
+            Err(_) => self.ready_samples.fill(0.0),
         }
-    };
 
-    let stream = device.build_output_stream(config, data_callback, err_fn, None)?;
-    stream.play()?;
+        self.routing.apply(&self.ready_samples, out);
+        self.gain.process(out);
 
-    stop.recv().ok();
+        &self.ready_samples
+    }
+}
 
-    Ok(())
+/// The function decoding RTP payloads of `bit_depth` to samples in the range -1.0 to 1.0.
+pub(crate) fn converter(bit_depth: &BitDepth) -> fn(&[u8]) -> Vec<f32> {
+    match bit_depth {
+        BitDepth::L16 => l16_samples,
+        BitDepth::L24 => l24_samples,
+        BitDepth::L32 => l32_samples,
+        BitDepth::FloatingPoint => f32_samples,
+    }
 }
 
 fn l16_samples(bytes: &[u8]) -> Vec<f32> {
@@ -255,7 +375,7 @@ fn l32_samples(bytes: &[u8]) -> Vec<f32> {
 fn f32_samples(bytes: &[u8]) -> Vec<f32> {
     let mut out = Vec::new();
 
-    for sample_bytes in bytes.chunks(3) {
+    for sample_bytes in bytes.chunks(4) {
         let mut sample = [0; 4];
         for (i, b) in sample_bytes.iter().enumerate() {
             sample[i] = *b;
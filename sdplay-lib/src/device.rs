@@ -0,0 +1,157 @@
+use crate::error::{SdpPlayerError, SdpPlayerResult};
+use cpal::traits::{DeviceTrait, HostTrait};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// An output device of one of the available audio hosts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Object)]
+pub struct OutputDevice {
+    /// position in the list of all output devices, usable as device selector
+    pub index: usize,
+    pub host: String,
+    pub name: String,
+    /// whether this is the default output device of its host
+    pub default: bool,
+    pub supported_configs: Vec<SupportedOutputConfig>,
+}
+
+/// A range of stream configs supported by an [`OutputDevice`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Object)]
+pub struct SupportedOutputConfig {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: String,
+}
+
+impl fmt::Display for SupportedOutputConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} channels, ", self.channels)?;
+        if self.min_sample_rate == self.max_sample_rate {
+            write!(f, "{} Hz, ", self.min_sample_rate)?;
+        } else {
+            write!(f, "{}-{} Hz, ", self.min_sample_rate, self.max_sample_rate)?;
+        }
+        write!(f, "{}", self.sample_format)
+    }
+}
+
+/// Selects the output device to play on.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DeviceSelector {
+    /// the default output device of the default host
+    #[default]
+    Default,
+    /// the device at this position in [`list_output_devices`]
+    Index(usize),
+    /// the first device whose name, or `host/name`, matches exactly, or else contains this
+    /// string, ignoring case
+    Name(String),
+}
+
+impl FromStr for DeviceSelector {
+    type Err = SdpPlayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() || s.eq_ignore_ascii_case("default") {
+            Ok(DeviceSelector::Default)
+        } else if let Ok(index) = s.parse() {
+            Ok(DeviceSelector::Index(index))
+        } else {
+            Ok(DeviceSelector::Name(s.to_owned()))
+        }
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSelector::Default => write!(f, "default"),
+            DeviceSelector::Index(index) => write!(f, "{index}"),
+            DeviceSelector::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Lists the output devices of all available hosts, including their supported configs.
+pub fn list_output_devices() -> SdpPlayerResult<Vec<OutputDevice>> {
+    let mut devices = Vec::new();
+    for (host, default, device) in output_devices()? {
+        let name = device.name()?;
+        let supported_configs = match device.supported_output_configs() {
+            Ok(configs) => configs
+                .map(|c| SupportedOutputConfig {
+                    channels: c.channels(),
+                    min_sample_rate: c.min_sample_rate().0,
+                    max_sample_rate: c.max_sample_rate().0,
+                    sample_format: c.sample_format().to_string(),
+                })
+                .collect(),
+            Err(e) => {
+                log::warn!("Could not query configs of output device '{name}': {e}");
+                Vec::new()
+            }
+        };
+        devices.push(OutputDevice {
+            index: devices.len(),
+            host: host.to_owned(),
+            default,
+            name,
+            supported_configs,
+        });
+    }
+    Ok(devices)
+}
+
+/// Opens the output device matching `selector`.
+pub fn find_output_device(selector: &DeviceSelector) -> SdpPlayerResult<cpal::Device> {
+    match selector {
+        DeviceSelector::Default => cpal::default_host()
+            .default_output_device()
+            .ok_or(SdpPlayerError::NoDefaultDevice),
+        DeviceSelector::Index(index) => output_devices()?
+            .into_iter()
+            .nth(*index)
+            .map(|(_, _, device)| device)
+            .ok_or_else(|| SdpPlayerError::NoSuchDevice(selector.to_string())),
+        DeviceSelector::Name(name) => {
+            let mut devices = Vec::new();
+            for (host, _, device) in output_devices()? {
+                let device_name = device.name()?;
+                let qualified_name = format!("{host}/{device_name}");
+                devices.push((device_name, qualified_name, device));
+            }
+            let lower = name.to_lowercase();
+            let position = devices
+                .iter()
+                .position(|(device_name, qualified_name, _)| {
+                    device_name == name || qualified_name == name
+                })
+                .or_else(|| {
+                    devices.iter().position(|(_, qualified_name, _)| {
+                        qualified_name.to_lowercase().contains(&lower)
+                    })
+                })
+                .ok_or_else(|| SdpPlayerError::NoSuchDevice(name.to_owned()))?;
+            Ok(devices.swap_remove(position).2)
+        }
+    }
+}
+
+/// All output devices of all available hosts, along with the name of their host and whether
+/// they are the host's default output device.
+fn output_devices() -> SdpPlayerResult<Vec<(&'static str, bool, cpal::Device)>> {
+    let mut devices = Vec::new();
+    for host_id in cpal::available_hosts() {
+        let host = cpal::host_from_id(host_id)?;
+        let default_name = host.default_output_device().and_then(|d| d.name().ok());
+        for device in host.output_devices()? {
+            let default = default_name.is_some() && device.name().ok() == default_name;
+            devices.push((host_id.name(), default, device));
+        }
+    }
+    Ok(devices)
+}
@@ -4,12 +4,18 @@ use std::{
     num::{ParseFloatError, ParseIntError},
 };
 
-use cpal::{BuildStreamError, DeviceNameError, PlayStreamError};
+use cpal::{
+    BuildStreamError, DefaultStreamConfigError, DeviceNameError, DevicesError, HostUnavailable,
+    PlayStreamError, StreamError, SupportedStreamConfigsError,
+};
 use http::StatusCode;
 use poem::error::ResponseError;
 use rtp_rs::RtpReaderError;
 use thiserror::Error;
-use tokio::sync::{broadcast, mpsc::error::SendError};
+use tokio::{
+    sync::{broadcast, mpsc::error::SendError},
+    task::JoinError,
+};
 
 #[derive(Error, Debug)]
 pub enum SdpPlayerError {
@@ -31,8 +37,6 @@ pub enum SdpPlayerError {
     InvalidChannels(ParseIntError),
     #[error("invalid sample rate: {0}")]
     InvalidSampleRate(ParseIntError),
-    #[error("invalid buffer multiplier: {0}")]
-    InvalidBufferMultiplier(ParseIntError),
     #[cfg(feature = "net")]
     #[error("reqwest error: {0}")]
     ReqwestError(#[from] reqwest::Error),
@@ -48,6 +52,32 @@ pub enum SdpPlayerError {
     UnsupportedMediaType(String),
     #[error("malformed media/transport descriptor: {0}")]
     MalformedMediaTransport(String),
+    #[error("malformed origin: {0}")]
+    MalformedOrigin(String),
+    #[error("malformed bandwidth information: {0}")]
+    MalformedBandwidth(String),
+    #[error("malformed timing: {0}")]
+    MalformedTiming(String),
+    #[error("malformed attribute: {0}")]
+    MalformedAttribute(String),
+    #[error("malformed source-filter attribute: {0}")]
+    MalformedSourceFilter(String),
+    #[error("malformed rtcp attribute: {0}")]
+    MalformedRtcpAttribute(String),
+    #[error("malformed capture file: {0}")]
+    MalformedCapture(String),
+    #[error("malformed RTCP packet")]
+    MalformedRtcpPacket,
+    #[error("malformed channel order: {0}")]
+    MalformedChannelOrder(String),
+    #[error("stream has no channel group {0}")]
+    NoSuchChannelGroup(usize),
+    #[error("malformed channel routing: {0}")]
+    MalformedRouting(String),
+    #[error("no media section matching '{0}' found")]
+    NoSuchStream(String),
+    #[error("media section '{0}' can't be played")]
+    UnusableStream(String),
     #[error("no config dir found")]
     NoConfigDir,
     #[error("yaml serde error: {0}")]
@@ -64,6 +94,24 @@ pub enum SdpPlayerError {
     Ipv6,
     #[error("receiver already started")]
     ReceiverAlreadystarted,
+    #[error("player is already playing")]
+    AlreadyPlaying,
+    #[error("no such player: {0}")]
+    NoSuchPlayer(String),
+    #[error("player already exists: {0}")]
+    PlayerExists(String),
+    #[error("invalid player id '{0}', only letters, digits, '-' and '_' are allowed")]
+    InvalidPlayerId(String),
+    #[error("playback task failed: {0}")]
+    TaskFailed(#[from] JoinError),
+    #[error("unsupported sample format: {0}")]
+    UnsupportedSampleFormat(String),
+    #[error("audio output ended unexpectedly")]
+    OutputFailed,
+    #[error("output stream error: {0}")]
+    OutputStreamError(#[from] StreamError),
+    #[error("redundant streams must share the same audio format")]
+    IncompatibleRedundantStreams,
     #[error("device name error: {0}")]
     DeviceNameError(#[from] DeviceNameError),
     #[error("play stream error: {0}")]
@@ -72,6 +120,26 @@ pub enum SdpPlayerError {
     BuildStreamError(#[from] BuildStreamError),
     #[error("no default output device found")]
     NoDefaultDevice,
+    #[error("no output device matching '{0}' found")]
+    NoSuchDevice(String),
+    #[error("audio host unavailable: {0}")]
+    HostUnavailable(#[from] HostUnavailable),
+    #[error("devices error: {0}")]
+    DevicesError(#[from] DevicesError),
+    #[error("default stream config error: {0}")]
+    DefaultStreamConfigError(#[from] DefaultStreamConfigError),
+    #[error("supported stream configs error: {0}")]
+    SupportedStreamConfigsError(#[from] SupportedStreamConfigsError),
+    #[error("output device does not support any stream config")]
+    NoSupportedOutputConfig,
+    #[error("invalid sink '{0}', expected 'device', 'wav:<path>', 'stdout' or 'null'")]
+    InvalidSink(String),
+    #[error("already recording")]
+    AlreadyRecording,
+    #[error("invalid recording duration: {0} s")]
+    InvalidRecordingDuration(f64),
+    #[error("invalid recording prefix '{0}', only letters, digits, '-' and '_' are allowed")]
+    InvalidRecordingPrefix(String),
 }
 
 impl SdpPlayerError {
@@ -102,10 +170,6 @@ impl SdpPlayerError {
     pub fn invalid_sample_rate(e: ParseIntError) -> Self {
         Self::InvalidSampleRate(e)
     }
-
-    pub fn invalid_buffer_multiplier(e: ParseIntError) -> Self {
-        Self::InvalidBufferMultiplier(e)
-    }
 }
 
 pub type SdpPlayerResult<T> = Result<T, SdpPlayerError>;
@@ -131,6 +195,14 @@ impl<E, V> ToSdpPlayerResult<E, V> for Result<V, E> {
 impl ResponseError for SdpPlayerError {
     fn status(&self) -> StatusCode {
         // TODO do this in a more elaborate way
-        StatusCode::INTERNAL_SERVER_ERROR
+        match self {
+            SdpPlayerError::NoSuchPlayer(_) => StatusCode::NOT_FOUND,
+            SdpPlayerError::PlayerExists(_) => StatusCode::CONFLICT,
+            SdpPlayerError::InvalidPlayerId(_) => StatusCode::BAD_REQUEST,
+            SdpPlayerError::AlreadyRecording => StatusCode::CONFLICT,
+            SdpPlayerError::InvalidRecordingPrefix(_)
+            | SdpPlayerError::InvalidRecordingDuration(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
     }
 }
@@ -0,0 +1,790 @@
+use crate::{
+    audio::converter,
+    error::SdpPlayerResult,
+    meter::{ChannelLevel, Meter, MeterLevels},
+    pcap::{PcapReader, UdpDatagram},
+    recorder::{to_little_endian, wav_encoding},
+    rtp::{ExtendedTimestamps, Reception, RtpStatistics, RtpStats},
+    stream::{leg_source_filters, parse_rtp_packet, source_allowed, RtpPacket},
+    wav::{WavFormat, WavWriter},
+    BitDepth, SessionDescriptor,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{BufWriter, Read},
+    net::{IpAddr, SocketAddrV4},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Sample rates a guessed clock rate is rounded to.
+const STANDARD_SAMPLE_RATES: [u32; 9] = [
+    8_000, 16_000, 32_000, 44_100, 48_000, 88_200, 96_000, 176_400, 192_000,
+];
+/// Packets of a flow no SDP describes that are looked at to guess its format.
+const GUESS_PACKETS: usize = 50;
+/// Most channels a guessed format may have.
+const MAX_GUESSED_CHANNELS: usize = 64;
+/// Payload types that RTCP packets sharing a port with RTP would show up as (RFC 5761).
+const RTCP_PAYLOAD_TYPES: std::ops::RangeInclusive<u8> = 72..=76;
+/// Packets held back so that reordered packets can be written in place.
+const REORDER_WINDOW: usize = 64;
+/// Longest gap in a flow that is filled with silence; a longer one is skipped.
+const MAX_CONCEALED_GAP: Duration = Duration::from_secs(1);
+
+/// Where to extract the flows of a capture to and how to decode them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtractConfig {
+    /// directory the WAV files are written to, created if missing
+    pub directory: PathBuf,
+    /// streams expected in the capture; flows none of them describes have their format guessed
+    pub descriptors: Vec<SessionDescriptor>,
+}
+
+/// What was extracted from one RTP flow of a capture, i.e. the packets of one SSRC sent from
+/// one source to one destination.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowReport {
+    pub source: SocketAddrV4,
+    pub destination: SocketAddrV4,
+    pub ssrc: u32,
+    pub payload_type: u8,
+    /// the format the flow was decoded with, `None` if it could not be guessed
+    pub descriptor: Option<SessionDescriptor>,
+    /// whether the format was guessed from the packets rather than taken from an SDP
+    pub guessed: bool,
+    /// the WAV file the flow was extracted to
+    pub file: Option<PathBuf>,
+    /// duration of the extracted audio, including concealed gaps
+    pub duration: Duration,
+    pub stats: RtpStats,
+    /// frames of silence written in place of lost packets
+    pub concealed_frames: u64,
+    /// packets that arrived too late to be put in place, or overlapped the packet before them
+    pub late_packets: u64,
+    /// peak, RMS and true-peak levels over the whole flow
+    pub levels: Vec<ChannelLevel>,
+    /// why the flow could not be extracted, completely or at all
+    pub error: Option<String>,
+}
+
+/// Extracts all RTP flows of the pcap or pcapng file `capture` to WAV files, as fast as the
+/// capture can be read, and reports on each of them in the order they first appear.
+pub fn extract(capture: &Path, config: &ExtractConfig) -> SdpPlayerResult<Vec<FlowReport>> {
+    extract_from(PcapReader::open(capture)?, config)
+}
+
+fn extract_from<R: Read>(
+    capture: PcapReader<R>,
+    config: &ExtractConfig,
+) -> SdpPlayerResult<Vec<FlowReport>> {
+    fs::create_dir_all(&config.directory)?;
+
+    let mut flows: Vec<Flow> = Vec::new();
+    let mut index = HashMap::new();
+    let mut first_time = None;
+    // packet arrival is tracked as instants, so capture times are placed relative to this one
+    let base = Instant::now();
+
+    for datagram in capture {
+        let UdpDatagram {
+            time,
+            source,
+            destination,
+            payload,
+        } = datagram?;
+        let Ok(packet) = parse_rtp_packet(&payload) else {
+            continue;
+        };
+        if RTCP_PAYLOAD_TYPES.contains(&packet.payload_type) {
+            continue;
+        }
+        let first_time = *first_time.get_or_insert(time);
+        let arrival = base + time.saturating_sub(first_time);
+
+        let key = (source, destination, packet.ssrc);
+        let flow = match index.get(&key) {
+            Some(&i) => &mut flows[i],
+            None => {
+                index.insert(key, flows.len());
+                let flow = Flow::new(flows.len() + 1, source, destination, &packet, config);
+                flows.push(flow);
+                flows.last_mut().expect("a flow was just added")
+            }
+        };
+        flow.push(packet, arrival, &config.directory);
+    }
+
+    Ok(flows
+        .into_iter()
+        .map(|flow| flow.finish(&config.directory))
+        .collect())
+}
+
+struct Flow {
+    number: usize,
+    report: FlowReport,
+    state: FlowState,
+}
+
+enum FlowState {
+    /// collecting packets until there are enough to guess the format from
+    Guessing(Vec<(RtpPacket, Instant)>),
+    Decoding(Box<Decoder>),
+    /// the format could not be guessed
+    Unknown,
+}
+
+impl Flow {
+    fn new(
+        number: usize,
+        source: SocketAddrV4,
+        destination: SocketAddrV4,
+        packet: &RtpPacket,
+        config: &ExtractConfig,
+    ) -> Self {
+        let descriptor = config
+            .descriptors
+            .iter()
+            .find(|descriptor| describes(descriptor, source, destination, packet))
+            .cloned();
+        let mut flow = Flow {
+            number,
+            report: FlowReport {
+                source,
+                destination,
+                ssrc: packet.ssrc,
+                payload_type: packet.payload_type,
+                descriptor: None,
+                guessed: descriptor.is_none(),
+                file: None,
+                duration: Duration::ZERO,
+                stats: RtpStats::default(),
+                concealed_frames: 0,
+                late_packets: 0,
+                levels: Vec::new(),
+                error: None,
+            },
+            state: FlowState::Guessing(Vec::new()),
+        };
+        if let Some(descriptor) = descriptor {
+            flow.start(descriptor, &config.directory);
+        }
+        flow
+    }
+
+    fn push(&mut self, packet: RtpPacket, arrival: Instant, directory: &Path) {
+        match &mut self.state {
+            FlowState::Decoding(decoder) => decoder.push(&packet, arrival),
+            FlowState::Guessing(packets) => {
+                packets.push((packet, arrival));
+                if packets.len() >= GUESS_PACKETS {
+                    self.guess(directory);
+                }
+            }
+            FlowState::Unknown => {}
+        }
+    }
+
+    fn guess(&mut self, directory: &Path) {
+        let FlowState::Guessing(packets) = std::mem::replace(&mut self.state, FlowState::Unknown)
+        else {
+            return;
+        };
+        let Some(descriptor) = guess_descriptor(self.report.destination, &packets) else {
+            self.report.error = Some("the stream format could not be guessed".to_owned());
+            return;
+        };
+        self.start(descriptor, directory);
+        if let FlowState::Decoding(decoder) = &mut self.state {
+            for (packet, arrival) in &packets {
+                decoder.push(packet, *arrival);
+            }
+        }
+    }
+
+    fn start(&mut self, descriptor: SessionDescriptor, directory: &Path) {
+        let path = directory.join(format!(
+            "{:02}-{}-{}-{:08x}.wav",
+            self.number,
+            self.report.destination.ip(),
+            self.report.destination.port(),
+            self.report.ssrc
+        ));
+        let format = WavFormat {
+            channels: descriptor.channels.max(1),
+            sample_rate: descriptor.sample_rate.max(1),
+            encoding: wav_encoding(&descriptor.bit_depth),
+        };
+        let writer =
+            File::create(&path).and_then(|file| WavWriter::new(BufWriter::new(file), format));
+        let writer = match writer {
+            Ok(writer) => {
+                self.report.file = Some(path);
+                Some(writer)
+            }
+            Err(e) => {
+                self.report.error = Some(format!("could not create {}: {e}", path.display()));
+                None
+            }
+        };
+        self.state = FlowState::Decoding(Box::new(Decoder::new(&descriptor, format, writer)));
+        self.report.descriptor = Some(descriptor);
+    }
+
+    fn finish(mut self, directory: &Path) -> FlowReport {
+        if matches!(self.state, FlowState::Guessing(_)) {
+            self.guess(directory);
+        }
+        if let FlowState::Decoding(decoder) = self.state {
+            decoder.finish(&mut self.report);
+        }
+        self.report
+    }
+}
+
+/// Whether `descriptor` describes the flow `packet` is part of.
+fn describes(
+    descriptor: &SessionDescriptor,
+    source: SocketAddrV4,
+    destination: SocketAddrV4,
+    packet: &RtpPacket,
+) -> bool {
+    destination == SocketAddrV4::new(descriptor.multicast_address, descriptor.multicast_port)
+        && source_allowed(&leg_source_filters(descriptor), IpAddr::V4(*source.ip()))
+        && (descriptor.payload_types.is_empty()
+            || descriptor.payload_types.contains(&packet.payload_type))
+        && descriptor.ssrc.is_none_or(|ssrc| ssrc == packet.ssrc)
+}
+
+/// Guesses the format of a flow from its first packets: the frame size from the payload size
+/// and timestamp increment of consecutive packets, the sample rate from how fast the timestamps
+/// advance. Frames of a multiple of 3 bytes are taken as L24, as AES67 streams usually are,
+/// others as L16.
+fn guess_descriptor(
+    destination: SocketAddrV4,
+    packets: &[(RtpPacket, Instant)],
+) -> Option<SessionDescriptor> {
+    let frames = packets.windows(2).find_map(|pair| {
+        let [(previous, _), (next, _)] = pair else {
+            return None;
+        };
+        let frames = next.timestamp.wrapping_sub(previous.timestamp) as usize;
+        (next.sequence_number == previous.sequence_number.wrapping_add(1)
+            && frames > 0
+            && !previous.payload.is_empty()
+            && previous.payload.len() % frames == 0)
+            .then_some(frames)
+    })?;
+    let payload_size = packets
+        .iter()
+        .map(|(packet, _)| packet.payload.len())
+        .find(|size| *size > 0 && size % frames == 0)?;
+    let frame_size = payload_size / frames;
+    let (bit_depth, channels) = if frame_size % 3 == 0 {
+        (BitDepth::L24, frame_size / 3)
+    } else if frame_size % 2 == 0 {
+        (BitDepth::L16, frame_size / 2)
+    } else {
+        return None;
+    };
+    if channels > MAX_GUESSED_CHANNELS {
+        return None;
+    }
+
+    let (first, first_arrival) = packets.first()?;
+    let (last, last_arrival) = packets.last()?;
+    let elapsed = last_arrival.duration_since(*first_arrival).as_secs_f64();
+    let advanced = last.timestamp.wrapping_sub(first.timestamp) as f64;
+    let sample_rate = if elapsed > 0.0 && advanced > 0.0 {
+        let measured = advanced / elapsed;
+        STANDARD_SAMPLE_RATES
+            .into_iter()
+            .min_by(|a, b| {
+                let distance = |rate: u32| (measured / rate as f64).ln().abs();
+                distance(*a).total_cmp(&distance(*b))
+            })
+            .expect("there are standard sample rates")
+    } else {
+        48_000
+    };
+
+    Some(SessionDescriptor {
+        multicast_address: *destination.ip(),
+        multicast_port: destination.port(),
+        bit_depth,
+        channels: channels as u16,
+        sample_rate,
+        packet_time: (frames as f64 * 1_000.0 / sample_rate as f64) as f32,
+        mid: None,
+        source_filters: Vec::new(),
+        channel_layout: None,
+        rtcp_port: None,
+        rtcp_address: None,
+        payload_types: vec![first.payload_type],
+        ssrc: Some(first.ssrc),
+        session_name: None,
+    })
+}
+
+/// Writes the samples of a flow to its WAV file as they are, in timestamp order, and decodes
+/// them to measure their levels.
+struct Decoder {
+    converter: fn(&[u8]) -> Vec<f32>,
+    format: WavFormat,
+    statistics: RtpStatistics,
+    timestamps: ExtendedTimestamps,
+    /// payloads cut to whole frames by extended timestamp, waiting for reordered packets
+    /// before them
+    pending: BTreeMap<u64, Vec<u8>>,
+    /// extended timestamp of the next frame to write
+    position: Option<u64>,
+    writer: Option<WavWriter<BufWriter<File>>>,
+    error: Option<String>,
+    frames: u64,
+    concealed_frames: u64,
+    late_packets: u64,
+    meter: Meter,
+    levels: Levels,
+}
+
+impl Decoder {
+    fn new(
+        descriptor: &SessionDescriptor,
+        format: WavFormat,
+        writer: Option<WavWriter<BufWriter<File>>>,
+    ) -> Self {
+        Decoder {
+            converter: converter(&descriptor.bit_depth),
+            format,
+            statistics: RtpStatistics::new(
+                descriptor.sample_rate,
+                Some(descriptor.buffer_size() as usize / 8),
+            ),
+            timestamps: ExtendedTimestamps::default(),
+            pending: BTreeMap::new(),
+            position: None,
+            writer,
+            error: None,
+            frames: 0,
+            concealed_frames: 0,
+            late_packets: 0,
+            meter: Meter::new(descriptor.channels, descriptor.sample_rate)
+                .with_channel_layout(descriptor.channel_layout.as_ref()),
+            levels: Levels::default(),
+        }
+    }
+
+    fn push(&mut self, packet: &RtpPacket, arrival: Instant) {
+        match self.statistics.track(packet, arrival) {
+            Reception::Duplicate | Reception::Discarded | Reception::Rejected => return,
+            Reception::Start | Reception::InOrder { .. } | Reception::Late => {}
+        }
+        let timestamp = self.timestamps.extend(packet.timestamp);
+        if self.position.is_some_and(|position| timestamp < position) {
+            self.late_packets += 1;
+            return;
+        }
+        let block_align = self.format.block_align() as usize;
+        let frames = packet.payload.len() / block_align;
+        self.pending
+            .insert(timestamp, packet.payload[..frames * block_align].to_vec());
+        while self.pending.len() > REORDER_WINDOW {
+            self.write_next();
+        }
+    }
+
+    fn write_next(&mut self) {
+        let Some((timestamp, payload)) = self.pending.pop_first() else {
+            return;
+        };
+        let block_align = self.format.block_align() as usize;
+        let position = *self.position.get_or_insert(timestamp);
+        let payload = match timestamp.checked_sub(position) {
+            Some(gap) => {
+                self.conceal(gap);
+                &payload[..]
+            }
+            None => {
+                // the payload overlaps the one written before it, e.g. after a sender restart
+                // or a duplicate of another length, so only the frames past that one are written
+                self.late_packets += 1;
+                let overlap = usize::try_from(position - timestamp).unwrap_or(usize::MAX);
+                match payload.get(overlap.saturating_mul(block_align)..) {
+                    Some(rest) if !rest.is_empty() => rest,
+                    _ => return,
+                }
+            }
+        };
+
+        let samples = (self.converter)(payload);
+        let interval_frames = self.meter.interval_frames();
+        self.meter
+            .process(&samples, |levels| self.levels.add(&levels, interval_frames));
+        let bytes_per_sample = self.format.encoding.bytes_per_sample() as usize;
+        self.write(&to_little_endian(payload, bytes_per_sample));
+        self.position = Some(timestamp.max(position) + (payload.len() / block_align) as u64);
+    }
+
+    /// Fills a gap of `frames` before the next payload with silence, unless it is too long.
+    fn conceal(&mut self, frames: u64) {
+        let max_gap = MAX_CONCEALED_GAP.as_secs() * self.format.sample_rate as u64;
+        if frames > max_gap {
+            log::warn!(
+                "Skipping a gap of {frames} frames in SSRC {}",
+                self.statistics.stats().ssrc.unwrap_or_default()
+            );
+        } else if frames > 0 {
+            self.write(&vec![
+                0;
+                frames as usize * self.format.block_align() as usize
+            ]);
+            self.concealed_frames += frames;
+        }
+    }
+
+    /// Writes whole frames of little endian samples.
+    fn write(&mut self, data: &[u8]) {
+        self.frames += (data.len() / self.format.block_align() as usize) as u64;
+        if let Some(writer) = &mut self.writer {
+            if let Err(e) = writer.write_data(data) {
+                self.error = Some(format!("writing failed: {e}"));
+                self.writer = None;
+            }
+        }
+    }
+
+    fn finish(mut self, report: &mut FlowReport) {
+        while !self.pending.is_empty() {
+            self.write_next();
+        }
+        if let Some((levels, frames)) = self.meter.flush() {
+            self.levels.add(&levels, frames);
+        }
+        if let Some(writer) = self.writer.take() {
+            if let Err(e) = writer.finish() {
+                self.error = Some(format!("writing failed: {e}"));
+            }
+        }
+
+        report.duration =
+            Duration::from_secs_f64(self.frames as f64 / self.format.sample_rate as f64);
+        report.stats = self.statistics.stats().clone();
+        report.concealed_frames = self.concealed_frames;
+        report.late_packets = self.late_packets;
+        report.levels = self.levels.channels();
+        if self.error.is_some() {
+            report.error = self.error;
+        }
+    }
+}
+
+/// Combines consecutive meter readings into levels over all of them.
+#[derive(Debug, Default)]
+struct Levels {
+    frames: usize,
+    labels: Vec<Option<String>>,
+    peak_db: Vec<f32>,
+    true_peak_db: Vec<f32>,
+    /// sum of the mean square of each reading, weighted by the frames it covers
+    power: Vec<f64>,
+}
+
+impl Levels {
+    fn add(&mut self, levels: &MeterLevels, frames: usize) {
+        let channels = levels.channels.len();
+        self.labels = levels
+            .channels
+            .iter()
+            .map(|level| level.label.clone())
+            .collect();
+        self.peak_db.resize(channels, f32::MIN);
+        self.true_peak_db.resize(channels, f32::MIN);
+        self.power.resize(channels, 0.0);
+        for (channel, level) in levels.channels.iter().enumerate() {
+            self.peak_db[channel] = self.peak_db[channel].max(level.peak_db);
+            self.true_peak_db[channel] = self.true_peak_db[channel].max(level.true_peak_db);
+            self.power[channel] += 10f64.powf(level.rms_db as f64 / 10.0) * frames as f64;
+        }
+        self.frames += frames;
+    }
+
+    fn channels(&self) -> Vec<ChannelLevel> {
+        (0..self.power.len())
+            .map(|channel| ChannelLevel {
+                label: self.labels[channel].clone(),
+                peak_db: self.peak_db[channel],
+                rms_db: (10.0 * (self.power[channel] / self.frames as f64).log10()) as f32,
+                true_peak_db: self.true_peak_db[channel],
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{self, temp_dir};
+    use std::{io::Cursor, net::Ipv4Addr};
+
+    const SOURCE: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 5004);
+    const DESTINATION: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 1, 1, 1), 5004);
+
+    fn rtp(sequence_number: u16, timestamp: u32, ssrc: u32, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x80, 98];
+        packet.extend_from_slice(&sequence_number.to_be_bytes());
+        packet.extend_from_slice(&timestamp.to_be_bytes());
+        packet.extend_from_slice(&ssrc.to_be_bytes());
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn ipv4_udp(source: SocketAddrV4, destination: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+        let mut ip = vec![0x45, 0, 0, 0, 0, 0, 0x40, 0, 64, 17, 0, 0];
+        ip.extend_from_slice(&source.ip().octets());
+        ip.extend_from_slice(&destination.ip().octets());
+        ip.extend_from_slice(&source.port().to_be_bytes());
+        ip.extend_from_slice(&destination.port().to_be_bytes());
+        ip.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        ip.extend_from_slice(&[0, 0]);
+        ip.extend_from_slice(payload);
+        let len = (ip.len() as u16).to_be_bytes();
+        ip[2..4].copy_from_slice(&len);
+        ip
+    }
+
+    /// A raw IPv4 pcap of `datagrams` captured at the given microseconds.
+    fn pcap(datagrams: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut file = 0xa1b2_c3d4u32.to_le_bytes().to_vec();
+        file.extend_from_slice(&[2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        file.extend_from_slice(&65535u32.to_le_bytes());
+        file.extend_from_slice(&101u32.to_le_bytes());
+        for (micros, datagram) in datagrams {
+            file.extend_from_slice(&(micros / 1_000_000).to_le_bytes());
+            file.extend_from_slice(&(micros % 1_000_000).to_le_bytes());
+            file.extend_from_slice(&(datagram.len() as u32).to_le_bytes());
+            file.extend_from_slice(&(datagram.len() as u32).to_le_bytes());
+            file.extend_from_slice(datagram);
+        }
+        file
+    }
+
+    fn descriptor() -> SessionDescriptor {
+        SessionDescriptor {
+            bit_depth: BitDepth::L16,
+            channels: 1,
+            payload_types: vec![98],
+            ..testing::descriptor()
+        }
+    }
+
+    fn packets(frame_size: usize, frames: u32, packet_time_us: u64) -> Vec<(RtpPacket, Instant)> {
+        let start = Instant::now();
+        (0..10u16)
+            .map(|i| {
+                let packet = RtpPacket {
+                    sequence_number: i,
+                    timestamp: 1000 + i as u32 * frames,
+                    ssrc: 7,
+                    payload_type: 97,
+                    payload: vec![0; frame_size * frames as usize],
+                };
+                (
+                    packet,
+                    start + Duration::from_micros(i as u64 * packet_time_us),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn guesses_format() {
+        let guessed = guess_descriptor(DESTINATION, &packets(6, 48, 1_000)).unwrap();
+        assert_eq!(guessed.bit_depth, BitDepth::L24);
+        assert_eq!(guessed.channels, 2);
+        assert_eq!(guessed.sample_rate, 48_000);
+        assert_eq!(guessed.packet_time, 1.0);
+        assert_eq!(guessed.payload_types, vec![97]);
+
+        let guessed = guess_descriptor(DESTINATION, &packets(16, 6, 125)).unwrap();
+        assert_eq!(guessed.bit_depth, BitDepth::L16);
+        assert_eq!(guessed.channels, 8);
+        assert_eq!(guessed.sample_rate, 48_000);
+        assert_eq!(guessed.packet_time, 0.125);
+
+        let guessed = guess_descriptor(DESTINATION, &packets(24, 12, 125)).unwrap();
+        assert_eq!(guessed.channels, 8);
+        assert_eq!(guessed.sample_rate, 96_000);
+
+        assert_eq!(guess_descriptor(DESTINATION, &packets(5, 48, 1_000)), None);
+        assert_eq!(
+            guess_descriptor(DESTINATION, &packets(6, 48, 1_000)[..1]),
+            None
+        );
+    }
+
+    #[test]
+    fn extracts_flows_bit_exactly() {
+        let directory = temp_dir("extract");
+
+        let payload = |i: u32| -> Vec<u8> {
+            (0..48)
+                .flat_map(|k| ((i * 48 + k) as i16).wrapping_mul(7).to_be_bytes())
+                .collect()
+        };
+        // packet 100 is lost and packets 150 and 151 arrive swapped
+        let mut order: Vec<u32> = (0..200).filter(|i| *i != 100).collect();
+        order.swap(149, 150);
+        let mut datagrams: Vec<(u32, Vec<u8>)> = order
+            .iter()
+            .map(|i| {
+                let packet = rtp(*i as u16, i * 48, 0x1234, &payload(*i));
+                (i * 1_000, ipv4_udp(SOURCE, DESTINATION, &packet))
+            })
+            .collect();
+        // a stream no descriptor describes, and something else entirely
+        let other = SocketAddrV4::new(Ipv4Addr::new(239, 1, 1, 2), 5004);
+        for i in 0..60u32 {
+            let packet = rtp(i as u16, i * 48, 0x5678, &[0; 288]);
+            datagrams.push((i * 1_000, ipv4_udp(SOURCE, other, &packet)));
+        }
+        datagrams.push((0, ipv4_udp(SOURCE, DESTINATION, b"no rtp")));
+
+        let config = ExtractConfig {
+            directory: directory.clone(),
+            descriptors: vec![descriptor()],
+        };
+        let capture = PcapReader::new(Cursor::new(pcap(&datagrams))).unwrap();
+        let reports = extract_from(capture, &config).unwrap();
+        assert_eq!(reports.len(), 2);
+
+        let report = &reports[0];
+        assert!(!report.guessed);
+        assert_eq!(report.error, None);
+        assert_eq!(report.ssrc, 0x1234);
+        assert_eq!(report.stats.packets_lost, 1);
+        assert_eq!(report.stats.out_of_order, 1);
+        assert_eq!(report.concealed_frames, 48);
+        assert_eq!(report.late_packets, 0);
+        assert_eq!(report.duration, Duration::from_millis(200));
+        assert_eq!(report.levels.len(), 1);
+
+        let file = fs::read(report.file.as_ref().unwrap()).unwrap();
+        let data = &file[44..];
+        assert_eq!(data.len(), 200 * 48 * 2);
+        for i in [0, 99, 149, 150, 151, 199] {
+            let expected: Vec<u8> = payload(i)
+                .chunks(2)
+                .flat_map(|sample| [sample[1], sample[0]])
+                .collect();
+            let start = i as usize * 96;
+            assert_eq!(&data[start..start + 96], &expected[..], "packet {i}");
+        }
+        assert!(data[100 * 96..101 * 96].iter().all(|b| *b == 0));
+
+        let report = &reports[1];
+        assert!(report.guessed);
+        assert_eq!(report.destination, other);
+        let guessed = report.descriptor.as_ref().unwrap();
+        assert_eq!(guessed.bit_depth, BitDepth::L24);
+        assert_eq!(guessed.channels, 2);
+        assert_eq!(report.duration, Duration::from_millis(60));
+        assert_eq!(report.levels[0].peak_db, -120.0);
+
+        fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn extracts_full_scale_and_floating_point_samples() {
+        let directory = temp_dir("extract-formats");
+
+        let l24: Vec<u8> = [0x7f_ffff, -0x80_0000, 5_000_001, -5_000_001, -1, 1]
+            .iter()
+            .flat_map(|sample: &i32| sample.to_be_bytes()[1..].to_vec())
+            .collect();
+        let float: Vec<u8> = [1.0f32, -1.0, 0.123_456_79, -0.5]
+            .iter()
+            .flat_map(|sample| sample.to_be_bytes())
+            .collect();
+        let float_destination = SocketAddrV4::new(*DESTINATION.ip(), 5006);
+        let datagrams = vec![
+            (0, ipv4_udp(SOURCE, DESTINATION, &rtp(1, 0, 1, &l24))),
+            (
+                0,
+                ipv4_udp(SOURCE, float_destination, &rtp(1, 0, 2, &float)),
+            ),
+        ];
+
+        let l24_descriptor = SessionDescriptor {
+            bit_depth: BitDepth::L24,
+            channels: 2,
+            ..descriptor()
+        };
+        let float_descriptor = SessionDescriptor {
+            multicast_port: float_destination.port(),
+            bit_depth: BitDepth::FloatingPoint,
+            channels: 2,
+            ..descriptor()
+        };
+        let config = ExtractConfig {
+            directory: directory.clone(),
+            descriptors: vec![l24_descriptor, float_descriptor],
+        };
+        let capture = PcapReader::new(Cursor::new(pcap(&datagrams))).unwrap();
+        let reports = extract_from(capture, &config).unwrap();
+        assert_eq!(reports.len(), 2);
+
+        for (report, payload, bytes_per_sample) in
+            [(&reports[0], &l24, 3), (&reports[1], &float, 4)]
+        {
+            assert_eq!(report.error, None);
+            let file = fs::read(report.file.as_ref().unwrap()).unwrap();
+            let expected: Vec<u8> = payload
+                .chunks(bytes_per_sample)
+                .flat_map(|sample| sample.iter().rev().copied())
+                .collect();
+            assert_eq!(&file[44..], &expected[..]);
+        }
+        assert!(reports[0].levels[0].peak_db > -0.001);
+        assert!(reports[1].levels[1].peak_db > -0.001);
+
+        fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn trims_overlapping_payloads() {
+        let directory = temp_dir("extract-overlap");
+
+        // the samples of each packet count up from its timestamp
+        let packets = [(0, 48), (24, 48), (72, 48), (100, 10), (120, 48)];
+        let datagrams: Vec<(u32, Vec<u8>)> = packets
+            .iter()
+            .enumerate()
+            .map(|(i, (timestamp, frames))| {
+                let payload: Vec<u8> = (*timestamp..timestamp + frames)
+                    .flat_map(i16::to_be_bytes)
+                    .collect();
+                let packet = rtp(i as u16, *timestamp as u32, 0x1234, &payload);
+                (i as u32 * 1_000, ipv4_udp(SOURCE, DESTINATION, &packet))
+            })
+            .collect();
+
+        let config = ExtractConfig {
+            directory: directory.clone(),
+            descriptors: vec![descriptor()],
+        };
+        let capture = PcapReader::new(Cursor::new(pcap(&datagrams))).unwrap();
+        let reports = extract_from(capture, &config).unwrap();
+        let report = &reports[0];
+        assert_eq!(report.error, None);
+        assert_eq!(report.late_packets, 2);
+        assert_eq!(report.concealed_frames, 0);
+
+        let file = fs::read(report.file.as_ref().unwrap()).unwrap();
+        let expected: Vec<u8> = (0..168i16).flat_map(i16::to_le_bytes).collect();
+        assert_eq!(&file[44..], &expected[..]);
+
+        fs::remove_dir_all(&directory).ok();
+    }
+}
@@ -0,0 +1,559 @@
+use crate::{
+    error::{SdpPlayerError, SdpPlayerResult},
+    recorder::{create_timestamped_file, is_valid_prefix, UtcTime},
+    rtp::ExtendedTimestamps,
+    wav::{WavEncoding, WavFormat, WavWriter},
+    SessionDescriptor,
+};
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, BufWriter},
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// Prefix of incident files if none is configured.
+pub const DEFAULT_INCIDENT_PREFIX: &str = "incident";
+/// Samples at or above this magnitude are at full scale.
+const CLIP_LEVEL: f32 = 0.999;
+/// Consecutive full scale samples of a channel that count as clipping.
+const CLIP_RUN: u32 = 3;
+/// Longest capture, however often it is extended by further events.
+const MAX_CAPTURE_DURATION: Duration = Duration::from_secs(60);
+/// Longest gap in a stream that is filled with silence; a longer one starts the pre-roll over.
+const MAX_CONCEALED_GAP: Duration = Duration::from_secs(1);
+/// Number of incidents kept, older ones are forgotten (but their files are kept).
+const MAX_INCIDENTS: usize = 1000;
+
+/// When to capture an incident and how much audio around it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IncidentConfig {
+    /// directory the captured audio is written to, created if missing
+    pub directory: PathBuf,
+    /// start of the file names, followed by the UTC time of the first captured sample
+    pub prefix: String,
+    /// audio kept from before an event
+    pub pre_roll: Duration,
+    /// audio captured after an event, counted from the packet it was detected in
+    pub post_roll: Duration,
+    /// level in dBFS all channels have to stay below to be silent
+    pub silence_threshold_db: f32,
+    /// how long a stream has to be silent to count as an incident
+    pub silence_duration: Duration,
+}
+
+impl Default for IncidentConfig {
+    fn default() -> Self {
+        IncidentConfig {
+            directory: PathBuf::from("."),
+            prefix: DEFAULT_INCIDENT_PREFIX.to_owned(),
+            pre_roll: Duration::from_secs(5),
+            post_roll: Duration::from_secs(5),
+            silence_threshold_db: -60.0,
+            silence_duration: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+pub enum IncidentKind {
+    /// packets were lost on all legs of the stream
+    PacketLoss,
+    /// all channels stayed below the silence threshold
+    Silence,
+    /// a channel stayed at full scale for several samples
+    Clipping,
+}
+
+/// A captured incident.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Object)]
+pub struct Incident {
+    pub id: u64,
+    /// the event that started the capture, followed by other kinds of events during it
+    pub kinds: Vec<IncidentKind>,
+    /// UTC time the incident was detected, e.g. `2023-11-14T22:13:20Z`
+    pub detected_at: String,
+    /// the captured audio, once it is written
+    pub file: Option<String>,
+    /// seconds of audio captured, once it is written
+    pub duration_seconds: Option<f64>,
+    /// why the captured audio could not be written
+    pub error: Option<String>,
+}
+
+/// Watches the played stream for packet loss, silence and clipping while armed, keeping the
+/// last seconds of received audio, and writes that pre-roll together with the audio following
+/// an event to a WAV file.
+///
+/// Clones refer to the same capture, which keeps its incidents across playbacks.
+#[derive(Debug, Clone, Default)]
+pub struct IncidentCapture {
+    state: Arc<Mutex<CaptureState>>,
+}
+
+#[derive(Debug, Default)]
+struct CaptureState {
+    /// set while armed
+    config: Option<IncidentConfig>,
+    /// the stream currently played
+    stream: Option<SessionDescriptor>,
+    /// watches the current stream while armed
+    detector: Option<Detector>,
+    incidents: VecDeque<Incident>,
+    next_id: u64,
+}
+
+impl IncidentCapture {
+    pub fn new() -> Self {
+        IncidentCapture::default()
+    }
+
+    /// Starts watching for incidents with `config`, replacing the current config if armed
+    /// already. Fails if the prefix isn't a plain file name.
+    pub fn arm(&self, config: IncidentConfig) -> SdpPlayerResult<()> {
+        if !is_valid_prefix(&config.prefix) {
+            return Err(SdpPlayerError::InvalidRecordingPrefix(config.prefix));
+        }
+        fs::create_dir_all(&config.directory)?;
+        log::info!(
+            "Capturing incidents to {}",
+            config.directory.to_string_lossy()
+        );
+
+        let mut state = self.lock();
+        self.flush(&mut state);
+        state.detector = state
+            .stream
+            .as_ref()
+            .map(|stream| Detector::new(stream, &config));
+        state.config = Some(config);
+        Ok(())
+    }
+
+    /// Stops watching for incidents. An incident being captured is written right away.
+    pub fn disarm(&self) {
+        let mut state = self.lock();
+        self.flush(&mut state);
+        state.detector = None;
+        state.config = None;
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.lock().config.is_some()
+    }
+
+    /// The incidents captured so far, oldest first.
+    pub fn incidents(&self) -> Vec<Incident> {
+        self.lock().incidents.iter().cloned().collect()
+    }
+
+    /// Tells the capture which stream the samples it is handed belong to, `None` once playback
+    /// ended.
+    pub(crate) fn set_stream(&self, stream: Option<SessionDescriptor>) {
+        let mut state = self.lock();
+        self.flush(&mut state);
+        state.detector = match (&state.config, &stream) {
+            (Some(config), Some(stream)) => Some(Detector::new(stream, config)),
+            _ => None,
+        };
+        state.stream = stream;
+    }
+
+    /// Watches the samples of a received packet, `lost` being the number of packets found
+    /// missing right before it.
+    pub(crate) fn process(&self, timestamp: u32, samples: &[f32], lost: u64) {
+        let mut state = self.lock();
+        let state = &mut *state;
+        let Some(detector) = &mut state.detector else {
+            return;
+        };
+        let Some(kinds) = detector.process(timestamp, samples, lost) else {
+            return;
+        };
+
+        if !kinds.is_empty() {
+            match &mut detector.capture {
+                Some(capture) => {
+                    capture.extend(&detector.format, &detector.config);
+                    if let Some(incident) = state.incidents.iter_mut().find(|i| i.id == capture.id)
+                    {
+                        for kind in kinds {
+                            if !incident.kinds.contains(&kind) {
+                                incident.kinds.push(kind);
+                            }
+                        }
+                    }
+                }
+                None => {
+                    let id = state.next_id;
+                    state.next_id += 1;
+                    log::warn!("Incident {id}: {kinds:?}, capturing audio");
+                    detector.start_capture(id);
+                    state.incidents.push_back(Incident {
+                        id,
+                        kinds,
+                        detected_at: UtcTime::from(SystemTime::now()).rfc3339(),
+                        file: None,
+                        duration_seconds: None,
+                        error: None,
+                    });
+                    if state.incidents.len() > MAX_INCIDENTS {
+                        state.incidents.pop_front();
+                    }
+                }
+            }
+        }
+        detector.append(samples);
+
+        if detector
+            .capture
+            .as_ref()
+            .map(|capture| capture.remaining_frames == 0)
+            .unwrap_or(false)
+        {
+            self.flush(state);
+        }
+    }
+
+    /// Writes the incident being captured, if any, on a thread of its own.
+    fn flush(&self, state: &mut CaptureState) {
+        let Some(detector) = &mut state.detector else {
+            return;
+        };
+        let Some(capture) = detector.capture.take() else {
+            return;
+        };
+        let format = detector.format;
+        let config = detector.config.clone();
+        let shared = self.state.clone();
+        thread::spawn(move || {
+            let id = capture.id;
+            let result = capture.write(&config, format);
+            let mut state = shared
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let Some(incident) = state.incidents.iter_mut().find(|i| i.id == id) else {
+                return;
+            };
+            match result {
+                Ok((path, duration)) => {
+                    log::info!("Incident {id} captured to {}", path.to_string_lossy());
+                    incident.file = Some(path.to_string_lossy().into_owned());
+                    incident.duration_seconds = Some(duration.as_secs_f64());
+                }
+                Err(e) => {
+                    log::error!("Could not write incident {id}: {e}");
+                    incident.error = Some(e.to_string());
+                }
+            }
+        });
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CaptureState> {
+        // a panic while holding the lock can't leave the state inconsistent
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Watches one stream for events and keeps its pre-roll.
+#[derive(Debug)]
+struct Detector {
+    config: IncidentConfig,
+    format: WavFormat,
+    timestamps: ExtendedTimestamps,
+    /// extended RTP timestamp of the next frame expected
+    next: Option<u64>,
+    /// the most recent frames, interleaved
+    pre_roll: VecDeque<f32>,
+    silence_level: f32,
+    silent_frames: u64,
+    silence_reported: bool,
+    /// full scale samples in a row, per channel
+    clip_runs: Vec<u32>,
+    capture: Option<Capture>,
+}
+
+#[derive(Debug)]
+struct Capture {
+    id: u64,
+    /// time of the first captured frame
+    start: SystemTime,
+    samples: Vec<f32>,
+    remaining_frames: u64,
+}
+
+impl Detector {
+    fn new(stream: &SessionDescriptor, config: &IncidentConfig) -> Self {
+        let channels = stream.channels.max(1);
+        Detector {
+            config: config.clone(),
+            format: WavFormat {
+                channels,
+                sample_rate: stream.sample_rate,
+                encoding: WavEncoding::Float32,
+            },
+            timestamps: ExtendedTimestamps::default(),
+            next: None,
+            pre_roll: VecDeque::new(),
+            silence_level: 10f32.powf(config.silence_threshold_db / 20.0),
+            silent_frames: 0,
+            silence_reported: false,
+            clip_runs: vec![0; channels as usize],
+            capture: None,
+        }
+    }
+
+    fn frames(&self, duration: Duration) -> u64 {
+        (duration.as_secs_f64() * self.format.sample_rate as f64) as u64
+    }
+
+    /// Places the packet in the stream, filling a gap before it with silence, and returns the
+    /// kinds of events it shows. Returns `None` for packets too late to be captured.
+    fn process(&mut self, timestamp: u32, samples: &[f32], lost: u64) -> Option<Vec<IncidentKind>> {
+        let channels = self.format.channels as usize;
+        let frames = (samples.len() / channels) as u64;
+        if frames == 0 {
+            return None;
+        }
+
+        let timestamp = self.timestamps.extend(timestamp);
+        if let Some(next) = self.next {
+            if timestamp < next {
+                return None;
+            }
+            let gap = timestamp - next;
+            if gap > self.frames(MAX_CONCEALED_GAP) {
+                self.pre_roll.clear();
+            } else if gap > 0 {
+                self.append(&vec![0.0; gap as usize * channels]);
+            }
+        }
+        self.next = Some(timestamp + frames);
+
+        let mut kinds = Vec::new();
+        if lost > 0 {
+            kinds.push(IncidentKind::PacketLoss);
+        }
+
+        let peak = samples.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+        if peak < self.silence_level {
+            self.silent_frames += frames;
+            if !self.silence_reported
+                && self.silent_frames >= self.frames(self.config.silence_duration)
+            {
+                self.silence_reported = true;
+                kinds.push(IncidentKind::Silence);
+            }
+        } else {
+            self.silent_frames = 0;
+            self.silence_reported = false;
+        }
+
+        let mut clipped = false;
+        for frame in samples.chunks_exact(channels) {
+            for (sample, run) in frame.iter().zip(&mut self.clip_runs) {
+                if sample.abs() >= CLIP_LEVEL {
+                    *run += 1;
+                    clipped |= *run == CLIP_RUN;
+                } else {
+                    *run = 0;
+                }
+            }
+        }
+        if clipped {
+            kinds.push(IncidentKind::Clipping);
+        }
+
+        Some(kinds)
+    }
+
+    /// Adds frames to the capture, or to the pre-roll if not capturing.
+    fn append(&mut self, samples: &[f32]) {
+        let frames = (samples.len() / self.format.channels as usize) as u64;
+        match &mut self.capture {
+            Some(capture) => {
+                capture.samples.extend_from_slice(samples);
+                capture.remaining_frames = capture.remaining_frames.saturating_sub(frames);
+            }
+            None => {
+                self.pre_roll.extend(samples);
+                let max_len =
+                    self.frames(self.config.pre_roll) as usize * self.format.channels as usize;
+                if self.pre_roll.len() > max_len {
+                    self.pre_roll.drain(..self.pre_roll.len() - max_len);
+                }
+            }
+        }
+    }
+
+    fn start_capture(&mut self, id: u64) {
+        let pre_roll_frames = self.pre_roll.len() / self.format.channels as usize;
+        let pre_roll =
+            Duration::from_secs_f64(pre_roll_frames as f64 / self.format.sample_rate as f64);
+        self.capture = Some(Capture {
+            id,
+            start: SystemTime::now() - pre_roll,
+            samples: self.pre_roll.drain(..).collect(),
+            remaining_frames: self.frames(self.config.post_roll),
+        });
+    }
+}
+
+impl Capture {
+    /// Captures another post-roll from now on, as long as the capture stays within its maximum
+    /// duration.
+    fn extend(&mut self, format: &WavFormat, config: &IncidentConfig) {
+        let rate = format.sample_rate as f64;
+        let captured = (self.samples.len() / format.channels as usize) as u64;
+        let max_remaining =
+            ((MAX_CAPTURE_DURATION.as_secs_f64() * rate) as u64).saturating_sub(captured);
+        let post_roll = (config.post_roll.as_secs_f64() * rate) as u64;
+        self.remaining_frames = self.remaining_frames.max(post_roll).min(max_remaining);
+    }
+
+    /// Writes the captured audio and returns where to and how much.
+    fn write(&self, config: &IncidentConfig, format: WavFormat) -> io::Result<(PathBuf, Duration)> {
+        let (file, path) = create_timestamped_file(&config.directory, &config.prefix, self.start)?;
+        let mut writer = WavWriter::new(BufWriter::new(file), format)?;
+        writer.write_samples(&self.samples)?;
+        writer.finish()?;
+        let frames = self.samples.len() / format.channels as usize;
+        Ok((
+            path,
+            Duration::from_secs_f64(frames as f64 / format.sample_rate as f64),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{descriptor, temp_dir};
+    use std::time::Instant;
+
+    fn capture(name: &str) -> (IncidentCapture, PathBuf) {
+        let directory = temp_dir(name);
+        let capture = IncidentCapture::new();
+        capture.set_stream(Some(SessionDescriptor {
+            channels: 1,
+            sample_rate: 1_000,
+            packet_time: 10.0,
+            ..descriptor()
+        }));
+        capture
+            .arm(IncidentConfig {
+                directory: directory.clone(),
+                prefix: name.to_owned(),
+                pre_roll: Duration::from_millis(50),
+                post_roll: Duration::from_millis(30),
+                silence_threshold_db: -60.0,
+                silence_duration: Duration::from_millis(100),
+            })
+            .unwrap();
+        (capture, directory)
+    }
+
+    fn written(capture: &IncidentCapture) -> Vec<Incident> {
+        let start = Instant::now();
+        loop {
+            let incidents = capture.incidents();
+            if incidents
+                .iter()
+                .all(|i| i.file.is_some() || i.error.is_some())
+                || start.elapsed() > Duration::from_secs(5)
+            {
+                return incidents;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn captures_pre_and_post_roll_around_packet_loss() {
+        let (capture, directory) = capture("loss");
+        let mut timestamp = 0;
+        for _ in 0..10 {
+            capture.process(timestamp, &[0.5; 10], 0);
+            timestamp += 10;
+        }
+        // one packet is lost
+        timestamp += 10;
+        capture.process(timestamp, &[0.25; 10], 1);
+        assert_eq!(capture.incidents()[0].kinds, vec![IncidentKind::PacketLoss]);
+        assert_eq!(capture.incidents()[0].file, None);
+        for _ in 0..3 {
+            timestamp += 10;
+            capture.process(timestamp, &[0.5; 10], 0);
+        }
+
+        let incidents = written(&capture);
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(incidents[0].duration_seconds, Some(0.08));
+        let bytes = fs::read(incidents[0].file.as_ref().unwrap()).unwrap();
+        let data: Vec<f32> = bytes[44..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        // 40 frames before the loss, 10 concealed and 30 after it
+        assert_eq!(data.len(), 80);
+        assert!(data[..40].iter().all(|&s| s == 0.5));
+        assert!(data[40..50].iter().all(|&s| s == 0.0));
+        assert!(data[50..60].iter().all(|&s| s == 0.25));
+        assert!(data[60..].iter().all(|&s| s == 0.5));
+
+        fs::remove_dir_all(directory).ok();
+    }
+
+    #[test]
+    fn detects_silence_once_and_merges_events_into_one_capture() {
+        let (capture, directory) = capture("silence");
+        let mut timestamp = 0;
+        for _ in 0..11 {
+            capture.process(timestamp, &[0.0; 10], 0);
+            timestamp += 10;
+        }
+        let mut clipping = [0.0; 10];
+        clipping[2..6].fill(1.0);
+        capture.process(timestamp, &clipping, 0);
+        for _ in 0..20 {
+            timestamp += 10;
+            capture.process(timestamp, &[0.0; 10], 0);
+        }
+
+        let incidents = written(&capture);
+        assert_eq!(incidents.len(), 2);
+        assert_eq!(
+            incidents[0].kinds,
+            vec![IncidentKind::Silence, IncidentKind::Clipping]
+        );
+        // the silence after the clipping is reported again once it lasted long enough
+        assert_eq!(incidents[1].kinds, vec![IncidentKind::Silence]);
+        assert!(incidents.iter().all(|i| i.file.is_some()));
+
+        fs::remove_dir_all(directory).ok();
+    }
+
+    #[test]
+    fn writes_pending_capture_when_stream_ends() {
+        let (capture, directory) = capture("ended");
+        capture.process(0, &[1.0; 10], 0);
+        capture.set_stream(None);
+        capture.process(10, &[0.5; 10], 0);
+
+        let incidents = written(&capture);
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(incidents[0].kinds, vec![IncidentKind::Clipping]);
+        assert_eq!(incidents[0].duration_seconds, Some(0.01));
+
+        capture.disarm();
+        assert!(!capture.is_armed());
+        fs::remove_dir_all(directory).ok();
+    }
+}
@@ -0,0 +1,360 @@
+use crate::rtp::ExtendedTimestamps;
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// How far the buffer may grow beyond its target latency before it skips ahead, as a multiple
+/// of the target latency.
+const MAX_LATENCY_FACTOR: u64 = 3;
+
+/// Time constant in seconds of the moving average over the buffer fill level.
+const DRIFT_AVERAGING_TIME: f64 = 2.0;
+/// Proportional gain of the drift controller, in correction per second of fill level error.
+const DRIFT_PROPORTIONAL_GAIN: f64 = 0.1;
+/// Integral gain of the drift controller, in correction per second of fill level error and
+/// second of runtime.
+const DRIFT_INTEGRAL_GAIN: f64 = 0.003;
+/// Largest clock deviation the drift controller compensates for.
+const MAX_DRIFT_CORRECTION: f64 = 0.001;
+
+/// Counters describing the health of a [`JitterBuffer`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Object)]
+pub struct JitterBufferStats {
+    /// frames that had to be filled with silence because no packet covered them
+    pub concealed_frames: u64,
+    /// packets that arrived after their playout time had passed
+    pub late_packets: u64,
+    /// packets that were received more than once
+    pub duplicate_packets: u64,
+    /// number of times the buffer ran empty and had to be filled up again
+    pub underruns: u64,
+    /// number of times the buffer grew too large and skipped ahead to its target latency
+    pub overruns: u64,
+}
+
+/// Estimates the drift between the sender's media clock and the clock pulling frames out of a
+/// [`JitterBuffer`] from the buffer's fill level.
+///
+/// If the output device runs slower than the sender, the buffer slowly fills up, if it runs
+/// faster, it drains. A PI controller turns the deviation of the averaged fill level from the
+/// target latency into a correction factor for the rate at which frames are consumed, which
+/// keeps the latency constant.
+#[derive(Debug, Clone, Default)]
+pub struct DriftEstimator {
+    average_fill: Option<f64>,
+    integral: f64,
+    correction: f64,
+}
+
+impl DriftEstimator {
+    /// Updates the estimate with the current fill level after `elapsed` seconds, all levels
+    /// given in seconds.
+    pub fn update(&mut self, fill: f64, target: f64, elapsed: f64) {
+        let average_fill = match self.average_fill {
+            Some(average) => average + (fill - average) * (elapsed / DRIFT_AVERAGING_TIME).min(1.0),
+            None => fill,
+        };
+        self.average_fill = Some(average_fill);
+
+        let error = average_fill - target;
+        self.integral = (self.integral + DRIFT_INTEGRAL_GAIN * error * elapsed)
+            .clamp(-MAX_DRIFT_CORRECTION, MAX_DRIFT_CORRECTION);
+        self.correction = (DRIFT_PROPORTIONAL_GAIN * error + self.integral)
+            .clamp(-MAX_DRIFT_CORRECTION, MAX_DRIFT_CORRECTION);
+    }
+
+    /// Restarts averaging, e.g. after the buffer was refilled, but keeps the learned drift.
+    pub fn reset(&mut self) {
+        self.average_fill = None;
+        self.correction = self.integral;
+    }
+
+    /// Factor by which frames should be consumed faster (> 1.0) or slower (< 1.0) than
+    /// nominal to keep the fill level at its target.
+    pub fn correction(&self) -> f64 {
+        1.0 + self.correction
+    }
+
+    /// The estimated clock drift in parts per million, positive if the sender is faster.
+    pub fn ppm(&self) -> f64 {
+        self.integral * 1_000_000.0
+    }
+}
+
+/// Reorders received RTP audio packets and plays them out at a fixed latency.
+///
+/// Payloads are placed on a timeline by their RTP timestamp, so reordered packets end up at
+/// their correct position and missing ones are concealed with silence. Playout starts once
+/// `target_latency` frames are buffered.
+#[derive(Debug)]
+pub struct JitterBuffer {
+    channels: usize,
+    sample_rate: u32,
+    target_latency: u64,
+    /// decoded interleaved samples by extended RTP timestamp of their first frame
+    packets: BTreeMap<u64, Vec<f32>>,
+    /// extended RTP timestamp of the next frame to be played, `None` while prefilling
+    playout: Option<u64>,
+    timestamps: ExtendedTimestamps,
+    drift: DriftEstimator,
+    stats: JitterBufferStats,
+}
+
+impl JitterBuffer {
+    pub fn new(channels: u16, sample_rate: u32, target_latency_ms: f32) -> Self {
+        let target_latency = (sample_rate as f64 * target_latency_ms as f64 / 1_000.0) as u64;
+        JitterBuffer {
+            channels: channels.max(1) as usize,
+            sample_rate: sample_rate.max(1),
+            target_latency: target_latency.max(1),
+            packets: BTreeMap::new(),
+            playout: None,
+            timestamps: ExtendedTimestamps::default(),
+            drift: DriftEstimator::default(),
+            stats: JitterBufferStats::default(),
+        }
+    }
+
+    /// Adds the decoded, interleaved samples of the packet with the given RTP timestamp.
+    pub fn push(&mut self, timestamp: u32, samples: Vec<f32>) {
+        let frames = (samples.len() / self.channels) as u64;
+        if frames == 0 {
+            return;
+        }
+
+        let timestamp = self.timestamps.extend(timestamp);
+
+        if let Some(playout) = self.playout {
+            if timestamp + frames <= playout {
+                self.stats.late_packets += 1;
+                return;
+            }
+        }
+
+        if self.packets.contains_key(&timestamp) {
+            self.stats.duplicate_packets += 1;
+            return;
+        }
+
+        self.packets.insert(timestamp, samples);
+
+        if let Some(playout) = self.playout {
+            let end = self.buffered_end().unwrap_or(playout);
+            if end.saturating_sub(playout) > self.target_latency * MAX_LATENCY_FACTOR {
+                log::warn!("Jitter buffer overrun, skipping ahead to target latency");
+                self.stats.overruns += 1;
+                self.skip_to(end - self.target_latency);
+                self.drift.reset();
+            }
+        }
+    }
+
+    /// Fills `out` with interleaved samples, concealing missing packets with silence. While the
+    /// buffer is prefilling, `out` is filled with silence entirely.
+    pub fn pull(&mut self, out: &mut [f32]) {
+        let channels = self.channels;
+        let mut written = 0;
+
+        while written < out.len() {
+            let Some(playout) = self.playout.or_else(|| self.start_playout()) else {
+                out[written..].fill(0.0);
+                return;
+            };
+
+            let remaining_frames = ((out.len() - written) / channels) as u64;
+            if remaining_frames == 0 {
+                out[written..].fill(0.0);
+                return;
+            }
+
+            let current = self
+                .packets
+                .range(..=playout)
+                .next_back()
+                .map(|(timestamp, samples)| (*timestamp, samples));
+
+            let frames = match current {
+                Some((timestamp, samples))
+                    if playout < timestamp + (samples.len() / channels) as u64 =>
+                {
+                    let offset = (playout - timestamp) as usize;
+                    let available = (samples.len() / channels - offset) as u64;
+                    let frames = available.min(remaining_frames);
+                    let from = offset * channels;
+                    let to = from + frames as usize * channels;
+                    out[written..written + (to - from)].copy_from_slice(&samples[from..to]);
+                    frames
+                }
+                _ => {
+                    let Some(next) = self.packets.range(playout..).next().map(|(t, _)| *t) else {
+                        log::debug!("Jitter buffer underrun, refilling");
+                        self.stats.underruns += 1;
+                        self.playout = None;
+                        self.drift.reset();
+                        continue;
+                    };
+                    let frames = (next - playout).min(remaining_frames);
+                    out[written..written + frames as usize * channels].fill(0.0);
+                    self.stats.concealed_frames += frames;
+                    frames
+                }
+            };
+
+            written += frames as usize * channels;
+            self.skip_to(playout + frames);
+        }
+
+        if self.playout.is_some() {
+            let sample_rate = self.sample_rate as f64;
+            self.drift.update(
+                self.fill() as f64 / sample_rate,
+                self.target_latency as f64 / sample_rate,
+                (out.len() / channels) as f64 / sample_rate,
+            );
+        }
+    }
+
+    /// Number of frames buffered ahead of the current playout position.
+    pub fn fill(&self) -> u64 {
+        match (self.playout, self.buffered_end()) {
+            (Some(playout), Some(end)) => end.saturating_sub(playout),
+            (None, Some(end)) => end - self.buffered_start().unwrap_or(end),
+            _ => 0,
+        }
+    }
+
+    pub fn target_latency(&self) -> u64 {
+        self.target_latency
+    }
+
+    pub fn stats(&self) -> JitterBufferStats {
+        self.stats
+    }
+
+    /// The clock drift estimate derived from the fill level of this buffer.
+    pub fn drift(&self) -> &DriftEstimator {
+        &self.drift
+    }
+
+    fn start_playout(&mut self) -> Option<u64> {
+        if self.fill() >= self.target_latency {
+            self.playout = self.buffered_start();
+        }
+        self.playout
+    }
+
+    fn skip_to(&mut self, playout: u64) {
+        self.playout = Some(playout);
+        let channels = self.channels as u64;
+        self.packets
+            .retain(|timestamp, samples| timestamp + samples.len() as u64 / channels > playout);
+    }
+
+    fn buffered_start(&self) -> Option<u64> {
+        self.packets.keys().next().copied()
+    }
+
+    fn buffered_end(&self) -> Option<u64> {
+        self.packets
+            .iter()
+            .next_back()
+            .map(|(timestamp, samples)| timestamp + (samples.len() / self.channels) as u64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn packet(value: f32) -> Vec<f32> {
+        vec![value; 4]
+    }
+
+    fn pull(buffer: &mut JitterBuffer, frames: usize) -> Vec<f32> {
+        let mut out = vec![-1.0; frames];
+        buffer.pull(&mut out);
+        out
+    }
+
+    #[test]
+    fn prefills_to_target_latency() {
+        // 1 channel at 1 kHz, 8 ms -> 8 frames
+        let mut buffer = JitterBuffer::new(1, 1000, 8.0);
+        buffer.push(0, packet(1.0));
+        assert_eq!(pull(&mut buffer, 4), vec![0.0; 4]);
+        buffer.push(4, packet(2.0));
+        assert_eq!(
+            pull(&mut buffer, 8),
+            vec![1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0]
+        );
+    }
+
+    #[test]
+    fn reorders_packets() {
+        let mut buffer = JitterBuffer::new(1, 1000, 8.0);
+        buffer.push(4, packet(2.0));
+        buffer.push(0, packet(1.0));
+        assert_eq!(
+            pull(&mut buffer, 8),
+            vec![1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0]
+        );
+    }
+
+    #[test]
+    fn conceals_missing_packets() {
+        let mut buffer = JitterBuffer::new(1, 1000, 12.0);
+        buffer.push(0, packet(1.0));
+        buffer.push(8, packet(3.0));
+        assert_eq!(
+            pull(&mut buffer, 12),
+            vec![1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 3.0, 3.0, 3.0, 3.0]
+        );
+        assert_eq!(buffer.stats().concealed_frames, 4);
+    }
+
+    #[test]
+    fn drops_late_and_duplicate_packets() {
+        let mut buffer = JitterBuffer::new(1, 1000, 8.0);
+        buffer.push(0, packet(1.0));
+        buffer.push(0, packet(1.0));
+        buffer.push(4, packet(2.0));
+        pull(&mut buffer, 8);
+        buffer.push(0, packet(1.0));
+        let stats = buffer.stats();
+        assert_eq!(stats.duplicate_packets, 1);
+        assert_eq!(stats.late_packets, 1);
+    }
+
+    #[test]
+    fn handles_timestamp_wraparound() {
+        let mut buffer = JitterBuffer::new(1, 1000, 8.0);
+        buffer.push(u32::MAX - 3, packet(1.0));
+        buffer.push(0, packet(2.0));
+        assert_eq!(
+            pull(&mut buffer, 8),
+            vec![1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0]
+        );
+    }
+
+    #[test]
+    fn interleaves_channels() {
+        let mut buffer = JitterBuffer::new(2, 1000, 2.0);
+        buffer.push(0, vec![0.1, 0.2, 0.3, 0.4]);
+        assert_eq!(pull(&mut buffer, 4), vec![0.1, 0.2, 0.3, 0.4]);
+    }
+
+    #[test]
+    fn drift_estimator_follows_fill_level() {
+        let mut drift = DriftEstimator::default();
+        // buffer 1 ms above its target for a minute, updated every 10 ms
+        for _ in 0..6000 {
+            drift.update(0.021, 0.020, 0.01);
+        }
+        assert!(drift.correction() > 1.0);
+        assert!(drift.ppm() > 0.0);
+        for _ in 0..6000 {
+            drift.update(0.019, 0.020, 0.01);
+        }
+        assert!(drift.correction() < 1.0);
+    }
+}
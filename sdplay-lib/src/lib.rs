@@ -1,10 +1,30 @@
 pub mod audio;
+pub mod device;
 pub mod error;
+pub mod extract;
+pub mod incident;
+pub mod jitter;
+pub mod loudness;
+pub mod meter;
+pub mod monitor;
+pub mod pcap;
+pub mod player;
+pub mod recorder;
+pub mod resample;
+pub mod routing;
+pub mod rtcp;
+pub mod rtp;
 pub mod sdp;
+pub mod sink;
 pub mod stream;
+#[cfg(test)]
+mod testing;
+pub mod volume;
+pub mod wav;
 
 use error::SdpPlayerError;
 use poem_openapi::{Enum, Object};
+use routing::{ChannelRouting, Route};
 use serde::{Deserialize, Serialize};
 use std::{fmt, net::Ipv4Addr, str::FromStr};
 
@@ -16,6 +36,31 @@ pub struct SessionDescriptor {
     pub channels: u16,
     pub sample_rate: u32,
     pub packet_time: f32,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub mid: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    #[oai(default)]
+    pub source_filters: Vec<SourceFilter>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub channel_layout: Option<ChannelLayout>,
+    /// port to exchange RTCP reports on, as signalled by `a=rtcp`; no RTCP is sent without it
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub rtcp_port: Option<u16>,
+    /// address RTCP reports are sent to, if other than `multicast_address`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub rtcp_address: Option<Ipv4Addr>,
+    /// RTP payload types of the stream as declared by the `m=` line; packets of other payload
+    /// types are dropped. Packets of any payload type are played if there are none.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    #[oai(default)]
+    pub payload_types: Vec<u8>,
+    /// SSRC of the sender to play, as declared by `a=ssrc`; without it, the first sender heard
+    /// is played until it falls silent
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub ssrc: Option<u32>,
+    /// the SDP's session name (`s=`)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub session_name: Option<String>,
 }
 
 impl SessionDescriptor {
@@ -29,6 +74,170 @@ impl SessionDescriptor {
     }
 }
 
+/// An RFC 4570 `a=source-filter` restricting which senders of a multicast group are received.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Object)]
+pub struct SourceFilter {
+    pub mode: FilterMode,
+    /// the multicast group the filter applies to, `None` for all groups
+    pub destination_address: Option<Ipv4Addr>,
+    pub source_addresses: Vec<Ipv4Addr>,
+}
+
+impl SourceFilter {
+    pub fn applies_to(&self, multicast_address: Ipv4Addr) -> bool {
+        self.destination_address
+            .map(|destination| destination == multicast_address)
+            .unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Enum)]
+pub enum FilterMode {
+    Include,
+    Exclude,
+}
+
+/// The SMPTE ST 2110-30 channel order of a stream, as signalled by
+/// `a=fmtp:<pt> channel-order=SMPTE2110.(...)`, e.g. `SMPTE2110.(ST,51,M)`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Object)]
+pub struct ChannelLayout {
+    pub groups: Vec<ChannelGroup>,
+}
+
+impl ChannelLayout {
+    /// The total number of channels of all groups.
+    pub fn channels(&self) -> u16 {
+        self.groups.iter().map(|g| g.channels).sum()
+    }
+
+    /// The stream channels, starting at 1, of the group with the given number, starting at 1.
+    pub fn group_channels(&self, group: usize) -> Option<Vec<u16>> {
+        let index = group.checked_sub(1)?;
+        let first: u16 = self.groups.iter().take(index).map(|g| g.channels).sum();
+        let group = self.groups.get(index)?;
+        Some((first + 1..=first + group.channels).collect())
+    }
+
+    /// A routing that plays only the group with the given number, starting at 1, on
+    /// consecutive device outputs. Mono groups are played on the first two outputs.
+    pub fn group_routing(&self, group: usize) -> Option<ChannelRouting> {
+        let channels = self.group_channels(group)?;
+        if self.groups[group - 1].kind == ChannelGroupKind::Mono {
+            Some(ChannelRouting {
+                routes: (1..=2)
+                    .map(|output| Route {
+                        input: channels[0],
+                        output,
+                        gain_db: 0.0,
+                    })
+                    .collect(),
+            })
+        } else {
+            Some(ChannelRouting::select(&channels))
+        }
+    }
+
+    /// A label for every stream channel, made of the number and symbol of its group and the
+    /// channel's name within the group, e.g. `2 ST R`.
+    pub fn channel_labels(&self) -> Vec<String> {
+        self.groups
+            .iter()
+            .zip(1..)
+            .flat_map(|(group, number)| {
+                group
+                    .channel_names()
+                    .into_iter()
+                    .map(move |name| format!("{number} {} {name}", group.symbol()))
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for ChannelLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbols: Vec<String> = self.groups.iter().map(ChannelGroup::symbol).collect();
+        write!(f, "SMPTE2110.({})", symbols.join(","))
+    }
+}
+
+/// A group of channels within a [`ChannelLayout`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Object)]
+pub struct ChannelGroup {
+    pub kind: ChannelGroupKind,
+    pub channels: u16,
+}
+
+impl ChannelGroup {
+    pub fn new(kind: ChannelGroupKind) -> Self {
+        let channels = match kind {
+            ChannelGroupKind::Mono => 1,
+            ChannelGroupKind::DualMono
+            | ChannelGroupKind::Stereo
+            | ChannelGroupKind::MatrixStereo => 2,
+            ChannelGroupKind::Surround51 => 6,
+            ChannelGroupKind::Surround71 => 8,
+            ChannelGroupKind::Surround222 => 24,
+            ChannelGroupKind::SdiGroup => 4,
+            ChannelGroupKind::Undefined => 1,
+        };
+        ChannelGroup { kind, channels }
+    }
+
+    pub fn undefined(channels: u16) -> Self {
+        ChannelGroup {
+            kind: ChannelGroupKind::Undefined,
+            channels,
+        }
+    }
+
+    /// The ST 2110-30 symbol of this group.
+    pub fn symbol(&self) -> String {
+        match self.kind {
+            ChannelGroupKind::Mono => "M".to_owned(),
+            ChannelGroupKind::DualMono => "DM".to_owned(),
+            ChannelGroupKind::Stereo => "ST".to_owned(),
+            ChannelGroupKind::MatrixStereo => "LtRt".to_owned(),
+            ChannelGroupKind::Surround51 => "51".to_owned(),
+            ChannelGroupKind::Surround71 => "71".to_owned(),
+            ChannelGroupKind::Surround222 => "222".to_owned(),
+            ChannelGroupKind::SdiGroup => "SGRP".to_owned(),
+            ChannelGroupKind::Undefined => format!("U{:02}", self.channels),
+        }
+    }
+
+    /// Names of the channels of this group, in stream order.
+    pub fn channel_names(&self) -> Vec<String> {
+        let names: &[&str] = match self.kind {
+            ChannelGroupKind::Mono => &["M"],
+            ChannelGroupKind::DualMono => &["M1", "M2"],
+            ChannelGroupKind::Stereo => &["L", "R"],
+            ChannelGroupKind::MatrixStereo => &["Lt", "Rt"],
+            ChannelGroupKind::Surround51 => &["L", "R", "C", "LFE", "Ls", "Rs"],
+            ChannelGroupKind::Surround71 => &["L", "R", "C", "LFE", "Lss", "Rss", "Lrs", "Rrs"],
+            _ => &[],
+        };
+        if names.is_empty() {
+            (1..=self.channels).map(|c| c.to_string()).collect()
+        } else {
+            names.iter().map(|n| n.to_string()).collect()
+        }
+    }
+}
+
+/// The kinds of channel groups defined by SMPTE ST 2110-30.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+pub enum ChannelGroupKind {
+    Mono,
+    DualMono,
+    Stereo,
+    MatrixStereo,
+    Surround51,
+    Surround71,
+    Surround222,
+    SdiGroup,
+    Undefined,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Enum)]
 pub enum BitDepth {
     L16,
@@ -59,10 +268,7 @@ impl BitDepth {
     }
 
     pub fn floating_point(&self) -> bool {
-        match self {
-            BitDepth::FloatingPoint => true,
-            _ => false,
-        }
+        matches!(self, BitDepth::FloatingPoint)
     }
 }
 
@@ -71,15 +277,15 @@ impl FromStr for BitDepth {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if s.contains("16") {
-            return Ok(BitDepth::L16);
+            Ok(BitDepth::L16)
         } else if s.contains("24") {
-            return Ok(BitDepth::L24);
+            Ok(BitDepth::L24)
         } else if s.contains("32") {
-            return Ok(BitDepth::L32);
+            Ok(BitDepth::L32)
         } else if s.to_lowercase().contains("float") {
-            return Ok(BitDepth::FloatingPoint);
+            Ok(BitDepth::FloatingPoint)
         } else {
-            return Err(SdpPlayerError::InvalidBitDepth(s.to_owned()));
+            Err(SdpPlayerError::InvalidBitDepth(s.to_owned()))
         }
     }
 }
@@ -0,0 +1,397 @@
+use crate::ChannelLayout;
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, f64::consts::PI};
+
+/// Length of the sub-blocks loudness is measured in, in seconds. Momentary and short-term
+/// loudness are updated once per sub-block.
+const SUB_BLOCK_TIME: f64 = 0.1;
+/// Sub-blocks making up the 400 ms momentary window.
+const MOMENTARY_SUB_BLOCKS: usize = 4;
+/// Sub-blocks making up the 3 s short-term window.
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const INTEGRATED_RELATIVE_GATE_LU: f64 = -10.0;
+const RANGE_RELATIVE_GATE_LU: f64 = -20.0;
+const RANGE_LOW_PERCENTILE: f64 = 0.10;
+const RANGE_HIGH_PERCENTILE: f64 = 0.95;
+
+/// Gated blocks are kept in a histogram from the absolute gate up to this loudness, so
+/// measurements can run indefinitely.
+const HISTOGRAM_MAX_LUFS: f64 = 10.0;
+const HISTOGRAM_STEP_LU: f64 = 0.1;
+
+/// Weight of surround channels relative to front channels.
+const SURROUND_WEIGHT: f64 = 1.41;
+
+/// Loudness readings according to EBU R 128 / ITU-R BS.1770. Values are missing until enough
+/// audio has been measured, or if everything measured was silent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, Object)]
+pub struct Loudness {
+    /// loudness of the last 400 ms in LUFS
+    pub momentary_lufs: Option<f64>,
+    /// loudness of the last 3 s in LUFS
+    pub short_term_lufs: Option<f64>,
+    /// gated loudness since the measurement started or was reset, in LUFS
+    pub integrated_lufs: Option<f64>,
+    /// loudness range (LRA) since the measurement started or was reset, in LU
+    pub loudness_range_lu: Option<f64>,
+}
+
+/// Measures momentary, short-term and integrated loudness and loudness range of interleaved
+/// audio.
+#[derive(Debug)]
+pub struct LoudnessMeter {
+    weights: Vec<f64>,
+    filters: Vec<KWeighting>,
+    sub_block_frames: usize,
+    frames: usize,
+    /// weighted sum of the squared, K-weighted samples of the current sub-block
+    energy: f64,
+    /// mean power of the most recent sub-blocks, newest last
+    sub_blocks: VecDeque<f64>,
+    momentary_blocks: GatedHistogram,
+    short_term_blocks: GatedHistogram,
+}
+
+impl LoudnessMeter {
+    /// Creates a meter weighting every channel by one of `weights`, as returned by
+    /// [`channel_weights`].
+    pub fn new(weights: Vec<f64>, sample_rate: u32) -> Self {
+        LoudnessMeter {
+            filters: weights
+                .iter()
+                .map(|_| KWeighting::new(sample_rate))
+                .collect(),
+            weights,
+            sub_block_frames: ((sample_rate as f64 * SUB_BLOCK_TIME) as usize).max(1),
+            frames: 0,
+            energy: 0.0,
+            sub_blocks: VecDeque::with_capacity(SHORT_TERM_SUB_BLOCKS + 1),
+            momentary_blocks: GatedHistogram::new(),
+            short_term_blocks: GatedHistogram::new(),
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) {
+        let channels = self.weights.len().max(1);
+        for frame in samples.chunks_exact(channels) {
+            for ((s, weight), filter) in frame.iter().zip(&self.weights).zip(&mut self.filters) {
+                let s = filter.process(*s as f64);
+                self.energy += weight * s * s;
+            }
+            self.frames += 1;
+            if self.frames >= self.sub_block_frames {
+                self.end_sub_block();
+            }
+        }
+    }
+
+    /// Starts the integrated loudness and loudness range measurements over.
+    pub fn reset(&mut self) {
+        self.momentary_blocks = GatedHistogram::new();
+        self.short_term_blocks = GatedHistogram::new();
+    }
+
+    pub fn loudness(&self) -> Loudness {
+        Loudness {
+            momentary_lufs: self.window_power(MOMENTARY_SUB_BLOCKS).and_then(lufs),
+            short_term_lufs: self.window_power(SHORT_TERM_SUB_BLOCKS).and_then(lufs),
+            integrated_lufs: self
+                .momentary_blocks
+                .gated_mean(INTEGRATED_RELATIVE_GATE_LU)
+                .and_then(lufs),
+            loudness_range_lu: self.short_term_blocks.range(),
+        }
+    }
+
+    fn end_sub_block(&mut self) {
+        self.sub_blocks.push_back(self.energy / self.frames as f64);
+        if self.sub_blocks.len() > SHORT_TERM_SUB_BLOCKS {
+            self.sub_blocks.pop_front();
+        }
+        self.frames = 0;
+        self.energy = 0.0;
+
+        // blocks overlap by all but one sub-block
+        if let Some(power) = self.window_power(MOMENTARY_SUB_BLOCKS) {
+            self.momentary_blocks.add(power);
+        }
+        if let Some(power) = self.window_power(SHORT_TERM_SUB_BLOCKS) {
+            self.short_term_blocks.add(power);
+        }
+    }
+
+    /// Mean power of the last `sub_blocks` sub-blocks, if that many have been measured.
+    fn window_power(&self, sub_blocks: usize) -> Option<f64> {
+        if self.sub_blocks.len() < sub_blocks {
+            return None;
+        }
+        let sum: f64 = self.sub_blocks.iter().rev().take(sub_blocks).sum();
+        Some(sum / sub_blocks as f64)
+    }
+}
+
+/// Loudness weights of the stream channels: LFE channels don't count, surround channels count
+/// more than front channels and channels of unknown layout count as front channels. If `group`
+/// is set, only the channels of that group (starting at 1) count.
+pub fn channel_weights(
+    layout: Option<&ChannelLayout>,
+    channels: u16,
+    group: Option<usize>,
+) -> Vec<f64> {
+    let mut weights = vec![if group.is_some() { 0.0 } else { 1.0 }; channels as usize];
+    let Some(layout) = layout else {
+        return weights;
+    };
+
+    let mut channel = 0;
+    for (number, layout_group) in (1..).zip(&layout.groups) {
+        for name in layout_group.channel_names() {
+            if let Some(weight) = weights.get_mut(channel) {
+                *weight = match name.as_str() {
+                    _ if group.is_some_and(|g| g != number) => 0.0,
+                    "LFE" => 0.0,
+                    "Ls" | "Rs" | "Lss" | "Rss" | "Lrs" | "Rrs" => SURROUND_WEIGHT,
+                    _ => 1.0,
+                };
+            }
+            channel += 1;
+        }
+    }
+    weights
+}
+
+fn lufs(power: f64) -> Option<f64> {
+    (power > 0.0).then(|| -0.691 + 10.0 * power.log10())
+}
+
+/// Block powers above the absolute gate, binned by loudness.
+#[derive(Debug)]
+struct GatedHistogram {
+    counts: Vec<u64>,
+    /// sum of the powers of the blocks in each bin
+    powers: Vec<f64>,
+}
+
+impl GatedHistogram {
+    fn new() -> Self {
+        let bins = ((HISTOGRAM_MAX_LUFS - ABSOLUTE_GATE_LUFS) / HISTOGRAM_STEP_LU).round() as usize;
+        GatedHistogram {
+            counts: vec![0; bins],
+            powers: vec![0.0; bins],
+        }
+    }
+
+    fn add(&mut self, block_power: f64) {
+        let Some(loudness) = lufs(block_power) else {
+            return;
+        };
+        if loudness < ABSOLUTE_GATE_LUFS {
+            return;
+        }
+        let bin = ((loudness - ABSOLUTE_GATE_LUFS) / HISTOGRAM_STEP_LU) as usize;
+        let bin = bin.min(self.counts.len() - 1);
+        self.counts[bin] += 1;
+        self.powers[bin] += block_power;
+    }
+
+    /// Mean power of the bins from `first` on.
+    fn mean_power(&self, first: usize) -> Option<f64> {
+        let count: u64 = self.counts[first..].iter().sum();
+        let power: f64 = self.powers[first..].iter().sum();
+        (count > 0).then(|| power / count as f64)
+    }
+
+    /// The first bin at or above `relative_gate_lu` relative to the mean of all blocks.
+    fn relative_gate_bin(&self, relative_gate_lu: f64) -> Option<usize> {
+        let gate = lufs(self.mean_power(0)?)? + relative_gate_lu;
+        let bin = ((gate - ABSOLUTE_GATE_LUFS) / HISTOGRAM_STEP_LU)
+            .ceil()
+            .max(0.0) as usize;
+        Some(bin.min(self.counts.len() - 1))
+    }
+
+    fn gated_mean(&self, relative_gate_lu: f64) -> Option<f64> {
+        self.mean_power(self.relative_gate_bin(relative_gate_lu)?)
+    }
+
+    /// Difference between the high and low percentile of the block loudness distribution
+    /// above the relative gate, in LU.
+    fn range(&self) -> Option<f64> {
+        let first = self.relative_gate_bin(RANGE_RELATIVE_GATE_LU)?;
+        let count: u64 = self.counts[first..].iter().sum();
+        if count == 0 {
+            return None;
+        }
+
+        let percentile_bin = |percentile: f64| {
+            let rank = (count as f64 * percentile) as u64;
+            let mut seen = 0;
+            for (bin, bin_count) in self.counts.iter().enumerate().skip(first) {
+                seen += bin_count;
+                if seen > rank {
+                    return bin;
+                }
+            }
+            self.counts.len() - 1
+        };
+
+        let low = percentile_bin(RANGE_LOW_PERCENTILE);
+        let high = percentile_bin(RANGE_HIGH_PERCENTILE);
+        Some((high - low) as f64 * HISTOGRAM_STEP_LU)
+    }
+}
+
+/// The K-weighting pre-filter of ITU-R BS.1770: a high shelf modelling the head, followed by
+/// a high pass.
+#[derive(Debug)]
+struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    fn new(sample_rate: u32) -> Self {
+        let fs = sample_rate as f64;
+
+        // coefficients derived for any sample rate, matching those given in BS.1770 for 48 kHz
+        let f0 = 1_681.974_450_955_533;
+        let gain_db = 3.999_843_853_973_347;
+        let q = 0.707_175_236_955_419_6;
+        let k = (PI * f0 / fs).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.499_666_774_154_541_6);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        let f0 = 38.135_470_876_024_44;
+        let q = 0.500_327_037_323_877_3;
+        let k = (PI * f0 / fs).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        KWeighting { shelf, high_pass }
+    }
+
+    fn process(&mut self, sample: f64) -> f64 {
+        self.high_pass.process(self.shelf.process(sample))
+    }
+}
+
+#[derive(Debug)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    state: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad {
+            b,
+            a,
+            state: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.state[0];
+        self.state[0] = self.b[1] * x - self.a[0] * y + self.state[1];
+        self.state[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ChannelGroup, ChannelGroupKind};
+
+    /// Stereo 1 kHz sine with the given level on both channels.
+    fn sine(level_db: f64, seconds: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(level_db / 20.0);
+        (0..(48_000.0 * seconds) as usize)
+            .flat_map(|i| {
+                let s = (amplitude * (2.0 * PI * 1_000.0 * i as f64 / 48_000.0).sin()) as f32;
+                [s, s]
+            })
+            .collect()
+    }
+
+    fn assert_close(value: Option<f64>, expected: f64, tolerance: f64) {
+        let value = value.expect("no reading");
+        assert!(
+            (value - expected).abs() <= tolerance,
+            "{value} is not {expected} +/- {tolerance}"
+        );
+    }
+
+    #[test]
+    fn sine_at_reference_level() {
+        // EBU Tech 3341, case 1
+        let mut meter = LoudnessMeter::new(vec![1.0, 1.0], 48_000);
+        meter.process(&sine(-23.0, 20.0));
+
+        let loudness = meter.loudness();
+        assert_close(loudness.momentary_lufs, -23.0, 0.1);
+        assert_close(loudness.short_term_lufs, -23.0, 0.1);
+        assert_close(loudness.integrated_lufs, -23.0, 0.1);
+        assert_close(loudness.loudness_range_lu, 0.0, 0.2);
+    }
+
+    #[test]
+    fn quiet_passages_are_gated() {
+        // EBU Tech 3341, case 3
+        let mut meter = LoudnessMeter::new(vec![1.0, 1.0], 48_000);
+        meter.process(&sine(-36.0, 10.0));
+        meter.process(&sine(-23.0, 60.0));
+        meter.process(&sine(-36.0, 10.0));
+
+        assert_close(meter.loudness().integrated_lufs, -23.0, 0.1);
+    }
+
+    #[test]
+    fn loudness_range() {
+        // EBU Tech 3342, case 1
+        let mut meter = LoudnessMeter::new(vec![1.0, 1.0], 48_000);
+        meter.process(&sine(-20.0, 20.0));
+        meter.process(&sine(-30.0, 20.0));
+
+        assert_close(meter.loudness().loudness_range_lu, 10.0, 1.0);
+
+        meter.reset();
+        assert_eq!(meter.loudness().integrated_lufs, None);
+        assert_eq!(meter.loudness().loudness_range_lu, None);
+    }
+
+    #[test]
+    fn weights_follow_channel_layout() {
+        let layout = ChannelLayout {
+            groups: vec![
+                ChannelGroup::new(ChannelGroupKind::Surround51),
+                ChannelGroup::new(ChannelGroupKind::Stereo),
+            ],
+        };
+        assert_eq!(
+            channel_weights(Some(&layout), 8, None),
+            vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41, 1.0, 1.0]
+        );
+        assert_eq!(
+            channel_weights(Some(&layout), 8, Some(2)),
+            vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0]
+        );
+        assert_eq!(channel_weights(None, 2, None), vec![1.0, 1.0]);
+    }
+}
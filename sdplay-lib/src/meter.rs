@@ -0,0 +1,312 @@
+use crate::ChannelLayout;
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+/// Time in milliseconds each published meter reading covers.
+pub const METER_INTERVAL_MS: f32 = 50.0;
+/// Level reported for silence, in dBFS.
+const MIN_LEVEL_DB: f32 = -120.0;
+/// Meter readings a subscriber may fall behind before it misses some.
+const METER_FEED_CAPACITY: usize = 16;
+
+/// Taps per phase of the true-peak oversampling filter.
+const TRUE_PEAK_TAPS: usize = 12;
+/// Polyphase FIR for 4x oversampling as given in ITU-R BS.1770-4, Annex 2.
+const TRUE_PEAK_FILTER: [[f32; TRUE_PEAK_TAPS]; 4] = [
+    [
+        0.001_708_984_4,
+        0.010_986_328,
+        -0.019_653_32,
+        0.033_203_125,
+        -0.059_448_242,
+        0.137_329_1,
+        0.972_167_97,
+        -0.102_294_92,
+        0.047_607_42,
+        -0.026_611_328,
+        0.014_892_578,
+        -0.008_300_781,
+    ],
+    [
+        -0.029_174_805,
+        0.029_296_875,
+        -0.051_757_81,
+        0.089_111_33,
+        -0.166_503_9,
+        0.465_087_9,
+        0.779_785_16,
+        -0.200_317_38,
+        0.101_562_5,
+        -0.058_227_54,
+        0.033_081_055,
+        -0.018_920_898,
+    ],
+    [
+        -0.018_920_898,
+        0.033_081_055,
+        -0.058_227_54,
+        0.101_562_5,
+        -0.200_317_38,
+        0.779_785_16,
+        0.465_087_9,
+        -0.166_503_9,
+        0.089_111_33,
+        -0.051_757_81,
+        0.029_296_875,
+        -0.029_174_805,
+    ],
+    [
+        -0.008_300_781,
+        0.014_892_578,
+        -0.026_611_328,
+        0.047_607_42,
+        -0.102_294_92,
+        0.972_167_97,
+        0.137_329_1,
+        -0.059_448_242,
+        0.033_203_125,
+        -0.019_653_32,
+        0.010_986_328,
+        0.001_708_984_4,
+    ],
+];
+
+/// Levels of one channel over one meter interval, in dBFS.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Object)]
+pub struct ChannelLevel {
+    /// the channel's label in the stream's SMPTE 2110 channel order, e.g. `2 ST R`, if the
+    /// stream declares one
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub label: Option<String>,
+    /// highest absolute sample value
+    pub peak_db: f32,
+    /// RMS level, a full scale sine reads -3 dB
+    pub rms_db: f32,
+    /// highest level of the signal reconstructed at 4x the sample rate, which also catches
+    /// peaks between samples
+    pub true_peak_db: f32,
+}
+
+/// Levels of all channels of a stream over one meter interval.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Object)]
+pub struct MeterLevels {
+    pub channels: Vec<ChannelLevel>,
+}
+
+/// The meter readings of a player, published about every [`METER_INTERVAL_MS`] while it is
+/// playing. Clones refer to the same feed.
+#[derive(Debug, Clone)]
+pub struct MeterFeed {
+    sender: broadcast::Sender<MeterLevels>,
+}
+
+impl Default for MeterFeed {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(METER_FEED_CAPACITY);
+        MeterFeed { sender }
+    }
+}
+
+impl MeterFeed {
+    pub fn new() -> Self {
+        MeterFeed::default()
+    }
+
+    /// Receives all readings published from now on. A receiver that doesn't keep up skips the
+    /// readings it missed.
+    pub fn subscribe(&self) -> broadcast::Receiver<MeterLevels> {
+        self.sender.subscribe()
+    }
+
+    pub(crate) fn publish(&self, levels: MeterLevels) {
+        // nobody listening is fine
+        self.sender.send(levels).ok();
+    }
+}
+
+/// Measures peak, RMS and true-peak levels of interleaved audio over consecutive intervals of
+/// [`METER_INTERVAL_MS`].
+#[derive(Debug)]
+pub struct Meter {
+    channels: usize,
+    labels: Vec<Option<String>>,
+    interval_frames: usize,
+    frames: usize,
+    peak: Vec<f32>,
+    square_sum: Vec<f64>,
+    true_peak: Vec<TruePeakDetector>,
+}
+
+impl Meter {
+    pub fn new(channels: u16, sample_rate: u32) -> Self {
+        let channels = channels.max(1) as usize;
+        Meter {
+            channels,
+            labels: vec![None; channels],
+            interval_frames: ((sample_rate as f32 * METER_INTERVAL_MS / 1_000.0) as usize).max(1),
+            frames: 0,
+            peak: vec![0.0; channels],
+            square_sum: vec![0.0; channels],
+            true_peak: (0..channels).map(|_| TruePeakDetector::default()).collect(),
+        }
+    }
+
+    /// Labels the channels of the readings after `layout`, if it has as many channels as the
+    /// meter.
+    pub fn with_channel_layout(mut self, layout: Option<&ChannelLayout>) -> Self {
+        if let Some(layout) = layout.filter(|layout| layout.channels() as usize == self.channels) {
+            self.labels = layout.channel_labels().into_iter().map(Some).collect();
+        }
+        self
+    }
+
+    /// Measures `samples` and calls `on_levels` for every interval completed by them.
+    pub fn process(&mut self, samples: &[f32], mut on_levels: impl FnMut(MeterLevels)) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, s) in frame.iter().enumerate() {
+                self.peak[channel] = self.peak[channel].max(s.abs());
+                self.square_sum[channel] += (*s as f64) * (*s as f64);
+                self.true_peak[channel].process(*s);
+            }
+            self.frames += 1;
+            if self.frames >= self.interval_frames {
+                on_levels(self.take());
+            }
+        }
+    }
+
+    /// Frames each reading covers, but the one returned by [`flush`](Self::flush).
+    pub fn interval_frames(&self) -> usize {
+        self.interval_frames
+    }
+
+    /// Ends the current interval early, returning its levels and the number of frames they
+    /// cover, if any samples were measured since the last reading.
+    pub fn flush(&mut self) -> Option<(MeterLevels, usize)> {
+        let frames = self.frames;
+        (frames > 0).then(|| (self.take(), frames))
+    }
+
+    fn take(&mut self) -> MeterLevels {
+        let frames = self.frames.max(1) as f64;
+        let channels = (0..self.channels)
+            .map(|channel| ChannelLevel {
+                label: self.labels[channel].clone(),
+                peak_db: level_db(self.peak[channel]),
+                rms_db: level_db((self.square_sum[channel] / frames).sqrt() as f32),
+                true_peak_db: level_db(self.true_peak[channel].take()),
+            })
+            .collect();
+
+        self.frames = 0;
+        self.peak.fill(0.0);
+        self.square_sum.fill(0.0);
+
+        MeterLevels { channels }
+    }
+}
+
+#[derive(Debug, Default)]
+struct TruePeakDetector {
+    /// the most recent samples, newest last
+    history: [f32; TRUE_PEAK_TAPS],
+    peak: f32,
+}
+
+impl TruePeakDetector {
+    fn process(&mut self, sample: f32) {
+        self.history.rotate_left(1);
+        self.history[TRUE_PEAK_TAPS - 1] = sample;
+
+        for phase in &TRUE_PEAK_FILTER {
+            let value: f32 = phase
+                .iter()
+                .zip(self.history.iter().rev())
+                .map(|(c, s)| c * s)
+                .sum();
+            self.peak = self.peak.max(value.abs());
+        }
+    }
+
+    fn take(&mut self) -> f32 {
+        std::mem::take(&mut self.peak)
+    }
+}
+
+fn level_db(level: f32) -> f32 {
+    if level > 0.0 {
+        (20.0 * level.log10()).max(MIN_LEVEL_DB)
+    } else {
+        MIN_LEVEL_DB
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn measure(samples: &[f32]) -> Vec<MeterLevels> {
+        let mut meter = Meter::new(1, 48_000);
+        let mut levels = vec![];
+        meter.process(samples, |l| levels.push(l));
+        levels
+    }
+
+    #[test]
+    fn full_scale_sine() {
+        let samples: Vec<f32> = (0..9_600)
+            .map(|i| (2.0 * PI * 997.0 * i as f32 / 48_000.0).sin())
+            .collect();
+
+        let levels = measure(&samples);
+        // 200 ms in 50 ms intervals
+        assert_eq!(levels.len(), 4);
+        let level = &levels[3].channels[0];
+        assert!(level.peak_db.abs() < 0.01, "{level:?}");
+        assert!((level.rms_db + 3.01).abs() < 0.05, "{level:?}");
+        assert!(level.true_peak_db.abs() < 0.2, "{level:?}");
+    }
+
+    #[test]
+    fn true_peak_catches_inter_sample_peaks() {
+        // a sine at a quarter of the sample rate, sampled 45 degrees off its peaks
+        let samples: Vec<f32> = (0..4_800)
+            .map(|i| (PI / 2.0 * i as f32 + PI / 4.0).sin())
+            .collect();
+
+        let level = &measure(&samples)[1].channels[0];
+        assert!((level.peak_db + 3.01).abs() < 0.05, "{level:?}");
+        assert!(level.true_peak_db > -0.6, "{level:?}");
+    }
+
+    #[test]
+    fn silence() {
+        let level = &measure(&[0.0; 2_400])[0].channels[0];
+        assert_eq!(level.peak_db, MIN_LEVEL_DB);
+        assert_eq!(level.rms_db, MIN_LEVEL_DB);
+        assert_eq!(level.true_peak_db, MIN_LEVEL_DB);
+        assert_eq!(level.label, None);
+    }
+
+    #[test]
+    fn labels_follow_channel_layout() {
+        let layout: ChannelLayout = "SMPTE2110.(M,ST)".parse().unwrap();
+        let mut meter = Meter::new(3, 48_000).with_channel_layout(Some(&layout));
+        let mut levels = vec![];
+        meter.process(&[0.0; 7_200], |l| levels.push(l));
+        let labels: Vec<_> = levels[0]
+            .channels
+            .iter()
+            .map(|level| level.label.as_deref())
+            .collect();
+        assert_eq!(labels, [Some("1 M M"), Some("2 ST L"), Some("2 ST R")]);
+
+        // a layout that doesn't match the stream is ignored
+        let mut meter = Meter::new(2, 48_000).with_channel_layout(Some(&layout));
+        let mut levels = vec![];
+        meter.process(&[0.0; 4_800], |l| levels.push(l));
+        assert_eq!(levels[0].channels[0].label, None);
+    }
+}
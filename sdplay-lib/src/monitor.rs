@@ -0,0 +1,114 @@
+use crate::{
+    incident::IncidentCapture, jitter::JitterBufferStats, loudness::Loudness, meter::MeterFeed,
+    recorder::Recorder, rtp::RtpStats,
+};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+/// Live figures of a running playback, updated by the playback engine about once per second.
+/// Clones refer to the same figures, so a clone can be kept to observe a playback after it was
+/// handed to [`play`](crate::audio::play).
+#[derive(Debug, Clone, Default)]
+pub struct PlaybackMonitor {
+    stats: Arc<Mutex<PlaybackStats>>,
+    meters: MeterFeed,
+    recorder: Recorder,
+    incidents: IncidentCapture,
+    reset_loudness: Arc<AtomicBool>,
+}
+
+impl PlaybackMonitor {
+    pub fn new() -> Self {
+        PlaybackMonitor::default()
+    }
+
+    /// A monitor that publishes its meter readings to `meters`, so they can be followed across
+    /// playbacks.
+    pub fn with_meters(meters: MeterFeed) -> Self {
+        PlaybackMonitor {
+            stats: Arc::default(),
+            meters,
+            recorder: Recorder::default(),
+            incidents: IncidentCapture::default(),
+            reset_loudness: Arc::default(),
+        }
+    }
+
+    /// Hands the received packets of the playback to `recorder`.
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// The live meter readings of the playback.
+    pub fn meters(&self) -> &MeterFeed {
+        &self.meters
+    }
+
+    /// The recorder the received packets of the playback are handed to.
+    pub fn recorder(&self) -> &Recorder {
+        &self.recorder
+    }
+
+    /// Watches the received audio of the playback for incidents with `incidents`.
+    pub fn with_incidents(mut self, incidents: IncidentCapture) -> Self {
+        self.incidents = incidents;
+        self
+    }
+
+    /// The incident capture the received audio of the playback is handed to.
+    pub fn incidents(&self) -> &IncidentCapture {
+        &self.incidents
+    }
+
+    /// A snapshot of the current figures.
+    pub fn stats(&self) -> PlaybackStats {
+        self.stats
+            .lock()
+            .map(|stats| stats.clone())
+            .unwrap_or_default()
+    }
+
+    /// Starts the integrated loudness and loudness range measurements over.
+    pub fn reset_loudness(&self) {
+        self.reset_loudness.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn take_loudness_reset(&self) -> bool {
+        self.reset_loudness.swap(false, Ordering::Relaxed)
+    }
+
+    pub(crate) fn update(&self, update: impl FnOnce(&mut PlaybackStats)) {
+        if let Ok(mut stats) = self.stats.lock() {
+            update(&mut stats);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Object)]
+pub struct PlaybackStats {
+    /// name of the output device, once it has been opened
+    pub device: Option<String>,
+    /// sample rate the output device runs at
+    pub device_sample_rate: Option<u32>,
+    /// RTP packets received, summed over all legs of a redundant stream
+    pub packets_received: u64,
+    /// RTP packets missing on the primary leg; for redundant streams only those missing on all
+    /// legs are counted
+    pub packets_lost: u64,
+    /// RTP packets received per second, summed over all legs
+    pub packet_rate: f32,
+    /// audio currently buffered in the jitter buffer, in milliseconds
+    pub buffer_fill_ms: f32,
+    /// estimated clock drift between sender and output device in ppm
+    pub drift_ppm: f64,
+    pub jitter_buffer: JitterBufferStats,
+    /// RFC 3550 reception statistics of each leg of the stream, the primary one first
+    pub rtp: Vec<RtpStats>,
+    /// loudness of the played channels, updated every 100 ms
+    pub loudness: Loudness,
+}
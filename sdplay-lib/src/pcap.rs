@@ -0,0 +1,704 @@
+use crate::error::{SdpPlayerError, SdpPlayerResult};
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, BufReader, ErrorKind, Read},
+    net::{Ipv4Addr, SocketAddrV4},
+    path::Path,
+    time::Duration,
+};
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_OPTION_END: u16 = 0;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+/// Largest block or record accepted, to not allocate absurd amounts for corrupt files.
+const MAX_RECORD_SIZE: usize = 16 * 1024 * 1024;
+
+const LINKTYPE_NULL: u16 = 0;
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_LOOP: u16 = 108;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_IPV4: u16 = 228;
+const LINKTYPE_LINUX_SLL2: u16 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+const IP_PROTOCOL_UDP: u8 = 17;
+const AF_INET: u32 = 2;
+
+/// A UDP datagram read from a capture file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdpDatagram {
+    /// when the datagram was captured, since the Unix epoch
+    pub time: Duration,
+    pub source: SocketAddrV4,
+    pub destination: SocketAddrV4,
+    pub payload: Vec<u8>,
+}
+
+/// Reads the IPv4 UDP datagrams of a pcap or pcapng capture file, as written by Wireshark or
+/// tcpdump, in the order they were captured. Other packets, including fragmented datagrams,
+/// are skipped.
+pub struct PcapReader<R: Read> {
+    reader: R,
+    format: Format,
+    /// link types packets were skipped for, so each is only warned about once
+    unsupported_link_types: HashSet<u16>,
+}
+
+enum Format {
+    Pcap {
+        big_endian: bool,
+        units_per_second: u64,
+        link_type: u16,
+    },
+    PcapNg {
+        big_endian: bool,
+        interfaces: Vec<Interface>,
+    },
+}
+
+struct Interface {
+    link_type: u16,
+    units_per_second: u64,
+}
+
+impl PcapReader<BufReader<File>> {
+    pub fn open(path: &Path) -> SdpPlayerResult<Self> {
+        PcapReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Reads the file header of the capture.
+    pub fn new(mut reader: R) -> SdpPlayerResult<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).map_err(truncated)?;
+
+        // the section header block type reads the same in both byte orders
+        if u32::from_le_bytes(magic) == PCAPNG_SECTION_HEADER {
+            let mut reader = PcapReader {
+                reader,
+                format: Format::PcapNg {
+                    big_endian: false,
+                    interfaces: Vec::new(),
+                },
+                unsupported_link_types: HashSet::new(),
+            };
+            if reader.read_block_body(PCAPNG_SECTION_HEADER)?.is_none() {
+                return Err(SdpPlayerError::MalformedCapture(
+                    "section header is incomplete".to_owned(),
+                ));
+            }
+            return Ok(reader);
+        }
+
+        let (big_endian, units_per_second) =
+            match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+                (PCAP_MAGIC_MICROS, _) => (false, 1_000_000),
+                (PCAP_MAGIC_NANOS, _) => (false, 1_000_000_000),
+                (_, PCAP_MAGIC_MICROS) => (true, 1_000_000),
+                (_, PCAP_MAGIC_NANOS) => (true, 1_000_000_000),
+                _ => {
+                    return Err(SdpPlayerError::MalformedCapture(
+                        "not a pcap or pcapng file".to_owned(),
+                    ))
+                }
+            };
+        let mut header = [0; 20];
+        reader.read_exact(&mut header).map_err(truncated)?;
+        Ok(PcapReader {
+            reader,
+            format: Format::Pcap {
+                big_endian,
+                units_per_second,
+                link_type: u32_at(&header, 16, big_endian) as u16,
+            },
+            unsupported_link_types: HashSet::new(),
+        })
+    }
+
+    /// The next UDP datagram of the capture, `None` at its end.
+    pub fn next_datagram(&mut self) -> SdpPlayerResult<Option<UdpDatagram>> {
+        loop {
+            let Some((link_type, time, data)) = self.next_packet()? else {
+                return Ok(None);
+            };
+            let Some(ip) = self.ip_packet(link_type, &data) else {
+                continue;
+            };
+            if let Some(datagram) = udp_datagram(time, ip) {
+                return Ok(Some(datagram));
+            }
+        }
+    }
+
+    /// The next captured packet with the link type and time it was captured with.
+    fn next_packet(&mut self) -> SdpPlayerResult<Option<(u16, Duration, Vec<u8>)>> {
+        match &self.format {
+            &Format::Pcap {
+                big_endian,
+                units_per_second,
+                link_type,
+            } => {
+                let mut header = [0; 16];
+                if !read_or_end(&mut self.reader, &mut header)? {
+                    return Ok(None);
+                }
+                let seconds = u32_at(&header, 0, big_endian) as u64;
+                let fraction = u32_at(&header, 4, big_endian) as u64;
+                let len = u32_at(&header, 8, big_endian) as usize;
+                let Some(data) = self.read_record(len)? else {
+                    return Ok(None);
+                };
+                let time = Duration::from_secs(seconds)
+                    + Duration::from_nanos(fraction * 1_000_000_000 / units_per_second);
+                Ok(Some((link_type, time, data)))
+            }
+            Format::PcapNg { .. } => loop {
+                let Some((block_type, body)) = self.read_block()? else {
+                    return Ok(None);
+                };
+                let Format::PcapNg {
+                    big_endian,
+                    interfaces,
+                } = &mut self.format
+                else {
+                    unreachable!("the format doesn't change within a file");
+                };
+                let big_endian = *big_endian;
+                match block_type {
+                    // a new section, its byte order and interfaces are set up already
+                    PCAPNG_SECTION_HEADER => continue,
+                    PCAPNG_INTERFACE_DESCRIPTION if body.len() >= 8 => {
+                        interfaces.push(Interface {
+                            link_type: u16_at(&body, 0, big_endian),
+                            units_per_second: interface_resolution(&body[8..], big_endian),
+                        });
+                    }
+                    PCAPNG_ENHANCED_PACKET if body.len() >= 20 => {
+                        let interface = u32_at(&body, 0, big_endian) as usize;
+                        let Some(interface) = interfaces.get(interface) else {
+                            return Err(SdpPlayerError::MalformedCapture(format!(
+                                "packet of undeclared interface {interface}"
+                            )));
+                        };
+                        let units = (u32_at(&body, 4, big_endian) as u64) << 32
+                            | u32_at(&body, 8, big_endian) as u64;
+                        let len = u32_at(&body, 12, big_endian) as usize;
+                        let data = body.get(20..20 + len).ok_or_else(|| {
+                            SdpPlayerError::MalformedCapture(
+                                "packet longer than its block".to_owned(),
+                            )
+                        })?;
+                        let units_per_second = interface.units_per_second;
+                        let time = Duration::from_secs(units / units_per_second)
+                            + Duration::from_nanos(
+                                ((units % units_per_second) as u128 * 1_000_000_000
+                                    / units_per_second as u128)
+                                    as u64,
+                            );
+                        return Ok(Some((interface.link_type, time, data.to_vec())));
+                    }
+                    // statistics, name resolution, simple packets without timestamps etc.
+                    _ => continue,
+                }
+            },
+        }
+    }
+
+    /// Reads the next pcapng block and returns its type and body, `None` at the end of the
+    /// file.
+    fn read_block(&mut self) -> SdpPlayerResult<Option<(u32, Vec<u8>)>> {
+        let Format::PcapNg { big_endian, .. } = self.format else {
+            return Ok(None);
+        };
+        let mut block_type = [0; 4];
+        if !read_or_end(&mut self.reader, &mut block_type)? {
+            return Ok(None);
+        }
+        self.read_block_body(u32_at(&block_type, 0, big_endian))
+    }
+
+    /// Reads the rest of a pcapng block of which the type has been read. A section header
+    /// block starts a new section in its own byte order.
+    fn read_block_body(&mut self, block_type: u32) -> SdpPlayerResult<Option<(u32, Vec<u8>)>> {
+        let Format::PcapNg { big_endian, .. } = self.format else {
+            return Ok(None);
+        };
+        let Some(len) = self.read_record(4)? else {
+            return Ok(None);
+        };
+
+        if block_type == PCAPNG_SECTION_HEADER {
+            let Some(magic) = self.read_record(4)? else {
+                return Ok(None);
+            };
+            let big_endian = if magic == PCAPNG_BYTE_ORDER_MAGIC.to_be_bytes() {
+                true
+            } else if magic == PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes() {
+                false
+            } else {
+                return Err(SdpPlayerError::MalformedCapture(
+                    "invalid pcapng byte order magic".to_owned(),
+                ));
+            };
+            self.format = Format::PcapNg {
+                big_endian,
+                interfaces: Vec::new(),
+            };
+            let body_len = block_body_len(u32_at(&len, 0, big_endian) as usize)?;
+            if body_len < 4 {
+                return Err(SdpPlayerError::MalformedCapture(
+                    "short section header".to_owned(),
+                ));
+            }
+            // the rest of the body and the trailing block length
+            let Some(rest) = self.read_record(body_len)? else {
+                return Ok(None);
+            };
+            let mut body = magic;
+            body.extend_from_slice(&rest[..rest.len() - 4]);
+            return Ok(Some((block_type, body)));
+        }
+
+        let body_len = block_body_len(u32_at(&len, 0, big_endian) as usize)?;
+        let Some(mut body) = self.read_record(body_len + 4)? else {
+            return Ok(None);
+        };
+        // drop the trailing block length
+        body.truncate(body_len);
+        Ok(Some((block_type, body)))
+    }
+
+    /// Reads a record of `len` bytes, `None` if the capture ends within it as it does when
+    /// capturing was interrupted.
+    fn read_record(&mut self, len: usize) -> SdpPlayerResult<Option<Vec<u8>>> {
+        if len > MAX_RECORD_SIZE {
+            return Err(SdpPlayerError::MalformedCapture(format!(
+                "record of {len} bytes"
+            )));
+        }
+        let mut data = vec![0; len];
+        match self.reader.read_exact(&mut data) {
+            Ok(()) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                log::warn!("Capture ends within a packet, it was probably cut off");
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The IPv4 packet carried by a frame of the given link type.
+    fn ip_packet<'a>(&mut self, link_type: u16, data: &'a [u8]) -> Option<&'a [u8]> {
+        let (ether_type, offset) = match link_type {
+            LINKTYPE_ETHERNET => {
+                let mut offset = 12;
+                let mut ether_type = u16_at_be(data, offset)?;
+                while ether_type == ETHERTYPE_VLAN || ether_type == ETHERTYPE_QINQ {
+                    offset += 4;
+                    ether_type = u16_at_be(data, offset)?;
+                }
+                (ether_type, offset + 2)
+            }
+            LINKTYPE_LINUX_SLL => (u16_at_be(data, 14)?, 16),
+            LINKTYPE_LINUX_SLL2 => (u16_at_be(data, 0)?, 20),
+            LINKTYPE_RAW | LINKTYPE_IPV4 => (ETHERTYPE_IPV4, 0),
+            LINKTYPE_NULL | LINKTYPE_LOOP => {
+                let family = data.get(..4)?;
+                let family = [family[0], family[1], family[2], family[3]];
+                let ipv4 =
+                    u32::from_le_bytes(family) == AF_INET || u32::from_be_bytes(family) == AF_INET;
+                (if ipv4 { ETHERTYPE_IPV4 } else { 0 }, 4)
+            }
+            _ => {
+                if self.unsupported_link_types.insert(link_type) {
+                    log::warn!("Skipping packets of unsupported link type {link_type}");
+                }
+                return None;
+            }
+        };
+        if ether_type == ETHERTYPE_IPV4 {
+            data.get(offset..)
+        } else {
+            None
+        }
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = SdpPlayerResult<UdpDatagram>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_datagram().transpose()
+    }
+}
+
+/// The UDP datagram carried by an IPv4 packet, if it isn't fragmented.
+fn udp_datagram(time: Duration, ip: &[u8]) -> Option<UdpDatagram> {
+    let version_and_length = *ip.first()?;
+    if version_and_length >> 4 != 4 || ip.get(9)? != &IP_PROTOCOL_UDP {
+        return None;
+    }
+    let fragment = u16_at_be(ip, 6)?;
+    // more fragments flag or fragment offset
+    if fragment & 0x3fff != 0 {
+        log::trace!("Skipping fragmented datagram");
+        return None;
+    }
+    let header_len = (version_and_length & 0x0f) as usize * 4;
+    if header_len < 20 {
+        return None;
+    }
+    let total_len = (u16_at_be(ip, 2)? as usize).min(ip.len());
+    let addresses = ip.get(12..20)?;
+    let source = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+    let destination = Ipv4Addr::new(addresses[4], addresses[5], addresses[6], addresses[7]);
+
+    let udp = ip.get(header_len..total_len)?;
+    let udp_len = (u16_at_be(udp, 4)? as usize).min(udp.len());
+    Some(UdpDatagram {
+        time,
+        source: SocketAddrV4::new(source, u16_at_be(udp, 0)?),
+        destination: SocketAddrV4::new(destination, u16_at_be(udp, 2)?),
+        payload: udp.get(8..udp_len)?.to_vec(),
+    })
+}
+
+/// Time units per second of a pcapng interface, from the `if_tsresol` option among `options`.
+fn interface_resolution(options: &[u8], big_endian: bool) -> u64 {
+    let mut offset = 0;
+    while let (Some(code), Some(len)) = (
+        options.get(offset..offset + 2),
+        options.get(offset + 2..offset + 4),
+    ) {
+        let code = u16_at(code, 0, big_endian);
+        let len = u16_at(len, 0, big_endian) as usize;
+        if code == PCAPNG_OPTION_END {
+            break;
+        }
+        if code == PCAPNG_OPTION_TSRESOL && len == 1 {
+            if let Some(&resolution) = options.get(offset + 4) {
+                let exponent = (resolution & 0x7f) as u32;
+                let base: u64 = if resolution & 0x80 == 0 { 10 } else { 2 };
+                if let Some(units) = base.checked_pow(exponent) {
+                    return units.max(1);
+                }
+            }
+        }
+        offset += 4 + len.div_ceil(4) * 4;
+    }
+    1_000_000
+}
+
+/// Length of the body of a pcapng block of total length `len`.
+fn block_body_len(len: usize) -> SdpPlayerResult<usize> {
+    if len < 12 || !len.is_multiple_of(4) {
+        return Err(SdpPlayerError::MalformedCapture(format!(
+            "invalid block length {len}"
+        )));
+    }
+    Ok(len - 12)
+}
+
+/// Fills `buf`, returning `false` if the reader is at its end before the first byte.
+fn read_or_end(reader: &mut impl Read, buf: &mut [u8]) -> SdpPlayerResult<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => {
+                log::warn!("Capture ends within a packet header, it was probably cut off");
+                return Ok(false);
+            }
+            Ok(n) => read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+fn truncated(e: io::Error) -> SdpPlayerError {
+    if e.kind() == ErrorKind::UnexpectedEof {
+        SdpPlayerError::MalformedCapture("file header is incomplete".to_owned())
+    } else {
+        e.into()
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize, big_endian: bool) -> u32 {
+    let value = [
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ];
+    if big_endian {
+        u32::from_be_bytes(value)
+    } else {
+        u32::from_le_bytes(value)
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize, big_endian: bool) -> u16 {
+    let value = [bytes[offset], bytes[offset + 1]];
+    if big_endian {
+        u16::from_be_bytes(value)
+    } else {
+        u16::from_le_bytes(value)
+    }
+}
+
+fn u16_at_be(bytes: &[u8], offset: usize) -> Option<u16> {
+    let value = bytes.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([value[0], value[1]]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ipv4_udp(source: SocketAddrV4, destination: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+        let mut ip = vec![0x45, 0, 0, 0, 0, 0, 0x40, 0, 64, IP_PROTOCOL_UDP, 0, 0];
+        ip.extend_from_slice(&source.ip().octets());
+        ip.extend_from_slice(&destination.ip().octets());
+        ip.extend_from_slice(&source.port().to_be_bytes());
+        ip.extend_from_slice(&destination.port().to_be_bytes());
+        ip.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        ip.extend_from_slice(&[0, 0]);
+        ip.extend_from_slice(payload);
+        let len = (ip.len() as u16).to_be_bytes();
+        ip[2..4].copy_from_slice(&len);
+        ip
+    }
+
+    fn ethernet(ip: &[u8], vlan: bool) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        if vlan {
+            frame.extend_from_slice(&ETHERTYPE_VLAN.to_be_bytes());
+            frame.extend_from_slice(&[0, 42]);
+        }
+        frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        frame.extend_from_slice(ip);
+        frame
+    }
+
+    fn pcap(big_endian: bool, link_type: u32, packets: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+        let u32_bytes = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut file = u32_bytes(PCAP_MAGIC_MICROS).to_vec();
+        file.extend_from_slice(&[2, 0, 4, 0]);
+        file.extend_from_slice(&[0; 8]);
+        file.extend_from_slice(&u32_bytes(65535));
+        file.extend_from_slice(&u32_bytes(link_type));
+        for (seconds, micros, data) in packets {
+            file.extend_from_slice(&u32_bytes(*seconds));
+            file.extend_from_slice(&u32_bytes(*micros));
+            file.extend_from_slice(&u32_bytes(data.len() as u32));
+            file.extend_from_slice(&u32_bytes(data.len() as u32));
+            file.extend_from_slice(data);
+        }
+        file
+    }
+
+    fn pcapng_block(big_endian: bool, block_type: u32, body: &[u8]) -> Vec<u8> {
+        let u32_bytes = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let padded = body.len().div_ceil(4) * 4;
+        let len = 12 + padded as u32;
+        let mut block = u32_bytes(block_type).to_vec();
+        block.extend_from_slice(&u32_bytes(len));
+        block.extend_from_slice(body);
+        block.resize(8 + padded, 0);
+        block.extend_from_slice(&u32_bytes(len));
+        block
+    }
+
+    /// A pcapng section with one interface of `link_type` and nanosecond timestamps, holding
+    /// `packets` captured at the given nanoseconds since the epoch.
+    fn pcapng_section(big_endian: bool, link_type: u16, packets: &[(u64, Vec<u8>)]) -> Vec<u8> {
+        let u16_bytes = |value: u16| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let u32_bytes = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut header = u32_bytes(PCAPNG_BYTE_ORDER_MAGIC).to_vec();
+        header.extend_from_slice(&u16_bytes(1));
+        header.extend_from_slice(&u16_bytes(0));
+        header.extend_from_slice(&[0xff; 8]);
+        let mut section = pcapng_block(big_endian, PCAPNG_SECTION_HEADER, &header);
+
+        let mut interface = u16_bytes(link_type).to_vec();
+        interface.extend_from_slice(&[0, 0]);
+        interface.extend_from_slice(&u32_bytes(65535));
+        interface.extend_from_slice(&u16_bytes(PCAPNG_OPTION_TSRESOL));
+        interface.extend_from_slice(&u16_bytes(1));
+        interface.extend_from_slice(&[9, 0, 0, 0]);
+        interface.extend_from_slice(&[0; 4]);
+        section.extend(pcapng_block(
+            big_endian,
+            PCAPNG_INTERFACE_DESCRIPTION,
+            &interface,
+        ));
+        // an interface statistics block, to be skipped
+        section.extend(pcapng_block(big_endian, 5, &[0; 12]));
+
+        for (nanos, data) in packets {
+            let mut packet = u32_bytes(0).to_vec();
+            packet.extend_from_slice(&u32_bytes((nanos >> 32) as u32));
+            packet.extend_from_slice(&u32_bytes(*nanos as u32));
+            packet.extend_from_slice(&u32_bytes(data.len() as u32));
+            packet.extend_from_slice(&u32_bytes(data.len() as u32));
+            packet.extend_from_slice(data);
+            section.extend(pcapng_block(big_endian, PCAPNG_ENHANCED_PACKET, &packet));
+        }
+        section
+    }
+
+    fn read_all(file: &[u8]) -> Vec<UdpDatagram> {
+        PcapReader::new(file)
+            .unwrap()
+            .collect::<SdpPlayerResult<Vec<_>>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_udp_datagrams_from_pcap() {
+        let sender = "192.168.1.10:5004".parse().unwrap();
+        let group = "239.1.1.1:5004".parse().unwrap();
+        let mut fragment = ipv4_udp(sender, group, b"fragment");
+        fragment[6] = 0x20;
+        let mut tcp = ipv4_udp(sender, group, b"tcp");
+        tcp[9] = 6;
+
+        for big_endian in [false, true] {
+            let file = pcap(
+                big_endian,
+                LINKTYPE_ETHERNET as u32,
+                &[
+                    (100, 250, ethernet(&ipv4_udp(sender, group, b"one"), false)),
+                    (100, 1250, ethernet(&fragment, false)),
+                    (100, 2250, ethernet(&tcp, false)),
+                    (101, 0, ethernet(&ipv4_udp(sender, group, b"two"), true)),
+                ],
+            );
+            let datagrams = read_all(&file);
+            assert_eq!(datagrams.len(), 2);
+            assert_eq!(datagrams[0].time, Duration::from_micros(100_000_250));
+            assert_eq!(datagrams[0].source, sender);
+            assert_eq!(datagrams[0].destination, group);
+            assert_eq!(datagrams[0].payload, b"one");
+            assert_eq!(datagrams[1].time, Duration::from_secs(101));
+            assert_eq!(datagrams[1].payload, b"two");
+        }
+    }
+
+    #[test]
+    fn reads_udp_datagrams_from_pcapng_sections() {
+        let sender = "10.0.0.1:40000".parse().unwrap();
+        let group = "239.69.0.1:5004".parse().unwrap();
+        let mut sll = vec![0; 14];
+        sll.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        sll.extend(ipv4_udp(sender, group, b"second"));
+
+        let mut file = pcapng_section(
+            true,
+            LINKTYPE_ETHERNET,
+            &[(
+                1_700_000_000_123_456_789,
+                ethernet(&ipv4_udp(sender, group, b"first"), false),
+            )],
+        );
+        file.extend(pcapng_section(
+            false,
+            LINKTYPE_LINUX_SLL,
+            &[(1_700_000_001_000_000_000, sll)],
+        ));
+
+        let datagrams = read_all(&file);
+        assert_eq!(datagrams.len(), 2);
+        assert_eq!(
+            datagrams[0].time,
+            Duration::from_nanos(1_700_000_000_123_456_789)
+        );
+        assert_eq!(datagrams[0].payload, b"first");
+        assert_eq!(datagrams[1].time, Duration::from_secs(1_700_000_001));
+        assert_eq!(datagrams[1].destination, group);
+        assert_eq!(datagrams[1].payload, b"second");
+    }
+
+    #[test]
+    fn cut_off_capture_ends_early() {
+        let sender = "192.168.1.10:5004".parse().unwrap();
+        let group = "239.1.1.1:5004".parse().unwrap();
+        let packet = ethernet(&ipv4_udp(sender, group, b"payload"), false);
+        let file = pcap(
+            false,
+            LINKTYPE_ETHERNET as u32,
+            &[(1, 0, packet.clone()), (2, 0, packet)],
+        );
+        let datagrams = read_all(&file[..file.len() - 5]);
+        assert_eq!(datagrams.len(), 1);
+    }
+
+    #[test]
+    fn skips_truncated_frames() {
+        let sender = "192.168.1.10:5004".parse().unwrap();
+        let group = "239.1.1.1:5004".parse().unwrap();
+        let ip = ipv4_udp(sender, group, b"payload");
+        let mut short_header = ip.clone();
+        short_header[0] = 0x44;
+
+        // frames cut off by a small snaplen, down to just past the protocol field
+        let mut packets: Vec<_> = (10..28)
+            .map(|len| (1, 0, ethernet(&ip[..len], false)))
+            .collect();
+        packets.push((1, 0, ethernet(&short_header, false)));
+        packets.push((2, 0, ethernet(&ip, false)));
+        let file = pcap(false, LINKTYPE_ETHERNET as u32, &packets);
+
+        let datagrams = read_all(&file);
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(datagrams[0].payload, b"payload");
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(matches!(
+            PcapReader::new(&b"v=0\r\no=- 1 1 IN IP4 10.0.0.1\r\n"[..]),
+            Err(SdpPlayerError::MalformedCapture(_))
+        ));
+        assert!(matches!(
+            PcapReader::new(&[0xd4, 0xc3][..]),
+            Err(SdpPlayerError::MalformedCapture(_))
+        ));
+    }
+}
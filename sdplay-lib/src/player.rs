@@ -0,0 +1,264 @@
+use crate::{
+    audio::{play, PlaybackConfig},
+    error::{SdpPlayerError, SdpPlayerResult},
+    incident::IncidentCapture,
+    loudness::Loudness,
+    meter::MeterFeed,
+    monitor::{PlaybackMonitor, PlaybackStats},
+    recorder::Recorder,
+    stream::Stream,
+    volume::Volume,
+    SessionDescriptor,
+};
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    select, spawn,
+    sync::{broadcast, oneshot},
+    task::JoinHandle,
+};
+
+/// Owns the playback of one stream at a time: its receiver and its audio output.
+///
+/// Starting waits until audio output is running and fails if it can't be set up, stopping
+/// waits until both receiver and output are shut down, so the sockets and the output device
+/// are released once [`stop`](Self::stop) returns.
+#[derive(Debug)]
+pub struct Player {
+    volume: Volume,
+    meters: MeterFeed,
+    recorder: Recorder,
+    incidents: IncidentCapture,
+    last_error: Arc<Mutex<Option<String>>>,
+    playback: Option<Playback>,
+}
+
+#[derive(Debug)]
+struct Playback {
+    descriptor: SessionDescriptor,
+    monitor: PlaybackMonitor,
+    started: Instant,
+    stop: broadcast::Sender<()>,
+    task: JoinHandle<SdpPlayerResult<()>>,
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Player::new(Volume::new())
+    }
+}
+
+impl Player {
+    pub fn new(volume: Volume) -> Self {
+        Player {
+            volume,
+            meters: MeterFeed::new(),
+            recorder: Recorder::new(),
+            incidents: IncidentCapture::new(),
+            last_error: Arc::default(),
+            playback: None,
+        }
+    }
+
+    /// The volume of this player, which is kept across playbacks.
+    pub fn volume(&self) -> &Volume {
+        &self.volume
+    }
+
+    /// The meter readings of this player, which keep coming across playbacks.
+    pub fn meters(&self) -> &MeterFeed {
+        &self.meters
+    }
+
+    /// Records the streams this player plays with `recorder` whenever it is recording.
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// The recorder of this player, which is kept across playbacks.
+    pub fn recorder(&self) -> &Recorder {
+        &self.recorder
+    }
+
+    /// Watches the streams this player plays for incidents with `incidents` whenever it is
+    /// armed.
+    pub fn with_incidents(mut self, incidents: IncidentCapture) -> Self {
+        self.incidents = incidents;
+        self
+    }
+
+    /// The incident capture of this player, which is kept across playbacks.
+    pub fn incidents(&self) -> &IncidentCapture {
+        &self.incidents
+    }
+
+    /// Whether a stream is playing, i.e. was started and neither stopped nor failed since.
+    pub fn is_playing(&self) -> bool {
+        self.playback
+            .as_ref()
+            .map(|playback| !playback.task.is_finished())
+            .unwrap_or(false)
+    }
+
+    /// The descriptor of the playing stream.
+    pub fn descriptor(&self) -> Option<&SessionDescriptor> {
+        self.current().map(|playback| &playback.descriptor)
+    }
+
+    /// How long the playing stream has been playing.
+    pub fn uptime(&self) -> Option<Duration> {
+        self.current().map(|playback| playback.started.elapsed())
+    }
+
+    /// Figures of the playing stream.
+    pub fn stats(&self) -> Option<PlaybackStats> {
+        self.current().map(|playback| playback.monitor.stats())
+    }
+
+    /// Loudness of the playing stream.
+    pub fn loudness(&self) -> Option<Loudness> {
+        self.stats().map(|stats| stats.loudness)
+    }
+
+    /// Starts the integrated loudness and loudness range measurements of the playing stream
+    /// over.
+    pub fn reset_loudness(&self) {
+        if let Some(playback) = self.current() {
+            playback.monitor.reset_loudness();
+        }
+    }
+
+    /// The error that ended the most recent playback, or prevented it from starting.
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error
+            .lock()
+            .ok()
+            .and_then(|last_error| last_error.clone())
+    }
+
+    /// Starts playing `stream` and returns once audio output is running. Fails if the player
+    /// is already playing.
+    pub async fn start(&mut self, stream: Stream, config: PlaybackConfig) -> SdpPlayerResult<()> {
+        if self.is_playing() {
+            return Err(SdpPlayerError::AlreadyPlaying);
+        }
+        self.playback = None;
+        self.set_last_error(None);
+
+        let descriptor = stream.descriptor.clone();
+        let monitor = PlaybackMonitor::with_meters(self.meters.clone())
+            .with_recorder(self.recorder.clone())
+            .with_incidents(self.incidents.clone());
+        let (stop, _) = broadcast::channel(1);
+        let (started_tx, started_rx) = oneshot::channel();
+
+        let last_error = self.last_error.clone();
+        let mut task = spawn(play_and_record_error(
+            play(
+                stream,
+                stop.clone(),
+                config,
+                self.volume.clone(),
+                monitor.clone(),
+                Some(started_tx),
+            ),
+            last_error,
+        ));
+
+        select! {
+            started = started_rx => {
+                if started.is_err() {
+                    // playback ended before output was running, the task tells why
+                    return task.await?;
+                }
+            }
+            result = &mut task => {
+                result??;
+                return Err(SdpPlayerError::OutputFailed);
+            }
+        }
+
+        self.playback = Some(Playback {
+            descriptor,
+            monitor,
+            started: Instant::now(),
+            stop,
+            task,
+        });
+        Ok(())
+    }
+
+    /// Stops playing and waits until receiver and output are shut down. Returns the error that
+    /// ended the playback, if it failed before it was stopped.
+    pub async fn stop(&mut self) -> SdpPlayerResult<()> {
+        match self.playback.take() {
+            Some(playback) => {
+                playback.stop.send(()).ok();
+                playback.task.await?
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Stops the current playback, if any, then opens the stream returned by `open` and starts
+    /// playing it. The stream is only opened once the previous one is shut down, so both may
+    /// use the same sockets. An error of the previous playback is logged, not returned.
+    pub async fn switch(
+        &mut self,
+        open: impl Future<Output = SdpPlayerResult<Stream>>,
+        config: PlaybackConfig,
+    ) -> SdpPlayerResult<()> {
+        if let Err(e) = self.stop().await {
+            log::warn!("Previous playback ended with error: {e}");
+        }
+        let stream = match open.await {
+            Ok(stream) => stream,
+            Err(e) => {
+                self.set_last_error(Some(e.to_string()));
+                return Err(e);
+            }
+        };
+        self.start(stream, config).await
+    }
+
+    /// Waits until the playback ends by itself, which only happens if it fails, and returns
+    /// why it ended. Returns immediately if the player is not playing.
+    pub async fn wait(&mut self) -> SdpPlayerResult<()> {
+        let result = match &mut self.playback {
+            Some(playback) => (&mut playback.task).await?,
+            None => return Ok(()),
+        };
+        self.playback = None;
+        result
+    }
+
+    fn current(&self) -> Option<&Playback> {
+        self.playback
+            .as_ref()
+            .filter(|playback| !playback.task.is_finished())
+    }
+
+    fn set_last_error(&self, error: Option<String>) {
+        if let Ok(mut last_error) = self.last_error.lock() {
+            *last_error = error;
+        }
+    }
+}
+
+async fn play_and_record_error(
+    playback: impl Future<Output = SdpPlayerResult<()>>,
+    last_error: Arc<Mutex<Option<String>>>,
+) -> SdpPlayerResult<()> {
+    let result = playback.await;
+    if let Err(e) = &result {
+        log::error!("Playback failed: {e}");
+        if let Ok(mut last_error) = last_error.lock() {
+            *last_error = Some(e.to_string());
+        }
+    }
+    result
+}
@@ -0,0 +1,784 @@
+use crate::{
+    error::{SdpPlayerError, SdpPlayerResult},
+    rtp::ExtendedTimestamps,
+    stream::RtpPacket,
+    wav::{BextChunk, WavEncoding, WavFormat, WavWriter},
+    BitDepth, SessionDescriptor,
+};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Prefix of recorded files if none is configured.
+pub const DEFAULT_RECORDING_PREFIX: &str = "sdplay";
+/// Longest gap in a stream that is filled with silence; a longer one starts a new file.
+const MAX_CONCEALED_GAP: Duration = Duration::from_secs(1);
+/// How often the header of a file being recorded is brought up to date, so a file stays
+/// readable if recording is interrupted.
+const HEADER_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where and how to record.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordingConfig {
+    /// directory the files are written to, created if missing
+    pub directory: PathBuf,
+    /// start of the file names, followed by the UTC origination time of each file
+    pub prefix: String,
+    /// start a new file once a file holds this much audio
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_duration: Option<Duration>,
+    /// start a new file before a file grows beyond this many bytes
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_file_size: Option<u64>,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        RecordingConfig {
+            directory: PathBuf::from("."),
+            prefix: DEFAULT_RECORDING_PREFIX.to_owned(),
+            max_duration: None,
+            max_file_size: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Object)]
+pub struct RecordingStatus {
+    /// whether received packets are being recorded
+    pub recording: bool,
+    /// the file currently written to
+    pub file: Option<String>,
+    /// all files of the current or most recent recording, in the order they were started
+    pub files: Vec<String>,
+    /// seconds of audio recorded, including concealed gaps
+    pub duration_seconds: f64,
+    /// bytes of audio data recorded
+    pub bytes_written: u64,
+    /// packets that arrived too late to be recorded
+    pub late_packets: u64,
+    /// frames of silence written in place of lost packets
+    pub concealed_frames: u64,
+    /// the error that ended the recording, if any
+    pub error: Option<String>,
+}
+
+/// Records the packets of the streams a player plays bit-exactly into Broadcast Wave Format
+/// files, in the stream's own sample format.
+///
+/// Clones refer to the same recorder, so recording can be started and stopped independently of
+/// the player it is handed to. Recording outlives playbacks: while no stream is played nothing
+/// is written, and each stream played while recording starts a new file.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    state: Arc<Mutex<RecorderState>>,
+}
+
+#[derive(Debug, Default)]
+struct RecorderState {
+    /// the stream currently played
+    stream: Option<SessionDescriptor>,
+    recording: Option<Recording>,
+    /// the final status of the most recent recording
+    last_status: RecordingStatus,
+}
+
+#[derive(Debug)]
+struct Recording {
+    tx: mpsc::Sender<Message>,
+    status: Arc<Mutex<RecordingStatus>>,
+    thread: JoinHandle<()>,
+}
+
+#[derive(Debug)]
+enum Message {
+    Stream(Option<SessionDescriptor>),
+    Packet {
+        arrival: SystemTime,
+        timestamp: u32,
+        payload: Vec<u8>,
+    },
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder::default()
+    }
+
+    /// Starts recording to a new set of files. Fails if already recording or if the prefix
+    /// isn't a plain file name.
+    pub fn start(&self, config: RecordingConfig) -> SdpPlayerResult<()> {
+        if !is_valid_prefix(&config.prefix) {
+            return Err(SdpPlayerError::InvalidRecordingPrefix(config.prefix));
+        }
+
+        let mut state = self.lock();
+        if state
+            .recording
+            .as_ref()
+            .map(|recording| !recording.thread.is_finished())
+            .unwrap_or(false)
+        {
+            return Err(SdpPlayerError::AlreadyRecording);
+        }
+        fs::create_dir_all(&config.directory)?;
+
+        log::info!("Recording to {}", config.directory.to_string_lossy());
+        let (tx, rx) = mpsc::channel();
+        tx.send(Message::Stream(state.stream.clone())).ok();
+        let status = Arc::new(Mutex::new(RecordingStatus {
+            recording: true,
+            ..Default::default()
+        }));
+        let thread_status = status.clone();
+        let thread = thread::spawn(move || {
+            let mut writer = SessionWriter::new(config, thread_status.clone());
+            if let Err(e) = writer.run(rx) {
+                log::error!("Recording failed: {e}");
+                if let Ok(mut status) = thread_status.lock() {
+                    status.error = Some(e.to_string());
+                }
+            }
+            if let Ok(mut status) = thread_status.lock() {
+                status.recording = false;
+                status.file = None;
+            }
+        });
+        state.recording = Some(Recording { tx, status, thread });
+        Ok(())
+    }
+
+    /// Stops recording and returns once the last file is complete. Returns the final status of
+    /// the recording, or `None` if not recording.
+    pub fn stop(&self) -> Option<RecordingStatus> {
+        let recording = self.lock().recording.take()?;
+        drop(recording.tx);
+        if recording.thread.join().is_err() {
+            log::error!("Recording thread panicked");
+        }
+        let status = lock_status(&recording.status).clone();
+        self.lock().last_status = status.clone();
+        log::info!("Recording stopped.");
+        Some(status)
+    }
+
+    /// Whether received packets are being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.status().recording
+    }
+
+    /// The status of the current recording, or the final one of the most recent recording.
+    pub fn status(&self) -> RecordingStatus {
+        let state = self.lock();
+        match &state.recording {
+            Some(recording) => lock_status(&recording.status).clone(),
+            None => state.last_status.clone(),
+        }
+    }
+
+    /// Tells the recorder which stream the packets it is handed belong to, `None` once playback
+    /// ended.
+    pub(crate) fn set_stream(&self, stream: Option<SessionDescriptor>) {
+        let mut state = self.lock();
+        state.stream = stream.clone();
+        if let Some(recording) = &state.recording {
+            recording.tx.send(Message::Stream(stream)).ok();
+        }
+    }
+
+    /// Records `packet` if recording, taking now as its arrival time.
+    pub(crate) fn record(&self, packet: &RtpPacket) {
+        if let Some(recording) = &self.lock().recording {
+            let message = Message::Packet {
+                arrival: SystemTime::now(),
+                timestamp: packet.timestamp,
+                payload: packet.payload.clone(),
+            };
+            recording.tx.send(message).ok();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RecorderState> {
+        // a panic while holding the lock can't leave the state inconsistent
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn lock_status(status: &Mutex<RecordingStatus>) -> MutexGuard<'_, RecordingStatus> {
+    status
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Writes the packets of one recording, splitting them into files whenever the stream changes
+/// or jumps, or a file reaches its size or duration limit.
+struct SessionWriter {
+    config: RecordingConfig,
+    status: Arc<Mutex<RecordingStatus>>,
+    session: Option<Session>,
+    file: Option<RecordingFile>,
+}
+
+/// The stream being recorded and where in it the recording is.
+struct Session {
+    descriptor: SessionDescriptor,
+    format: WavFormat,
+    timestamps: ExtendedTimestamps,
+    /// extended RTP timestamp of the next frame to write and its wall clock time, derived from
+    /// the arrival time of the first packet of an uninterrupted run of packets
+    next: Option<(u64, SystemTime)>,
+}
+
+struct RecordingFile {
+    writer: WavWriter<BufWriter<File>>,
+    path: PathBuf,
+    frames: u64,
+    frames_since_header_update: u64,
+}
+
+impl SessionWriter {
+    fn new(config: RecordingConfig, status: Arc<Mutex<RecordingStatus>>) -> Self {
+        SessionWriter {
+            config,
+            status,
+            session: None,
+            file: None,
+        }
+    }
+
+    fn run(&mut self, rx: mpsc::Receiver<Message>) -> io::Result<()> {
+        while let Ok(message) = rx.recv() {
+            match message {
+                Message::Stream(descriptor) => {
+                    self.close_file()?;
+                    self.session = descriptor.map(Session::new);
+                }
+                Message::Packet {
+                    arrival,
+                    timestamp,
+                    payload,
+                } => self.packet(arrival, timestamp, &payload)?,
+            }
+        }
+        self.close_file()
+    }
+
+    fn packet(&mut self, arrival: SystemTime, timestamp: u32, payload: &[u8]) -> io::Result<()> {
+        let Some(session) = &mut self.session else {
+            return Ok(());
+        };
+        let block_align = session.format.block_align() as usize;
+        let bytes_per_sample = session.format.encoding.bytes_per_sample() as usize;
+        let frames = payload.len() / block_align;
+        if frames == 0 {
+            return Ok(());
+        }
+        let timestamp = session.timestamps.extend(timestamp);
+        let max_gap = (MAX_CONCEALED_GAP.as_secs_f64() * session.format.sample_rate as f64) as u64;
+
+        let gap = match session.next {
+            Some((next, _)) if timestamp < next && next - timestamp <= max_gap => {
+                lock_status(&self.status).late_packets += 1;
+                return Ok(());
+            }
+            Some((next, _)) if timestamp >= next && timestamp - next <= max_gap => timestamp - next,
+            Some((next, _)) => {
+                log::warn!(
+                    "RTP timestamp jumped by {} frames, starting a new file",
+                    timestamp as i64 - next as i64
+                );
+                session.next = Some((timestamp, arrival));
+                self.close_file()?;
+                0
+            }
+            None => {
+                session.next = Some((timestamp, arrival));
+                0
+            }
+        };
+
+        if gap > 0 {
+            log::debug!("Filling gap of {gap} frames in recording with silence");
+            self.write(&vec![0; gap as usize * block_align])?;
+            lock_status(&self.status).concealed_frames += gap;
+        }
+        self.write(&to_little_endian(
+            &payload[..frames * block_align],
+            bytes_per_sample,
+        ))
+    }
+
+    /// Writes whole frames of little endian samples, starting new files as needed.
+    fn write(&mut self, mut data: &[u8]) -> io::Result<()> {
+        let Some(session) = &mut self.session else {
+            return Ok(());
+        };
+        let block_align = session.format.block_align() as u64;
+        let sample_rate = session.format.sample_rate as u64;
+        let header_update_frames =
+            (HEADER_UPDATE_INTERVAL.as_secs_f64() * sample_rate as f64) as u64;
+
+        while !data.is_empty() {
+            let Some((next, next_time)) = session.next else {
+                return Ok(());
+            };
+            let file = match &mut self.file {
+                Some(file) => file,
+                file @ None => {
+                    let created = RecordingFile::create(&self.config, session, next_time)?;
+                    let path = created.path.to_string_lossy().into_owned();
+                    log::info!("Recording to file {path}");
+                    let mut status = lock_status(&self.status);
+                    status.file = Some(path.clone());
+                    status.files.push(path);
+                    file.insert(created)
+                }
+            };
+
+            let mut frames = data.len() as u64 / block_align;
+            if let Some(max_duration) = self.config.max_duration {
+                let max_frames = (max_duration.as_secs_f64() * sample_rate as f64).ceil() as u64;
+                frames = frames.min(max_frames.saturating_sub(file.frames));
+            }
+            if let Some(max_file_size) = self.config.max_file_size {
+                let room = max_file_size.saturating_sub(file.writer.file_size()) / block_align;
+                frames = frames.min(room);
+            }
+            // a file holds at least one frame, however small its limits
+            if file.frames == 0 {
+                frames = frames.max(1);
+            }
+
+            if frames > 0 {
+                let (chunk, rest) = data.split_at((frames * block_align) as usize);
+                file.writer.write_data(chunk)?;
+                file.frames += frames;
+                file.frames_since_header_update += frames;
+                if file.frames_since_header_update >= header_update_frames {
+                    file.writer.update_header()?;
+                    file.frames_since_header_update = 0;
+                }
+                data = rest;
+
+                session.next = Some((
+                    next + frames,
+                    next_time + Duration::from_secs_f64(frames as f64 / sample_rate as f64),
+                ));
+                let mut status = lock_status(&self.status);
+                status.bytes_written += chunk.len() as u64;
+                status.duration_seconds += frames as f64 / sample_rate as f64;
+            }
+
+            if !data.is_empty() {
+                // the file is full
+                finish_file(&mut self.file, &self.status)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn close_file(&mut self) -> io::Result<()> {
+        finish_file(&mut self.file, &self.status)
+    }
+}
+
+fn finish_file(
+    file: &mut Option<RecordingFile>,
+    status: &Mutex<RecordingStatus>,
+) -> io::Result<()> {
+    if let Some(file) = file.take() {
+        lock_status(status).file = None;
+        file.writer.finish()?;
+    }
+    Ok(())
+}
+
+impl Session {
+    fn new(descriptor: SessionDescriptor) -> Self {
+        Session {
+            format: WavFormat {
+                channels: descriptor.channels.max(1),
+                sample_rate: descriptor.sample_rate,
+                encoding: wav_encoding(&descriptor.bit_depth),
+            },
+            descriptor,
+            timestamps: ExtendedTimestamps::default(),
+            next: None,
+        }
+    }
+
+    fn bext(&self, origination: SystemTime) -> BextChunk {
+        let descriptor = &self.descriptor;
+        let time = UtcTime::from(origination);
+        let since_epoch = origination
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let since_midnight = since_epoch % SECONDS_PER_DAY as f64;
+        let mode = match descriptor.channels {
+            1 => "mono".to_owned(),
+            2 => "stereo".to_owned(),
+            channels => format!("{channels}ch"),
+        };
+        BextChunk {
+            description: descriptor.session_name.clone().unwrap_or_else(|| {
+                format!(
+                    "{}:{}",
+                    descriptor.multicast_address, descriptor.multicast_port
+                )
+            }),
+            originator: "sdplay".to_owned(),
+            originator_reference: descriptor
+                .ssrc
+                .map(|ssrc| format!("SSRC {ssrc:08X}"))
+                .unwrap_or_default(),
+            origination_date: time.date(),
+            origination_time: time.time(),
+            time_reference: (since_midnight * descriptor.sample_rate as f64) as u64,
+            coding_history: format!(
+                "A=PCM,F={},W={},M={mode},T=RTP {} {}:{}\r\n",
+                descriptor.sample_rate,
+                descriptor.bit_depth.bits(),
+                descriptor.bit_depth,
+                descriptor.multicast_address,
+                descriptor.multicast_port
+            ),
+        }
+    }
+}
+
+impl RecordingFile {
+    /// Creates a new file named after the prefix and `origination`.
+    fn create(
+        config: &RecordingConfig,
+        session: &Session,
+        origination: SystemTime,
+    ) -> io::Result<Self> {
+        let (file, path) = create_timestamped_file(&config.directory, &config.prefix, origination)?;
+        let writer = WavWriter::with_bext(
+            BufWriter::new(file),
+            session.format,
+            Some(&session.bext(origination)),
+        )?;
+        Ok(RecordingFile {
+            writer,
+            path,
+            frames: 0,
+            frames_since_header_update: 0,
+        })
+    }
+}
+
+/// Creates a new WAV file in `directory` named `<prefix>-<yyyymmdd>T<hhmmss>Z.wav` after `time`,
+/// numbered if a file of that name exists.
+pub(crate) fn create_timestamped_file(
+    directory: &Path,
+    prefix: &str,
+    time: SystemTime,
+) -> io::Result<(File, PathBuf)> {
+    let time = UtcTime::from(time);
+    let name = format!(
+        "{prefix}-{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        time.year, time.month, time.day, time.hour, time.minute, time.second
+    );
+    let mut number = 0;
+    loop {
+        let path = match number {
+            0 => directory.join(format!("{name}.wav")),
+            n => directory.join(format!("{name}-{n}.wav")),
+        };
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((file, path)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => number += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Whether `prefix` can start a file name without leaving the directory it is meant for.
+pub(crate) fn is_valid_prefix(prefix: &str) -> bool {
+    !prefix.is_empty()
+        && prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The WAV encoding that stores samples of `bit_depth` as they are.
+pub(crate) fn wav_encoding(bit_depth: &BitDepth) -> WavEncoding {
+    match bit_depth {
+        BitDepth::L16 => WavEncoding::Pcm16,
+        BitDepth::L24 => WavEncoding::Pcm24,
+        BitDepth::L32 => WavEncoding::Pcm32,
+        BitDepth::FloatingPoint => WavEncoding::Float32,
+    }
+}
+
+/// Converts network byte order samples of `bytes_per_sample` bytes each to little endian, as
+/// WAV files store them.
+pub(crate) fn to_little_endian(data: &[u8], bytes_per_sample: usize) -> Vec<u8> {
+    let mut data = data.to_vec();
+    for sample in data.chunks_exact_mut(bytes_per_sample) {
+        sample.reverse();
+    }
+    data
+}
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// A point in time as UTC calendar date and time of day, to the second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct UtcTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+}
+
+impl UtcTime {
+    /// `yyyy-mm-dd`
+    pub(crate) fn date(&self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+
+    /// `hh:mm:ss`
+    pub(crate) fn time(&self) -> String {
+        format!("{:02}:{:02}:{:02}", self.hour, self.minute, self.second)
+    }
+
+    /// `yyyy-mm-ddThh:mm:ssZ`
+    pub(crate) fn rfc3339(&self) -> String {
+        format!("{}T{}Z", self.date(), self.time())
+    }
+}
+
+impl From<SystemTime> for UtcTime {
+    fn from(time: SystemTime) -> Self {
+        let seconds = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let time_of_day = seconds % SECONDS_PER_DAY;
+
+        // days since 1970-01-01 to civil date, after Howard Hinnant's `civil_from_days`
+        let days = (seconds / SECONDS_PER_DAY) as i64 + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        } as u32;
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        UtcTime {
+            year,
+            month,
+            day,
+            hour: (time_of_day / 3_600) as u32,
+            minute: (time_of_day % 3_600 / 60) as u32,
+            second: (time_of_day % 60) as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{self, temp_dir};
+
+    fn descriptor() -> SessionDescriptor {
+        SessionDescriptor {
+            ssrc: Some(0x1234),
+            session_name: Some("Studio 1".to_owned()),
+            ..testing::descriptor()
+        }
+    }
+
+    /// A packet of `frames` stereo L24 frames whose samples count up from `first`.
+    fn packet(first: u32, frames: usize) -> Vec<u8> {
+        (0..frames as u32 * 2)
+            .flat_map(|i| {
+                let bytes = (first + i).to_be_bytes();
+                [bytes[1], bytes[2], bytes[3]]
+            })
+            .collect()
+    }
+
+    fn data_chunk(bytes: &[u8]) -> &[u8] {
+        let position = bytes.windows(4).position(|w| w == b"data").unwrap();
+        &bytes[position + 8..]
+    }
+
+    #[test]
+    fn utc_time() {
+        let time = UtcTime::from(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        assert_eq!(time.date(), "2023-11-14");
+        assert_eq!(time.time(), "22:13:20");
+        assert_eq!(time.rfc3339(), "2023-11-14T22:13:20Z");
+
+        let time = UtcTime::from(UNIX_EPOCH + Duration::from_secs(951_825_600));
+        assert_eq!(time.date(), "2000-02-29");
+        assert_eq!(time.time(), "12:00:00");
+
+        assert_eq!(UtcTime::from(UNIX_EPOCH).date(), "1970-01-01");
+    }
+
+    #[test]
+    fn converts_samples_to_little_endian() {
+        assert_eq!(
+            to_little_endian(&[1, 2, 3, 4, 5, 6], 3),
+            vec![3, 2, 1, 6, 5, 4]
+        );
+        assert_eq!(to_little_endian(&[1, 2, 3, 4], 2), vec![2, 1, 4, 3]);
+    }
+
+    #[test]
+    fn records_bit_exactly_and_conceals_gaps() {
+        let directory = temp_dir("record");
+        let recorder = Recorder::new();
+        recorder.set_stream(Some(descriptor()));
+        recorder
+            .start(RecordingConfig {
+                directory: directory.clone(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let rtp = |timestamp: u32, payload: Vec<u8>| RtpPacket {
+            sequence_number: 0,
+            timestamp,
+            ssrc: 0x1234,
+            payload_type: 97,
+            payload,
+        };
+        recorder.record(&rtp(u32::MAX - 47, packet(0x010203, 48)));
+        // the next packet is lost, the one after it arrives before a late one
+        recorder.record(&rtp(48, packet(0x100000, 48)));
+        recorder.record(&rtp(0, packet(0x200000, 48)));
+        let status = recorder.stop().unwrap();
+
+        assert!(!status.recording);
+        assert_eq!(status.files.len(), 1);
+        assert_eq!(status.late_packets, 1);
+        assert_eq!(status.concealed_frames, 48);
+        assert_eq!(status.bytes_written, 3 * 48 * 6);
+
+        let bytes = fs::read(&status.files[0]).unwrap();
+        assert_eq!(&bytes[12..16], b"bext");
+        assert_eq!(&bytes[20..28], b"Studio 1");
+        let data = data_chunk(&bytes);
+        assert_eq!(data.len(), 3 * 48 * 6);
+        assert_eq!(&data[..6], &[0x03, 0x02, 0x01, 0x04, 0x02, 0x01]);
+        assert!(data[48 * 6..2 * 48 * 6].iter().all(|&b| b == 0));
+        assert_eq!(&data[2 * 48 * 6..2 * 48 * 6 + 3], &[0x00, 0x00, 0x10]);
+
+        fs::remove_dir_all(directory).ok();
+    }
+
+    #[test]
+    fn rotates_files_by_duration_and_size() {
+        let directory = temp_dir("rotate");
+        let recorder = Recorder::new();
+        recorder
+            .start(RecordingConfig {
+                directory: directory.clone(),
+                prefix: "rotate".to_owned(),
+                max_duration: Some(Duration::from_millis(2)),
+                max_file_size: None,
+            })
+            .unwrap();
+        recorder.set_stream(Some(descriptor()));
+        for i in 0..5 {
+            recorder.record(&RtpPacket {
+                sequence_number: i as u16,
+                timestamp: i * 48,
+                ssrc: 0x1234,
+                payload_type: 97,
+                payload: packet(i * 96, 48),
+            });
+        }
+        let status = recorder.stop().unwrap();
+        // 2 ms files of 96 frames, the last one holding the remaining 48
+        assert_eq!(status.files.len(), 3);
+        let lengths: Vec<usize> = status
+            .files
+            .iter()
+            .map(|file| data_chunk(&fs::read(file).unwrap()).len())
+            .collect();
+        assert_eq!(lengths, vec![96 * 6, 96 * 6, 48 * 6]);
+        assert!(status.files[0].contains("rotate-"));
+        assert_ne!(status.files[0], status.files[1]);
+
+        let limit = fs::metadata(&status.files[0]).unwrap().len() - 6;
+        let recorder = Recorder::new();
+        recorder.set_stream(Some(descriptor()));
+        recorder
+            .start(RecordingConfig {
+                directory: directory.clone(),
+                prefix: "size".to_owned(),
+                max_duration: None,
+                max_file_size: Some(limit),
+            })
+            .unwrap();
+        recorder.record(&RtpPacket {
+            sequence_number: 0,
+            timestamp: 0,
+            ssrc: 0x1234,
+            payload_type: 97,
+            payload: packet(0, 96),
+        });
+        let status = recorder.stop().unwrap();
+        assert_eq!(status.files.len(), 2);
+        for file in &status.files {
+            assert!(fs::metadata(file).unwrap().len() <= limit);
+        }
+
+        fs::remove_dir_all(directory).ok();
+    }
+
+    #[test]
+    fn rejects_invalid_prefix_and_second_start() {
+        let directory = temp_dir("reject");
+        let recorder = Recorder::new();
+        let config = RecordingConfig {
+            directory: directory.clone(),
+            prefix: "../escape".to_owned(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            recorder.start(config.clone()),
+            Err(SdpPlayerError::InvalidRecordingPrefix(_))
+        ));
+        let config = RecordingConfig {
+            prefix: "ok".to_owned(),
+            ..config
+        };
+        recorder.start(config.clone()).unwrap();
+        assert!(matches!(
+            recorder.start(config),
+            Err(SdpPlayerError::AlreadyRecording)
+        ));
+        assert!(recorder.is_recording());
+        assert!(recorder.stop().is_some());
+        assert!(recorder.stop().is_none());
+        assert!(!recorder.is_recording());
+
+        fs::remove_dir_all(directory).ok();
+    }
+}
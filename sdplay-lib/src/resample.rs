@@ -0,0 +1,170 @@
+use std::f64::consts::PI;
+
+/// Number of input frames on each side of the interpolation point taken into account.
+const HALF_TAPS: usize = 16;
+const TAPS: usize = 2 * HALF_TAPS;
+/// Number of precomputed fractional positions of the interpolation kernel.
+const PHASES: usize = 256;
+/// Number of input frames requested from the source at once.
+const CHUNK_FRAMES: usize = 64;
+
+/// Asynchronous band-limited resampler for interleaved audio.
+///
+/// Converts between a nominal input and output sample rate and additionally applies a small,
+/// continuously variable correction factor, which is used to compensate the drift between the
+/// sender's media clock and the clock of the output device. Interpolation uses a Blackman
+/// windowed sinc kernel, low-pass filtered to the lower of both rates.
+#[derive(Debug)]
+pub struct Resampler {
+    channels: usize,
+    ratio: f64,
+    kernel: Vec<f32>,
+    /// interleaved input frames, the first one being the oldest still needed
+    history: Vec<f32>,
+    /// read position in input frames relative to the start of `history`
+    position: f64,
+}
+
+impl Resampler {
+    pub fn new(channels: u16, input_rate: u32, output_rate: u32) -> Self {
+        let channels = channels.max(1) as usize;
+        let ratio = input_rate as f64 / output_rate as f64;
+        Resampler {
+            channels,
+            ratio,
+            kernel: kernel(ratio.max(1.0)),
+            history: vec![0.0; (HALF_TAPS - 1) * channels],
+            position: (HALF_TAPS - 1) as f64,
+        }
+    }
+
+    /// Input frames consumed per output frame, without drift correction.
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    /// Fills `out` with resampled interleaved frames, pulling input from `source` as needed.
+    ///
+    /// `correction` scales the consumption of input frames, values above 1.0 consume the input
+    /// faster than its nominal rate.
+    pub fn process(
+        &mut self,
+        correction: f64,
+        out: &mut [f32],
+        mut source: impl FnMut(&mut [f32]),
+    ) {
+        let channels = self.channels;
+        let step = self.ratio * correction;
+
+        for frame in out.chunks_mut(channels) {
+            let index = self.position.floor() as usize;
+            let needed = (index + HALF_TAPS + 1) * channels;
+            while self.history.len() < needed {
+                let start = self.history.len();
+                self.history.resize(start + CHUNK_FRAMES * channels, 0.0);
+                source(&mut self.history[start..]);
+            }
+
+            let fraction = (self.position - index as f64) * PHASES as f64;
+            let phase = fraction.floor() as usize;
+            let blend = (fraction - phase as f64) as f32;
+            let lower = &self.kernel[phase * TAPS..(phase + 1) * TAPS];
+            let upper = &self.kernel[(phase + 1) * TAPS..(phase + 2) * TAPS];
+
+            let first = (index + 1 - HALF_TAPS) * channels;
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let mut acc = 0.0;
+                for tap in 0..TAPS {
+                    let weight = lower[tap] + (upper[tap] - lower[tap]) * blend;
+                    acc += weight * self.history[first + tap * channels + channel];
+                }
+                *sample = acc;
+            }
+
+            self.position += step;
+
+            let consumed = (self.position.floor() as usize).saturating_sub(HALF_TAPS - 1);
+            if consumed > CHUNK_FRAMES {
+                self.history.drain(..consumed * channels);
+                self.position -= consumed as f64;
+            }
+        }
+    }
+}
+
+/// Precomputes the windowed sinc kernel for `PHASES + 1` fractional positions. Row `p` holds the
+/// weights of the `TAPS` input frames surrounding an interpolation point `p / PHASES` frames
+/// after the center tap.
+fn kernel(ratio: f64) -> Vec<f32> {
+    let cutoff = 1.0 / ratio;
+    let mut kernel = Vec::with_capacity((PHASES + 1) * TAPS);
+    for phase in 0..=PHASES {
+        let fraction = phase as f64 / PHASES as f64;
+        let row = kernel.len();
+        for tap in 0..TAPS {
+            let x = tap as f64 - (HALF_TAPS - 1) as f64 - fraction;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (PI * x * cutoff).sin() / (PI * x * cutoff)
+            };
+            let n = (x + HALF_TAPS as f64) / TAPS as f64;
+            let window = if (0.0..=1.0).contains(&n) {
+                0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos()
+            } else {
+                0.0
+            };
+            kernel.push((cutoff * sinc * window) as f32);
+        }
+        // normalize for unity gain at DC
+        let sum: f32 = kernel[row..].iter().sum();
+        for weight in &mut kernel[row..] {
+            *weight /= sum;
+        }
+    }
+    kernel
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ramp_source() -> impl FnMut(&mut [f32]) {
+        let mut next = 0.0;
+        move |buf: &mut [f32]| {
+            for s in buf {
+                *s = next;
+                next += 1.0;
+            }
+        }
+    }
+
+    #[test]
+    fn passes_through_at_unity_ratio() {
+        let mut resampler = Resampler::new(1, 48000, 48000);
+        let mut out = vec![0.0; 200];
+        resampler.process(1.0, &mut out, ramp_source());
+        for (i, s) in out.iter().enumerate() {
+            assert!((s - i as f32).abs() < 1e-3, "{i}: {s}");
+        }
+    }
+
+    #[test]
+    fn consumes_input_at_ratio() {
+        let mut resampler = Resampler::new(2, 96000, 48000);
+        let mut consumed = 0;
+        let mut out = vec![0.0; 2 * 1000];
+        resampler.process(1.0, &mut out, |buf| consumed += buf.len() / 2);
+        assert!((2000..2000 + 2 * CHUNK_FRAMES + HALF_TAPS).contains(&consumed));
+    }
+
+    #[test]
+    fn keeps_dc_level() {
+        let mut resampler = Resampler::new(1, 48000, 44100);
+        let mut out = vec![0.0; 2000];
+        resampler.process(1.0001, &mut out, |buf| buf.fill(0.5));
+        for s in &out[100..] {
+            assert!((s - 0.5).abs() < 1e-3, "{s}");
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use crate::error::SdpPlayerError;
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Connects one stream channel to one device output channel.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Object)]
+pub struct Route {
+    /// stream channel, starting at 1
+    pub input: u16,
+    /// device output channel, starting at 1
+    pub output: u16,
+    /// gain applied to the stream channel in dB
+    #[serde(default)]
+    #[oai(default)]
+    pub gain_db: f32,
+}
+
+/// Describes which stream channels are played on which device outputs.
+///
+/// Several routes to the same output are summed, so a downmix is expressed by routing all
+/// channels to one output with an appropriate gain. Without any routes, stream channels are
+/// mapped 1:1 to device outputs and surplus channels are dropped.
+///
+/// The textual form, as used on the command line, is a comma separated list of
+/// `input:output[@gain_db]` routes, where either side may name several channels joined by `+`:
+/// `3:1,4:2` plays channels 3 and 4 on a stereo output, `1+2:1@-6` sums channels 1 and 2 to
+/// mono.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Object)]
+pub struct ChannelRouting {
+    #[serde(default)]
+    #[oai(default)]
+    pub routes: Vec<Route>,
+}
+
+impl ChannelRouting {
+    /// Plays the given stream channels on consecutive device outputs.
+    pub fn select(channels: &[u16]) -> Self {
+        ChannelRouting {
+            routes: channels
+                .iter()
+                .zip(1..)
+                .map(|(input, output)| Route {
+                    input: *input,
+                    output,
+                    gain_db: 0.0,
+                })
+                .collect(),
+        }
+    }
+
+    /// Sums the given stream channels to the first device output, attenuated by their count.
+    pub fn mono(channels: &[u16]) -> Self {
+        let gain_db = -20.0 * (channels.len().max(1) as f32).log10();
+        ChannelRouting {
+            routes: channels
+                .iter()
+                .map(|input| Route {
+                    input: *input,
+                    output: 1,
+                    gain_db,
+                })
+                .collect(),
+        }
+    }
+
+    /// The number of device outputs the routes require, `None` for the default 1:1 mapping.
+    pub fn output_channels(&self) -> Option<u16> {
+        self.routes.iter().map(|r| r.output).max()
+    }
+
+    /// Resolves the routing for a stream with `inputs` channels played on a device with
+    /// `outputs` channels. Routes to channels that don't exist on either side are ignored.
+    pub fn matrix(&self, inputs: u16, outputs: u16) -> RoutingMatrix {
+        let connections = if self.routes.is_empty() {
+            (0..inputs.min(outputs) as usize)
+                .map(|channel| (channel, channel, 1.0))
+                .collect()
+        } else {
+            self.routes
+                .iter()
+                .filter(|route| {
+                    let valid = (1..=inputs).contains(&route.input)
+                        && (1..=outputs).contains(&route.output);
+                    if !valid {
+                        log::warn!(
+                            "Ignoring route {}:{}, stream has {inputs} and device {outputs} channels",
+                            route.input,
+                            route.output
+                        );
+                    }
+                    valid
+                })
+                .map(|route| {
+                    (
+                        route.input as usize - 1,
+                        route.output as usize - 1,
+                        10f32.powf(route.gain_db / 20.0),
+                    )
+                })
+                .collect()
+        };
+
+        RoutingMatrix {
+            inputs: inputs.max(1) as usize,
+            outputs: outputs.max(1) as usize,
+            connections,
+        }
+    }
+}
+
+impl FromStr for ChannelRouting {
+    type Err = SdpPlayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || SdpPlayerError::MalformedRouting(s.to_owned());
+        let parse_channels = |channels: &str| {
+            channels
+                .split('+')
+                .map(|c| match c.trim().parse::<u16>() {
+                    Ok(c) if c > 0 => Ok(c),
+                    _ => Err(malformed()),
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        let mut routes = Vec::new();
+        for route in s.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            let (route, gain_db) = match route.split_once('@') {
+                Some((route, gain)) => (route, gain.trim().parse().map_err(|_| malformed())?),
+                None => (route, 0.0),
+            };
+            let (inputs, outputs) = route.split_once(':').ok_or_else(malformed)?;
+            for input in parse_channels(inputs)? {
+                for output in parse_channels(outputs)? {
+                    routes.push(Route {
+                        input,
+                        output,
+                        gain_db,
+                    });
+                }
+            }
+        }
+
+        Ok(ChannelRouting { routes })
+    }
+}
+
+impl fmt::Display for ChannelRouting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, route) in self.routes.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}:{}", route.input, route.output)?;
+            if route.gain_db != 0.0 {
+                write!(f, "@{}", route.gain_db)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A [`ChannelRouting`] resolved for a concrete stream and device channel count.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutingMatrix {
+    inputs: usize,
+    outputs: usize,
+    /// input channel index, output channel index, linear gain
+    connections: Vec<(usize, usize, f32)>,
+}
+
+impl RoutingMatrix {
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    pub fn outputs(&self) -> usize {
+        self.outputs
+    }
+
+    /// Routes the interleaved stream frames in `input` to the interleaved device frames in
+    /// `output`. Both buffers must hold the same number of frames.
+    pub fn apply(&self, input: &[f32], output: &mut [f32]) {
+        output.fill(0.0);
+        for (in_frame, out_frame) in input
+            .chunks_exact(self.inputs)
+            .zip(output.chunks_exact_mut(self.outputs))
+        {
+            for (i, o, gain) in &self.connections {
+                out_frame[*o] += in_frame[*i] * gain;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_routing() {
+        let routing: ChannelRouting = "3:1, 4:2@-6,1+2:3".parse().unwrap();
+        assert_eq!(
+            routing.routes,
+            vec![
+                Route {
+                    input: 3,
+                    output: 1,
+                    gain_db: 0.0
+                },
+                Route {
+                    input: 4,
+                    output: 2,
+                    gain_db: -6.0
+                },
+                Route {
+                    input: 1,
+                    output: 3,
+                    gain_db: 0.0
+                },
+                Route {
+                    input: 2,
+                    output: 3,
+                    gain_db: 0.0
+                },
+            ]
+        );
+        assert_eq!(routing.output_channels(), Some(3));
+        assert_eq!(routing.to_string(), "3:1,4:2@-6,1:3,2:3");
+
+        assert!("0:1".parse::<ChannelRouting>().is_err());
+        assert!("1-2".parse::<ChannelRouting>().is_err());
+        assert!("1:2@loud".parse::<ChannelRouting>().is_err());
+        assert_eq!(
+            "".parse::<ChannelRouting>().unwrap(),
+            ChannelRouting::default()
+        );
+    }
+
+    #[test]
+    fn default_routing_maps_channels_one_to_one() {
+        let matrix = ChannelRouting::default().matrix(4, 2);
+        let mut output = vec![0.0; 4];
+        matrix.apply(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0], &mut output);
+        assert_eq!(output, vec![1.0, 2.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn routes_select_sum_and_scale_channels() {
+        let matrix = ChannelRouting::mono(&[1, 2]).matrix(2, 1);
+        let mut output = vec![0.0; 2];
+        matrix.apply(&[0.5, 0.5, 1.0, 0.0], &mut output);
+        assert!((output[0] - 0.5).abs() < 1e-6);
+        assert!((output[1] - 0.5).abs() < 1e-6);
+
+        let matrix = "2:2,9:1".parse::<ChannelRouting>().unwrap().matrix(2, 2);
+        let mut output = vec![0.0; 2];
+        matrix.apply(&[0.25, 0.75], &mut output);
+        assert_eq!(output, vec![0.0, 0.75]);
+    }
+}
@@ -0,0 +1,386 @@
+use crate::{
+    error::{SdpPlayerError, SdpPlayerResult},
+    rtp::RtpStats,
+};
+use std::time::{Duration, Instant};
+
+const VERSION: u8 = 2;
+const SENDER_REPORT: u8 = 200;
+const RECEIVER_REPORT: u8 = 201;
+const SOURCE_DESCRIPTION: u8 = 202;
+const GOODBYE: u8 = 203;
+const CNAME: u8 = 1;
+
+const HEADER_SIZE: usize = 4;
+const SENDER_INFO_SIZE: usize = 20;
+const REPORT_BLOCK_SIZE: usize = 24;
+
+/// Interval between receiver reports, the minimum RFC 3550 allows.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The sender information of an RTCP sender report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SenderReport {
+    pub ssrc: u32,
+    /// wallclock time the report was sent, as 64 bit NTP timestamp
+    pub ntp_timestamp: u64,
+    /// the same time in RTP timestamp units
+    pub rtp_timestamp: u32,
+    pub packet_count: u32,
+    pub octet_count: u32,
+}
+
+/// Reception statistics of one source, as sent in receiver reports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportBlock {
+    pub ssrc: u32,
+    /// fraction of packets lost since the previous report, in 1/256
+    pub fraction_lost: u8,
+    /// cumulative number of packets lost, 24 bit signed
+    pub cumulative_lost: i32,
+    pub highest_sequence_number: u32,
+    /// interarrival jitter in RTP timestamp units
+    pub jitter: u32,
+    /// middle 32 bits of the NTP timestamp of the last sender report received from the source
+    pub last_sender_report: u32,
+    /// time since the last sender report was received, in 1/65536 seconds
+    pub delay_since_last_sender_report: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RtcpPacket {
+    SenderReport(SenderReport),
+    ReceiverReport {
+        ssrc: u32,
+        reports: Vec<ReportBlock>,
+    },
+    Goodbye {
+        ssrcs: Vec<u32>,
+    },
+    /// any other packet type, e.g. source descriptions
+    Other(u8),
+}
+
+impl RtcpPacket {
+    /// Parses all packets of a compound RTCP packet.
+    pub fn parse_compound(mut buf: &[u8]) -> SdpPlayerResult<Vec<RtcpPacket>> {
+        let mut packets = Vec::new();
+        while !buf.is_empty() {
+            if buf.len() < HEADER_SIZE || buf[0] >> 6 != VERSION {
+                return Err(SdpPlayerError::MalformedRtcpPacket);
+            }
+            let count = (buf[0] & 0x1f) as usize;
+            let packet_type = buf[1];
+            let length = (u16::from_be_bytes([buf[2], buf[3]]) as usize + 1) * 4;
+            if buf.len() < length {
+                return Err(SdpPlayerError::MalformedRtcpPacket);
+            }
+            let body = &buf[HEADER_SIZE..length];
+
+            packets.push(match packet_type {
+                SENDER_REPORT => {
+                    if body.len() < 4 + SENDER_INFO_SIZE {
+                        return Err(SdpPlayerError::MalformedRtcpPacket);
+                    }
+                    RtcpPacket::SenderReport(SenderReport {
+                        ssrc: read_u32(body, 0),
+                        ntp_timestamp: (read_u32(body, 4) as u64) << 32 | read_u32(body, 8) as u64,
+                        rtp_timestamp: read_u32(body, 12),
+                        packet_count: read_u32(body, 16),
+                        octet_count: read_u32(body, 20),
+                    })
+                }
+                RECEIVER_REPORT => {
+                    if body.len() < 4 + count * REPORT_BLOCK_SIZE {
+                        return Err(SdpPlayerError::MalformedRtcpPacket);
+                    }
+                    let reports = body[4..]
+                        .chunks_exact(REPORT_BLOCK_SIZE)
+                        .take(count)
+                        .map(parse_report_block)
+                        .collect();
+                    RtcpPacket::ReceiverReport {
+                        ssrc: read_u32(body, 0),
+                        reports,
+                    }
+                }
+                GOODBYE => {
+                    if body.len() < count * 4 {
+                        return Err(SdpPlayerError::MalformedRtcpPacket);
+                    }
+                    RtcpPacket::Goodbye {
+                        ssrcs: (0..count).map(|i| read_u32(body, i * 4)).collect(),
+                    }
+                }
+                packet_type => RtcpPacket::Other(packet_type),
+            });
+
+            buf = &buf[length..];
+        }
+        Ok(packets)
+    }
+}
+
+/// A compound packet of a receiver report with the given report blocks and a source
+/// description with the receiver's canonical name.
+pub fn receiver_report(ssrc: u32, reports: &[ReportBlock], cname: &str) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_receiver_report(&mut buf, ssrc, reports);
+    write_source_description(&mut buf, ssrc, cname);
+    buf
+}
+
+/// A compound packet announcing that the receiver leaves the session.
+pub fn goodbye(ssrc: u32, cname: &str) -> Vec<u8> {
+    let mut buf = Vec::new();
+    // compound packets always start with a report
+    write_receiver_report(&mut buf, ssrc, &[]);
+    write_source_description(&mut buf, ssrc, cname);
+    write_header(&mut buf, 1, GOODBYE, 1);
+    buf.extend_from_slice(&ssrc.to_be_bytes());
+    buf
+}
+
+fn write_header(buf: &mut Vec<u8>, count: usize, packet_type: u8, words: usize) {
+    buf.push(VERSION << 6 | count as u8);
+    buf.push(packet_type);
+    buf.extend_from_slice(&(words as u16).to_be_bytes());
+}
+
+fn write_receiver_report(buf: &mut Vec<u8>, ssrc: u32, reports: &[ReportBlock]) {
+    // the report count has 5 bits
+    let reports = &reports[..reports.len().min(31)];
+    write_header(
+        buf,
+        reports.len(),
+        RECEIVER_REPORT,
+        (4 + reports.len() * REPORT_BLOCK_SIZE) / 4,
+    );
+    buf.extend_from_slice(&ssrc.to_be_bytes());
+    for report in reports {
+        let cumulative_lost = report.cumulative_lost.clamp(-0x80_0000, 0x7f_ffff) as u32;
+        buf.extend_from_slice(&report.ssrc.to_be_bytes());
+        buf.push(report.fraction_lost);
+        buf.extend_from_slice(&cumulative_lost.to_be_bytes()[1..]);
+        buf.extend_from_slice(&report.highest_sequence_number.to_be_bytes());
+        buf.extend_from_slice(&report.jitter.to_be_bytes());
+        buf.extend_from_slice(&report.last_sender_report.to_be_bytes());
+        buf.extend_from_slice(&report.delay_since_last_sender_report.to_be_bytes());
+    }
+}
+
+fn write_source_description(buf: &mut Vec<u8>, ssrc: u32, cname: &str) {
+    let cname = &cname.as_bytes()[..cname.len().min(255)];
+    // ssrc, item type and length, the name and at least one terminating null octet
+    let chunk_size = (4 + 2 + cname.len() + 1).next_multiple_of(4);
+    write_header(buf, 1, SOURCE_DESCRIPTION, chunk_size / 4);
+    let start = buf.len();
+    buf.extend_from_slice(&ssrc.to_be_bytes());
+    buf.push(CNAME);
+    buf.push(cname.len() as u8);
+    buf.extend_from_slice(cname);
+    buf.resize(start + chunk_size, 0);
+}
+
+fn parse_report_block(block: &[u8]) -> ReportBlock {
+    // sign extend the 24 bit count
+    let cumulative_lost = i32::from_be_bytes([block[5], block[6], block[7], 0]) >> 8;
+    ReportBlock {
+        ssrc: read_u32(block, 0),
+        fraction_lost: block[4],
+        cumulative_lost,
+        highest_sequence_number: read_u32(block, 8),
+        jitter: read_u32(block, 12),
+        last_sender_report: read_u32(block, 16),
+        delay_since_last_sender_report: read_u32(block, 20),
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+/// Turns the reception statistics of a stream into the report blocks of consecutive receiver
+/// reports.
+#[derive(Debug)]
+pub struct ReceiverReporter {
+    clock_rate: u32,
+    source: Option<u32>,
+    expected_prior: u64,
+    received_prior: u64,
+    /// source, middle bits of the NTP timestamp and arrival of the last sender report
+    last_sender_report: Option<(u32, u32, Instant)>,
+}
+
+impl ReceiverReporter {
+    pub fn new(clock_rate: u32) -> Self {
+        ReceiverReporter {
+            clock_rate,
+            source: None,
+            expected_prior: 0,
+            received_prior: 0,
+            last_sender_report: None,
+        }
+    }
+
+    pub fn sender_report(&mut self, report: &SenderReport, arrival: Instant) {
+        let middle = (report.ntp_timestamp >> 16) as u32;
+        self.last_sender_report = Some((report.ssrc, middle, arrival));
+    }
+
+    /// The report block for `stats`, or `None` if nothing was received yet.
+    pub fn report_block(&mut self, stats: &RtpStats, now: Instant) -> Option<ReportBlock> {
+        let ssrc = stats.ssrc?;
+        if self.source != Some(ssrc) || stats.packets_expected < self.expected_prior {
+            // a new sender, or the sender restarted
+            self.source = Some(ssrc);
+            self.expected_prior = 0;
+            self.received_prior = 0;
+        }
+
+        let expected_interval = stats.packets_expected - self.expected_prior;
+        let received_interval = stats.packets_received.saturating_sub(self.received_prior);
+        let lost_interval = expected_interval as i64 - received_interval as i64;
+        let fraction_lost = if expected_interval == 0 || lost_interval <= 0 {
+            0
+        } else {
+            ((lost_interval << 8) / expected_interval as i64).min(255) as u8
+        };
+        self.expected_prior = stats.packets_expected;
+        self.received_prior = stats.packets_received;
+
+        let (last_sender_report, delay_since_last_sender_report) = match self.last_sender_report {
+            Some((source, middle, arrival)) if source == ssrc => {
+                let delay = now.saturating_duration_since(arrival).as_secs_f64();
+                (middle, (delay * 65_536.0) as u32)
+            }
+            _ => (0, 0),
+        };
+
+        Some(ReportBlock {
+            ssrc,
+            fraction_lost,
+            cumulative_lost: stats.packets_lost.clamp(-0x80_0000, 0x7f_ffff) as i32,
+            highest_sequence_number: stats.highest_sequence_number as u32,
+            jitter: (stats.jitter_ms * self.clock_rate as f64 / 1_000.0) as u32,
+            last_sender_report,
+            delay_since_last_sender_report,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn receiver_report_round_trip() {
+        let block = ReportBlock {
+            ssrc: 0x1234_5678,
+            fraction_lost: 12,
+            cumulative_lost: -3,
+            highest_sequence_number: 0x0001_0005,
+            jitter: 48,
+            last_sender_report: 0xdead_beef,
+            delay_since_last_sender_report: 65_536,
+        };
+        let buf = receiver_report(42, std::slice::from_ref(&block), "sdplay@10.0.0.1");
+        assert_eq!(buf.len() % 4, 0);
+
+        let packets = RtcpPacket::parse_compound(&buf).unwrap();
+        assert_eq!(
+            packets,
+            vec![
+                RtcpPacket::ReceiverReport {
+                    ssrc: 42,
+                    reports: vec![block]
+                },
+                RtcpPacket::Other(SOURCE_DESCRIPTION)
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_sender_report() {
+        let buf = [
+            0x80, 200, 0x00, 0x06, // header, no report blocks
+            0x00, 0x00, 0x00, 0x07, // ssrc
+            0xe8, 0x00, 0x00, 0x01, 0x80, 0x00, 0x00, 0x00, // ntp timestamp
+            0x00, 0x00, 0x10, 0x00, // rtp timestamp
+            0x00, 0x00, 0x00, 0x64, // packet count
+            0x00, 0x00, 0x27, 0x10, // octet count
+        ];
+        assert_eq!(
+            RtcpPacket::parse_compound(&buf).unwrap(),
+            vec![RtcpPacket::SenderReport(SenderReport {
+                ssrc: 7,
+                ntp_timestamp: 0xe800_0001_8000_0000,
+                rtp_timestamp: 4096,
+                packet_count: 100,
+                octet_count: 10_000,
+            })]
+        );
+        assert!(RtcpPacket::parse_compound(&buf[..20]).is_err());
+    }
+
+    #[test]
+    fn goodbye_is_a_compound_packet() {
+        let packets = RtcpPacket::parse_compound(&goodbye(42, "sdplay")).unwrap();
+        assert_eq!(
+            packets,
+            vec![
+                RtcpPacket::ReceiverReport {
+                    ssrc: 42,
+                    reports: vec![]
+                },
+                RtcpPacket::Other(SOURCE_DESCRIPTION),
+                RtcpPacket::Goodbye { ssrcs: vec![42] }
+            ]
+        );
+    }
+
+    #[test]
+    fn report_blocks_cover_the_interval_since_the_previous_report() {
+        let mut reporter = ReceiverReporter::new(48_000);
+        let now = Instant::now();
+        let mut stats = RtpStats {
+            ssrc: Some(7),
+            packets_received: 90,
+            packets_expected: 100,
+            packets_lost: 10,
+            highest_sequence_number: 65_635,
+            jitter_ms: 0.5,
+            ..Default::default()
+        };
+        let block = reporter.report_block(&stats, now).unwrap();
+        assert_eq!(block.fraction_lost, 25);
+        assert_eq!(block.cumulative_lost, 10);
+        assert_eq!(block.highest_sequence_number, 65_635);
+        assert_eq!(block.jitter, 24);
+        assert_eq!(block.last_sender_report, 0);
+
+        reporter.sender_report(
+            &SenderReport {
+                ssrc: 7,
+                ntp_timestamp: 0x0001_0002_0003_0004,
+                rtp_timestamp: 0,
+                packet_count: 0,
+                octet_count: 0,
+            },
+            now,
+        );
+        stats.packets_received = 190;
+        stats.packets_expected = 200;
+        let block = reporter
+            .report_block(&stats, now + Duration::from_millis(500))
+            .unwrap();
+        assert_eq!(block.fraction_lost, 0);
+        assert_eq!(block.last_sender_report, 0x0002_0003);
+        assert_eq!(block.delay_since_last_sender_report, 32_768);
+    }
+}
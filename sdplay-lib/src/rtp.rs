@@ -0,0 +1,663 @@
+use crate::stream::RtpPacket;
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Largest forward jump in sequence numbers that is still considered the same stream.
+const MAX_DROPOUT: u16 = 3000;
+/// Largest backward jump in sequence numbers that is considered a late packet.
+const MAX_MISORDER: u16 = 100;
+const SEQUENCE_NUMBER_MOD: u64 = 1 << 16;
+/// Number of sequence numbers remembered to tell duplicates from late packets.
+const DUPLICATE_WINDOW: usize = 1024;
+/// Interval the fraction of lost packets is computed over.
+const FRACTION_LOST_INTERVAL: Duration = Duration::from_secs(1);
+/// Time after which the sender a stream locked onto is considered gone, so that another one
+/// may take over.
+const SSRC_LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Offset added to the first timestamp so that packets older than it can still be unwrapped
+/// without underflowing.
+const EXTENDED_TIMESTAMP_OFFSET: u64 = 1 << 32;
+
+/// How a received packet relates to the packets of its sender received before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reception {
+    /// the first packet of a sender, or the first one after it restarted its sequence numbers
+    Start,
+    /// a packet with a higher sequence number than all before it, `lost` sequence numbers were
+    /// skipped to get there
+    InOrder { lost: u64 },
+    /// a packet that was skipped before and arrived after all
+    Late,
+    /// a packet that was received before
+    Duplicate,
+    /// a packet whose sequence number jumped too far to belong to the stream, which is ignored
+    /// unless the next packet confirms the jump
+    Discarded,
+    /// a packet of another payload type or from another sender than the one played, which is
+    /// not part of the stream at all
+    Rejected,
+}
+
+/// Extends 32 bit RTP timestamps to 64 bits, so that they keep increasing when they wrap
+/// around. Each timestamp is placed within 2^31 of the previous one, which keeps reordered
+/// packets from before a wraparound in place.
+#[derive(Debug, Clone, Default)]
+pub struct ExtendedTimestamps {
+    last: Option<u64>,
+}
+
+impl ExtendedTimestamps {
+    pub fn extend(&mut self, timestamp: u32) -> u64 {
+        let extended = match self.last {
+            Some(last) => {
+                let diff = timestamp.wrapping_sub(last as u32) as i32;
+                (last as i64 + diff as i64) as u64
+            }
+            None => EXTENDED_TIMESTAMP_OFFSET + timestamp as u64,
+        };
+        self.last = Some(extended);
+        extended
+    }
+}
+
+/// A snapshot of the reception statistics of an RTP stream, as defined by RFC 3550.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Object)]
+pub struct RtpStats {
+    /// SSRC of the sender, once a packet was received
+    pub ssrc: Option<u32>,
+    /// packets received from the current sender, including duplicates
+    pub packets_received: u64,
+    /// packets the current sender sent according to the sequence numbers received so far
+    pub packets_expected: u64,
+    /// highest sequence number received, extended by the number of wraparounds times 65536
+    pub highest_sequence_number: u64,
+    /// expected minus received packets; negative if more duplicates than losses were received
+    pub packets_lost: i64,
+    /// fraction of the packets expected during the last second that were not received
+    pub fraction_lost: f32,
+    /// interarrival jitter in milliseconds
+    pub jitter_ms: f64,
+    /// packets received more than once
+    pub duplicates: u64,
+    /// packets received after a packet with a higher sequence number
+    pub out_of_order: u64,
+    /// number of times the sender's SSRC changed
+    pub ssrc_changes: u64,
+    /// packets with a payload size other than the one expected from the stream's format
+    pub payload_size_anomalies: u64,
+    /// payload size of the last packet in bytes
+    pub payload_size: Option<u32>,
+    /// packets dropped because their payload type is not one of the stream's
+    pub rejected_payload_type: u64,
+    /// packets dropped because they came from another sender than the one played
+    pub rejected_ssrc: u64,
+    /// datagrams dropped because they are no RTP packets
+    pub rejected_malformed: u64,
+}
+
+/// Keeps the reception statistics of an RTP stream, following the algorithms of RFC 3550,
+/// Appendix A. A change of SSRC starts the sequence number and loss statistics over.
+///
+/// Only one sender is tracked at a time: the selected SSRC if there is one, otherwise the
+/// first one heard, until it was silent for [`SSRC_LOCK_TIMEOUT`]. Packets of other senders
+/// or payload types are rejected.
+#[derive(Debug)]
+pub struct RtpStatistics {
+    clock_rate: u32,
+    expected_payload_size: Option<usize>,
+    payload_types: Vec<u8>,
+    selected_ssrc: Option<u32>,
+    stats: RtpStats,
+
+    max_sequence_number: u16,
+    /// sequence number cycles, shifted by 16 bits
+    cycles: u64,
+    base_sequence_number: u64,
+    /// sequence number expected to follow a large jump, confirming the sender restarted
+    bad_sequence_number: Option<u16>,
+    seen: Vec<Option<u64>>,
+
+    interval_start: Option<Instant>,
+    expected_prior: u64,
+    received_prior: u64,
+
+    timestamps: ExtendedTimestamps,
+    /// arrival time and extended RTP timestamp of the previous packet
+    previous: Option<(Instant, u64)>,
+    /// arrival time of the last packet of the tracked sender
+    last_arrival: Option<Instant>,
+    /// interarrival jitter in RTP timestamp units
+    jitter: f64,
+}
+
+impl RtpStatistics {
+    /// Creates statistics for a stream whose RTP timestamps count at `clock_rate` and whose
+    /// packets are expected to carry `expected_payload_size` bytes, if known.
+    pub fn new(clock_rate: u32, expected_payload_size: Option<usize>) -> Self {
+        RtpStatistics {
+            clock_rate: clock_rate.max(1),
+            expected_payload_size,
+            payload_types: Vec::new(),
+            selected_ssrc: None,
+            stats: RtpStats::default(),
+            max_sequence_number: 0,
+            cycles: 0,
+            base_sequence_number: 0,
+            bad_sequence_number: None,
+            seen: vec![None; DUPLICATE_WINDOW],
+            interval_start: None,
+            expected_prior: 0,
+            received_prior: 0,
+            timestamps: ExtendedTimestamps::default(),
+            previous: None,
+            last_arrival: None,
+            jitter: 0.0,
+        }
+    }
+
+    /// Rejects packets of other payload types than `payload_types`, unless there are none, and,
+    /// if given, from other senders than `ssrc`.
+    pub fn filtered(mut self, payload_types: Vec<u8>, ssrc: Option<u32>) -> Self {
+        self.payload_types = payload_types;
+        self.selected_ssrc = ssrc;
+        self
+    }
+
+    pub fn stats(&self) -> &RtpStats {
+        &self.stats
+    }
+
+    /// Accounts for a datagram that could not be parsed as an RTP packet.
+    pub fn reject_malformed(&mut self) {
+        self.stats.rejected_malformed += 1;
+    }
+
+    /// Accounts for `packet`, which arrived at `arrival`, and tells how it fits in with the
+    /// packets before it.
+    pub fn track(&mut self, packet: &RtpPacket, arrival: Instant) -> Reception {
+        if self.rejects(packet, arrival) {
+            return Reception::Rejected;
+        }
+        self.last_arrival = Some(arrival);
+
+        let reception = if self.stats.ssrc != Some(packet.ssrc) {
+            if let Some(ssrc) = self.stats.ssrc {
+                log::warn!("RTP sender changed from SSRC {ssrc} to {}", packet.ssrc);
+                self.stats.ssrc_changes += 1;
+            }
+            self.stats.ssrc = Some(packet.ssrc);
+            self.start_over(packet.sequence_number);
+            self.jitter = 0.0;
+            Reception::Start
+        } else {
+            self.update_sequence_number(packet.sequence_number)
+        };
+        match reception {
+            Reception::Start => {
+                self.timestamps = ExtendedTimestamps::default();
+                self.previous = None;
+            }
+            Reception::Discarded => {
+                // a sender restart is only accepted once the next packet confirms it
+                self.previous = None;
+                return reception;
+            }
+            _ => {}
+        }
+
+        let payload_size = packet.payload.len();
+        self.stats.payload_size = Some(payload_size as u32);
+        if self
+            .expected_payload_size
+            .is_some_and(|expected| expected != payload_size)
+        {
+            self.stats.payload_size_anomalies += 1;
+        }
+
+        let timestamp = self.timestamps.extend(packet.timestamp);
+        self.update_jitter(timestamp, arrival);
+        self.update_fraction_lost(arrival);
+
+        self.stats.highest_sequence_number = self.extended_max_sequence_number();
+        self.stats.packets_expected =
+            self.extended_max_sequence_number() - self.base_sequence_number + 1;
+        self.stats.packets_lost =
+            self.stats.packets_expected as i64 - self.stats.packets_received as i64;
+        reception
+    }
+
+    fn rejects(&mut self, packet: &RtpPacket, arrival: Instant) -> bool {
+        if !self.payload_types.is_empty() && !self.payload_types.contains(&packet.payload_type) {
+            self.stats.rejected_payload_type += 1;
+            if self.stats.rejected_payload_type == 1 {
+                log::warn!(
+                    "Ignoring RTP packets of payload type {}, the stream has payload types {:?}",
+                    packet.payload_type,
+                    self.payload_types
+                );
+            }
+            return true;
+        }
+
+        let played = match self.selected_ssrc {
+            Some(ssrc) => Some(ssrc),
+            None => self.stats.ssrc.filter(|_| {
+                self.last_arrival
+                    .is_some_and(|last| arrival.saturating_duration_since(last) < SSRC_LOCK_TIMEOUT)
+            }),
+        };
+        match played {
+            Some(ssrc) if ssrc != packet.ssrc => {
+                self.stats.rejected_ssrc += 1;
+                if self.stats.rejected_ssrc == 1 {
+                    log::warn!(
+                        "Ignoring RTP packets from SSRC {}, playing SSRC {ssrc}",
+                        packet.ssrc
+                    );
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn extended_max_sequence_number(&self) -> u64 {
+        self.cycles + self.max_sequence_number as u64
+    }
+
+    fn start_over(&mut self, sequence_number: u16) {
+        self.base_sequence_number = sequence_number as u64;
+        self.max_sequence_number = sequence_number;
+        self.cycles = 0;
+        self.bad_sequence_number = None;
+        self.seen.fill(None);
+        self.stats.packets_received = 0;
+        self.expected_prior = 0;
+        self.received_prior = 0;
+        self.interval_start = None;
+        self.mark_seen(sequence_number as u64);
+        self.stats.packets_received += 1;
+    }
+
+    fn update_sequence_number(&mut self, sequence_number: u16) -> Reception {
+        let delta = sequence_number.wrapping_sub(self.max_sequence_number);
+        let highest = self.extended_max_sequence_number();
+
+        let extended = if delta < MAX_DROPOUT {
+            if sequence_number < self.max_sequence_number {
+                self.cycles += SEQUENCE_NUMBER_MOD;
+            }
+            self.max_sequence_number = sequence_number;
+            self.extended_max_sequence_number()
+        } else if delta as u64 <= SEQUENCE_NUMBER_MOD - MAX_MISORDER as u64 {
+            if self.bad_sequence_number == Some(sequence_number) {
+                log::warn!("RTP sequence number jumped to {sequence_number}, sender restarted");
+                self.start_over(sequence_number);
+                return Reception::Start;
+            }
+            self.bad_sequence_number = Some(sequence_number.wrapping_add(1));
+            return Reception::Discarded;
+        } else {
+            // late packet, possibly from before the last wraparound
+            let back = SEQUENCE_NUMBER_MOD - delta as u64;
+            match highest.checked_sub(back) {
+                Some(extended) if extended >= self.base_sequence_number => extended,
+                _ => return Reception::Discarded,
+            }
+        };
+
+        let reception = if self.seen[extended as usize % DUPLICATE_WINDOW] == Some(extended) {
+            self.stats.duplicates += 1;
+            Reception::Duplicate
+        } else if extended < highest {
+            self.stats.out_of_order += 1;
+            Reception::Late
+        } else {
+            Reception::InOrder {
+                lost: extended.saturating_sub(highest + 1),
+            }
+        };
+        self.mark_seen(extended);
+        self.stats.packets_received += 1;
+        reception
+    }
+
+    fn mark_seen(&mut self, extended: u64) {
+        self.seen[extended as usize % DUPLICATE_WINDOW] = Some(extended);
+    }
+
+    fn update_jitter(&mut self, timestamp: u64, arrival: Instant) {
+        if let Some((previous_arrival, previous_timestamp)) = self.previous {
+            let arrival_delta = arrival
+                .saturating_duration_since(previous_arrival)
+                .as_secs_f64()
+                * self.clock_rate as f64;
+            let timestamp_delta = (timestamp as i64 - previous_timestamp as i64) as f64;
+            let d = arrival_delta - timestamp_delta;
+            self.jitter += (d.abs() - self.jitter) / 16.0;
+            self.stats.jitter_ms = self.jitter * 1_000.0 / self.clock_rate as f64;
+        }
+        self.previous = Some((arrival, timestamp));
+    }
+
+    fn update_fraction_lost(&mut self, arrival: Instant) {
+        let Some(interval_start) = self.interval_start else {
+            self.interval_start = Some(arrival);
+            return;
+        };
+        if arrival.saturating_duration_since(interval_start) < FRACTION_LOST_INTERVAL {
+            return;
+        }
+
+        let expected = self.extended_max_sequence_number() - self.base_sequence_number + 1;
+        let expected_interval = expected - self.expected_prior;
+        let received_interval = self.stats.packets_received - self.received_prior;
+        let lost_interval = expected_interval as i64 - received_interval as i64;
+        self.stats.fraction_lost = if expected_interval == 0 || lost_interval <= 0 {
+            0.0
+        } else {
+            lost_interval as f32 / expected_interval as f32
+        };
+
+        self.expected_prior = expected;
+        self.received_prior = self.stats.packets_received;
+        self.interval_start = Some(arrival);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn packet(sequence_number: u16, timestamp: u32) -> RtpPacket {
+        RtpPacket {
+            sequence_number,
+            timestamp,
+            ssrc: 1,
+            payload_type: 98,
+            payload: vec![0; 288],
+        }
+    }
+
+    /// Feeds packets of 48 samples at 48 kHz, arriving exactly on time.
+    fn track(
+        statistics: &mut RtpStatistics,
+        start: Instant,
+        sequence_numbers: &[u16],
+    ) -> Vec<Reception> {
+        sequence_numbers
+            .iter()
+            .map(|sequence_number| {
+                let index = sequence_number.wrapping_sub(sequence_numbers[0]) as u32;
+                let arrival = start + Duration::from_millis(index as u64);
+                statistics.track(&packet(*sequence_number, index * 48), arrival)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counts_loss_duplicates_and_reordering() {
+        let mut statistics = RtpStatistics::new(48_000, Some(288));
+        track(
+            &mut statistics,
+            Instant::now(),
+            &[65530, 65531, 65533, 65532, 65533, 65535, 0, 1, 2],
+        );
+
+        let stats = statistics.stats();
+        // 65530..=2 with 65534 missing and 65533 twice
+        assert_eq!(stats.packets_expected, 9);
+        assert_eq!(stats.packets_received, 9);
+        assert_eq!(stats.packets_lost, 0);
+        assert_eq!(stats.duplicates, 1);
+        assert_eq!(stats.out_of_order, 1);
+        assert_eq!(stats.payload_size_anomalies, 0);
+    }
+
+    #[test]
+    fn on_time_packets_have_no_jitter() {
+        let mut statistics = RtpStatistics::new(48_000, Some(288));
+        let sequence_numbers: Vec<u16> = (0..100).collect();
+        track(&mut statistics, Instant::now(), &sequence_numbers);
+        assert!(statistics.stats().jitter_ms < 0.001);
+
+        // every other packet arrives a millisecond late
+        let start = Instant::now();
+        for i in 100..300u16 {
+            let late = if i % 2 == 0 { 1 } else { 0 };
+            let arrival = start + Duration::from_millis((i + late) as u64);
+            statistics.track(&packet(i, i as u32 * 48), arrival);
+        }
+        assert!((statistics.stats().jitter_ms - 1.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn ssrc_change_starts_over() {
+        let mut statistics = RtpStatistics::new(48_000, Some(288));
+        let start = Instant::now();
+        track(&mut statistics, start, &[10, 11, 14]);
+        assert_eq!(statistics.stats().packets_lost, 2);
+
+        // the first sender fell silent
+        let mut other = packet(5000, 0);
+        other.ssrc = 2;
+        other.payload.truncate(100);
+        statistics.track(&other, start + SSRC_LOCK_TIMEOUT * 2);
+
+        let stats = statistics.stats();
+        assert_eq!(stats.ssrc, Some(2));
+        assert_eq!(stats.ssrc_changes, 1);
+        assert_eq!(stats.packets_received, 1);
+        assert_eq!(stats.packets_lost, 0);
+        assert_eq!(stats.payload_size_anomalies, 1);
+    }
+
+    #[test]
+    fn sender_restart_needs_confirmation() {
+        let mut statistics = RtpStatistics::new(48_000, None);
+        track(&mut statistics, Instant::now(), &[100, 101]);
+        statistics.track(&packet(30000, 0), Instant::now());
+        assert_eq!(statistics.stats().packets_received, 2);
+        statistics.track(&packet(30001, 48), Instant::now());
+        assert_eq!(statistics.stats().packets_received, 1);
+        assert_eq!(statistics.stats().packets_expected, 1);
+    }
+
+    #[test]
+    fn sequence_number_wraparound() {
+        let mut statistics = RtpStatistics::new(48_000, Some(288));
+        let receptions = track(&mut statistics, Instant::now(), &[65534, 65535, 0, 1]);
+        assert_eq!(
+            receptions,
+            vec![
+                Reception::Start,
+                Reception::InOrder { lost: 0 },
+                Reception::InOrder { lost: 0 },
+                Reception::InOrder { lost: 0 },
+            ]
+        );
+
+        let stats = statistics.stats();
+        assert_eq!(stats.highest_sequence_number, 65536 + 1);
+        assert_eq!(stats.packets_expected, 4);
+        assert_eq!(stats.packets_lost, 0);
+        assert_eq!(stats.out_of_order, 0);
+    }
+
+    #[test]
+    fn many_wraparounds() {
+        let mut statistics = RtpStatistics::new(48_000, Some(288));
+        let start = Instant::now();
+        for i in 0..3 * 65536 + 10u32 {
+            let arrival = start + Duration::from_millis(i as u64);
+            let reception = statistics.track(&packet(i as u16, i * 48), arrival);
+            if i > 0 {
+                assert_eq!(reception, Reception::InOrder { lost: 0 }, "packet {i}");
+            }
+        }
+
+        let stats = statistics.stats();
+        assert_eq!(stats.highest_sequence_number, 3 * 65536 + 9);
+        assert_eq!(stats.packets_expected, 3 * 65536 + 10);
+        assert_eq!(stats.packets_lost, 0);
+        assert!(stats.jitter_ms < 0.001);
+    }
+
+    #[test]
+    fn reordering_across_wraparound() {
+        let mut statistics = RtpStatistics::new(48_000, Some(288));
+        let receptions = track(&mut statistics, Instant::now(), &[65534, 0, 65535, 1]);
+        assert_eq!(
+            receptions,
+            vec![
+                Reception::Start,
+                Reception::InOrder { lost: 1 },
+                Reception::Late,
+                Reception::InOrder { lost: 0 },
+            ]
+        );
+
+        let stats = statistics.stats();
+        assert_eq!(stats.highest_sequence_number, 65536 + 1);
+        assert_eq!(stats.packets_lost, 0);
+        assert_eq!(stats.out_of_order, 1);
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut statistics = RtpStatistics::new(48_000, Some(288));
+        let receptions = track(&mut statistics, Instant::now(), &[5, 6, 6, 5, 7, 6]);
+        assert_eq!(
+            receptions,
+            vec![
+                Reception::Start,
+                Reception::InOrder { lost: 0 },
+                Reception::Duplicate,
+                Reception::Duplicate,
+                Reception::InOrder { lost: 0 },
+                Reception::Duplicate,
+            ]
+        );
+
+        let stats = statistics.stats();
+        assert_eq!(stats.duplicates, 3);
+        assert_eq!(stats.out_of_order, 0);
+        assert_eq!(stats.packets_expected, 3);
+        assert_eq!(stats.packets_received, 6);
+        assert_eq!(stats.packets_lost, -3);
+    }
+
+    #[test]
+    fn burst_loss() {
+        let mut statistics = RtpStatistics::new(48_000, Some(288));
+        let receptions = track(&mut statistics, Instant::now(), &[65500, 65501, 20, 21]);
+        assert_eq!(
+            receptions,
+            vec![
+                Reception::Start,
+                Reception::InOrder { lost: 0 },
+                Reception::InOrder { lost: 54 },
+                Reception::InOrder { lost: 0 },
+            ]
+        );
+
+        let stats = statistics.stats();
+        assert_eq!(stats.highest_sequence_number, 65536 + 21);
+        assert_eq!(stats.packets_expected, 58);
+        assert_eq!(stats.packets_lost, 54);
+
+        // a packet from the burst shows up after all
+        let late = track(&mut statistics, Instant::now(), &[0]);
+        assert_eq!(late, vec![Reception::Late]);
+        assert_eq!(statistics.stats().packets_lost, 53);
+    }
+
+    #[test]
+    fn jump_beyond_dropout_is_discarded() {
+        let mut statistics = RtpStatistics::new(48_000, Some(288));
+        let receptions = track(&mut statistics, Instant::now(), &[100, 101, 20000, 102]);
+        assert_eq!(
+            receptions,
+            vec![
+                Reception::Start,
+                Reception::InOrder { lost: 0 },
+                Reception::Discarded,
+                Reception::InOrder { lost: 0 },
+            ]
+        );
+        assert_eq!(statistics.stats().packets_lost, 0);
+    }
+
+    #[test]
+    fn timestamp_wraparound() {
+        let mut timestamps = ExtendedTimestamps::default();
+        let first = timestamps.extend(u32::MAX - 47);
+        assert_eq!(timestamps.extend(0), first + 48);
+        // reordered from before the wraparound
+        assert_eq!(timestamps.extend(u32::MAX - 47), first);
+        assert_eq!(timestamps.extend(48), first + 96);
+
+        // jitter is computed on the unwrapped timestamps
+        let mut statistics = RtpStatistics::new(48_000, Some(288));
+        let start = Instant::now();
+        for i in 0..100u32 {
+            let timestamp = (u32::MAX - 48 * 50).wrapping_add(i * 48);
+            let arrival = start + Duration::from_millis(i as u64);
+            statistics.track(&packet(i as u16, timestamp), arrival);
+        }
+        assert!(statistics.stats().jitter_ms < 0.001);
+    }
+
+    #[test]
+    fn locks_onto_first_sender() {
+        let mut statistics = RtpStatistics::new(48_000, Some(288));
+        let start = Instant::now();
+        track(&mut statistics, start, &[10, 11]);
+
+        let mut other = packet(5000, 0);
+        other.ssrc = 2;
+        assert_eq!(statistics.track(&other, start), Reception::Rejected);
+        assert_eq!(
+            track(&mut statistics, start, &[12]),
+            vec![Reception::InOrder { lost: 0 }]
+        );
+
+        let stats = statistics.stats();
+        assert_eq!(stats.ssrc, Some(1));
+        assert_eq!(stats.rejected_ssrc, 1);
+        assert_eq!(stats.packets_received, 3);
+        assert_eq!(stats.ssrc_changes, 0);
+
+        // the other sender takes over once the first one fell silent
+        let later = start + SSRC_LOCK_TIMEOUT + Duration::from_millis(100);
+        assert_eq!(statistics.track(&other, later), Reception::Start);
+        assert_eq!(statistics.stats().ssrc, Some(2));
+    }
+
+    #[test]
+    fn filters_payload_type_and_selected_ssrc() {
+        let mut statistics = RtpStatistics::new(48_000, Some(288)).filtered(vec![98], Some(2));
+        let start = Instant::now();
+
+        let mut other_payload_type = packet(1, 48);
+        other_payload_type.ssrc = 2;
+        other_payload_type.payload_type = 97;
+        assert_eq!(
+            statistics.track(&other_payload_type, start),
+            Reception::Rejected
+        );
+
+        // the selected sender is played even though another one was heard first
+        assert_eq!(statistics.track(&packet(0, 0), start), Reception::Rejected);
+        let mut selected = packet(7, 0);
+        selected.ssrc = 2;
+        assert_eq!(statistics.track(&selected, start), Reception::Start);
+
+        let stats = statistics.stats();
+        assert_eq!(stats.ssrc, Some(2));
+        assert_eq!(stats.rejected_payload_type, 1);
+        assert_eq!(stats.rejected_ssrc, 1);
+        assert_eq!(stats.packets_received, 1);
+    }
+}
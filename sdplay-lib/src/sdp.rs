@@ -1,11 +1,13 @@
 use crate::{
     error::{SdpPlayerError, SdpPlayerResult},
-    BitDepth, SessionDescriptor,
+    BitDepth, ChannelGroup, ChannelGroupKind, ChannelLayout, FilterMode, SessionDescriptor,
+    SourceFilter,
 };
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 #[cfg(feature = "fs")]
 use std::path::Path;
-use std::{net::Ipv4Addr, str::FromStr};
+use std::{fmt, net::Ipv4Addr, str::FromStr};
 #[cfg(feature = "fs")]
 use tokio::fs;
 #[cfg(feature = "net")]
@@ -17,18 +19,25 @@ const RTPMAP_BITDEPTH_GROUPT: usize = 2;
 const RTPMAP_SAMPLERATE_GROUPT: usize = 3;
 const RTPMAP_CHANNELS_GROUPT: usize = 4;
 
-const MEDIA_AND_TRANSPORT_REGEX: &str = r"(.+) ([0-9]+) (.+) ([0-9]+)";
-const MEDIA_AND_TRANSPORT_MEDIA_GROUP: usize = 1;
-const MEDIA_AND_TRANSPORT_PORT_GROUP: usize = 2;
-const MEDIA_AND_TRANSPORT_PROTOCOL_GROUP: usize = 3;
-const MEDIA_AND_TRANSPORT_PAYLOAD_ID_GROUP: usize = 4;
-
-const CONNECTION_INFO_REGEX: &str = r"(.+) (IP[4,6]) ([0-9]+\.[0-9]+\.[0-9]+\.[0-9]+)\/([0-9]+)";
-const CONNECTION_INFO_MULTICAST_GROUP: usize = 3;
-
 const PTIME_REGEX: &str = r"ptime:(.+)";
 const PTIME_GROUP: usize = 1;
 
+const SOURCE_FILTER_REGEX: &str = r"source-filter: *(incl|excl) +IN +IP4 +(\S+) +(.+)";
+const SOURCE_FILTER_MODE_GROUP: usize = 1;
+const SOURCE_FILTER_DESTINATION_GROUP: usize = 2;
+const SOURCE_FILTER_SOURCES_GROUP: usize = 3;
+
+const FMTP_REGEX: &str = r"fmtp:([0-9]+) (.*)";
+const FMTP_PAYLOAD_ID_GROUP: usize = 1;
+const FMTP_PARAMETERS_GROUP: usize = 2;
+
+const RTCP_REGEX: &str = r"^rtcp: *([0-9]+)(?: +IN +IP4 +([0-9.]+))?";
+const RTCP_PORT_GROUP: usize = 1;
+const RTCP_ADDRESS_GROUP: usize = 2;
+
+const CHANNEL_ORDER_REGEX: &str = r"^SMPTE2110\.\((.*)\)$";
+const CHANNEL_ORDER_GROUPS_GROUP: usize = 1;
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct RtpMap {
     payload_id: u16,
@@ -74,47 +83,35 @@ impl FromStr for RtpMap {
     }
 }
 
+/// The playback relevant fields of an `m=` line, which is parsed as a [`MediaDescription`].
 #[derive(Debug, Clone, PartialEq)]
 pub struct MediaAndTransport {
     media: Media,
     port: u16,
     protocol: String,
-    payload_id: u16,
+    /// the formats, i.e. RTP payload types, in order of preference
+    payload_ids: Vec<u16>,
 }
 
 impl FromStr for MediaAndTransport {
     type Err = SdpPlayerError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let re = Regex::new(MEDIA_AND_TRANSPORT_REGEX).expect("cannot fail");
-        if let Some(caps) = re.captures(s) {
-            Ok(MediaAndTransport {
-                media: caps
-                    .get(MEDIA_AND_TRANSPORT_MEDIA_GROUP)
-                    .expect("must exist in matches")
-                    .as_str()
-                    .parse()?,
-                port: caps
-                    .get(MEDIA_AND_TRANSPORT_PORT_GROUP)
-                    .expect("must exist in matches")
-                    .as_str()
-                    .parse()
-                    .map_err(SdpPlayerError::invalid_port)?,
-                protocol: caps
-                    .get(MEDIA_AND_TRANSPORT_PROTOCOL_GROUP)
-                    .expect("must exist in matches")
-                    .as_str()
-                    .to_owned(),
-                payload_id: caps
-                    .get(MEDIA_AND_TRANSPORT_PAYLOAD_ID_GROUP)
-                    .expect("must exist in matches")
-                    .as_str()
-                    .parse()
-                    .map_err(SdpPlayerError::invalid_payload_id)?,
-            })
-        } else {
-            Err(SdpPlayerError::MalformedMediaTransport(s.to_owned()))
+        let media: MediaDescription = s.parse()?;
+        if media.formats.is_empty() {
+            return Err(SdpPlayerError::MalformedMediaTransport(s.to_owned()));
         }
+        let payload_ids = media
+            .formats
+            .iter()
+            .map(|format| format.parse().map_err(SdpPlayerError::invalid_payload_id))
+            .collect::<SdpPlayerResult<_>>()?;
+        Ok(MediaAndTransport {
+            media: media.media.parse()?,
+            port: media.port,
+            protocol: media.protocol,
+            payload_ids,
+        })
     }
 }
 
@@ -136,6 +133,7 @@ impl FromStr for Media {
     }
 }
 
+/// The multicast address of a `c=` line, which is parsed as a [`Connection`].
 #[derive(Debug, Clone, PartialEq)]
 pub struct ConnectionInfo {
     multicast_address: Ipv4Addr,
@@ -145,19 +143,13 @@ impl FromStr for ConnectionInfo {
     type Err = SdpPlayerError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let re = Regex::new(CONNECTION_INFO_REGEX).expect("cannot fail");
-        if let Some(caps) = re.captures(s) {
-            Ok(ConnectionInfo {
-                multicast_address: caps
-                    .get(CONNECTION_INFO_MULTICAST_GROUP)
-                    .expect("must exist in matches")
-                    .as_str()
-                    .parse()
-                    .map_err(SdpPlayerError::invalid_ip)?,
-            })
-        } else {
-            Err(SdpPlayerError::MalformedConnectionInfo(s.to_owned()))
-        }
+        let connection: Connection = s.parse()?;
+        Ok(ConnectionInfo {
+            multicast_address: connection
+                .base_address()
+                .parse()
+                .map_err(SdpPlayerError::invalid_ip)?,
+        })
     }
 }
 
@@ -175,39 +167,223 @@ fn parse_packet_time(attribue: &str) -> SdpPlayerResult<f32> {
     }
 }
 
+impl FromStr for SourceFilter {
+    type Err = SdpPlayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let re = Regex::new(SOURCE_FILTER_REGEX).expect("cannot fail");
+        if let Some(caps) = re.captures(s) {
+            let mode = match caps
+                .get(SOURCE_FILTER_MODE_GROUP)
+                .expect("must exist in matches")
+                .as_str()
+            {
+                "incl" => FilterMode::Include,
+                _ => FilterMode::Exclude,
+            };
+            let destination_address = match caps
+                .get(SOURCE_FILTER_DESTINATION_GROUP)
+                .expect("must exist in matches")
+                .as_str()
+            {
+                "*" => None,
+                address => Some(address.parse().map_err(SdpPlayerError::invalid_ip)?),
+            };
+            let source_addresses = caps
+                .get(SOURCE_FILTER_SOURCES_GROUP)
+                .expect("must exist in matches")
+                .as_str()
+                .split_whitespace()
+                .map(|address| address.parse().map_err(SdpPlayerError::invalid_ip))
+                .collect::<SdpPlayerResult<Vec<Ipv4Addr>>>()?;
+            Ok(SourceFilter {
+                mode,
+                destination_address,
+                source_addresses,
+            })
+        } else {
+            Err(SdpPlayerError::MalformedSourceFilter(s.to_owned()))
+        }
+    }
+}
+
+/// Where a stream's RTCP packets go, from an RFC 3605 `a=rtcp:<port> [IN IP4 <address>]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RtcpAttribute {
+    pub port: u16,
+    /// the address RTCP is sent to if it differs from the stream's address
+    pub address: Option<Ipv4Addr>,
+}
+
+impl FromStr for RtcpAttribute {
+    type Err = SdpPlayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let re = Regex::new(RTCP_REGEX).expect("cannot fail");
+        if let Some(caps) = re.captures(s) {
+            Ok(RtcpAttribute {
+                port: caps
+                    .get(RTCP_PORT_GROUP)
+                    .expect("must exist in matches")
+                    .as_str()
+                    .parse()
+                    .map_err(SdpPlayerError::invalid_port)?,
+                address: caps
+                    .get(RTCP_ADDRESS_GROUP)
+                    .map(|address| address.as_str().parse().map_err(SdpPlayerError::invalid_ip))
+                    .transpose()?,
+            })
+        } else {
+            Err(SdpPlayerError::MalformedRtcpAttribute(s.to_owned()))
+        }
+    }
+}
+
+/// Format specific parameters of a payload type, from `a=fmtp:<pt> <name>=<value>; ...`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fmtp {
+    pub payload_id: u16,
+    pub parameters: Vec<(String, String)>,
+}
+
+impl Fmtp {
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl FromStr for Fmtp {
+    type Err = SdpPlayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let re = Regex::new(FMTP_REGEX).expect("cannot fail");
+        if let Some(caps) = re.captures(s) {
+            Ok(Fmtp {
+                payload_id: caps
+                    .get(FMTP_PAYLOAD_ID_GROUP)
+                    .expect("must exist in matches")
+                    .as_str()
+                    .parse()
+                    .map_err(SdpPlayerError::invalid_payload_id)?,
+                parameters: caps
+                    .get(FMTP_PARAMETERS_GROUP)
+                    .expect("must exist in matches")
+                    .as_str()
+                    .split(';')
+                    .filter_map(|parameter| {
+                        let (name, value) = parameter.split_once('=')?;
+                        Some((name.trim().to_owned(), value.trim().to_owned()))
+                    })
+                    .collect(),
+            })
+        } else {
+            Err(SdpPlayerError::MalformedAttribute(s.to_owned()))
+        }
+    }
+}
+
+impl FromStr for ChannelLayout {
+    type Err = SdpPlayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let re = Regex::new(CHANNEL_ORDER_REGEX).expect("cannot fail");
+        let caps = re
+            .captures(s.trim())
+            .ok_or_else(|| SdpPlayerError::MalformedChannelOrder(s.to_owned()))?;
+        let groups = caps
+            .get(CHANNEL_ORDER_GROUPS_GROUP)
+            .expect("must exist in matches")
+            .as_str()
+            .split(',')
+            .map(|symbol| match symbol.trim() {
+                "M" => Ok(ChannelGroup::new(ChannelGroupKind::Mono)),
+                "DM" => Ok(ChannelGroup::new(ChannelGroupKind::DualMono)),
+                "ST" => Ok(ChannelGroup::new(ChannelGroupKind::Stereo)),
+                "LtRt" => Ok(ChannelGroup::new(ChannelGroupKind::MatrixStereo)),
+                "51" => Ok(ChannelGroup::new(ChannelGroupKind::Surround51)),
+                "71" => Ok(ChannelGroup::new(ChannelGroupKind::Surround71)),
+                "222" => Ok(ChannelGroup::new(ChannelGroupKind::Surround222)),
+                "SGRP" => Ok(ChannelGroup::new(ChannelGroupKind::SdiGroup)),
+                symbol => symbol
+                    .strip_prefix('U')
+                    .and_then(|channels| channels.parse().ok())
+                    .filter(|channels| (1..=64).contains(channels))
+                    .map(ChannelGroup::undefined)
+                    .ok_or_else(|| SdpPlayerError::MalformedChannelOrder(s.to_owned())),
+            })
+            .collect::<SdpPlayerResult<Vec<ChannelGroup>>>()?;
+        Ok(ChannelLayout { groups })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum SdpValue {
+    ProtocolVersion(u8),                             // v
     OriginatorAndSessionIdentifier(String),          // o
     SessionName(String),                             // s
-    ActiveTime((usize, usize)),                      // t
+    ActiveTime(TimeDescription),                     // t
+    RepeatTimes(String),                             // r
+    TimeZones(String),                               // z
     MediaNameAndTransportAddress(MediaAndTransport), // m
     SessionInfo(String),                             // i
     SessionDescription(String),                      // u
+    EmailAddress(String),                            // e
+    PhoneNumber(String),                             // p
     ConnectionInformation(ConnectionInfo),           // c
+    BandwidthInformation(Bandwidth),                 // b
+    EncryptionKey(String),                           // k
     Attribute(String),                               // a
 }
 
 #[cfg(feature = "net")]
-pub async fn session_descriptor_from_sdp_url(url: &Url) -> SdpPlayerResult<SessionDescriptor> {
+pub async fn session_descriptor_from_sdp_url(
+    url: &Url,
+    stream: &StreamSelector,
+) -> SdpPlayerResult<SessionDescriptor> {
+    stream.select(session_descriptors_from_sdp_url(url).await?)
+}
+
+#[cfg(feature = "net")]
+pub async fn session_descriptors_from_sdp_url(
+    url: &Url,
+) -> SdpPlayerResult<Vec<Option<SessionDescriptor>>> {
     let sdp_content = reqwest::get(url.as_str()).await?.text().await?;
     log::debug!("SDP: \n{sdp_content}");
-    sdp_content.parse()
+    parse_session_descriptors(&sdp_content)
 }
 
 #[cfg(feature = "fs")]
 pub async fn session_descriptor_from_sdp_file(
     path: impl AsRef<Path>,
+    stream: &StreamSelector,
 ) -> SdpPlayerResult<SessionDescriptor> {
+    stream.select(session_descriptors_from_sdp_file(path).await?)
+}
+
+#[cfg(feature = "fs")]
+pub async fn session_descriptors_from_sdp_file(
+    path: impl AsRef<Path>,
+) -> SdpPlayerResult<Vec<Option<SessionDescriptor>>> {
     let sdp_content = fs::read_to_string(path).await?;
     log::debug!("SDP: \n{sdp_content}");
-    sdp_content.parse()
+    parse_session_descriptors(&sdp_content)
 }
 
 pub async fn session_descriptor_from_sdp_str(
     sdp_content: &str,
+    stream: &StreamSelector,
 ) -> SdpPlayerResult<SessionDescriptor> {
+    stream.select(session_descriptors_from_sdp_str(sdp_content).await?)
+}
+
+pub async fn session_descriptors_from_sdp_str(
+    sdp_content: &str,
+) -> SdpPlayerResult<Vec<Option<SessionDescriptor>>> {
     log::debug!("SDP: \n{sdp_content}");
-    sdp_content.parse()
+    parse_session_descriptors(sdp_content)
 }
 
 fn parse_line(line: &str) -> SdpPlayerResult<Option<(&str, SdpValue)>> {
@@ -217,8 +393,7 @@ fn parse_line(line: &str) -> SdpPlayerResult<Option<(&str, SdpValue)>> {
         return Ok(None);
     }
 
-    let mut kv = trim.split("=");
-    if let (Some(key), Some(value)) = (kv.next(), kv.next()) {
+    if let Some((key, value)) = trim.split_once('=') {
         if let Some(value) = parse_value(key, value)? {
             Ok(Some((key, value)))
         } else {
@@ -233,86 +408,755 @@ fn parse_line(line: &str) -> SdpPlayerResult<Option<(&str, SdpValue)>> {
 
 fn parse_value(key: &str, value: &str) -> SdpPlayerResult<Option<SdpValue>> {
     match key {
+        "v" => Ok(Some(SdpValue::ProtocolVersion(
+            value.parse().map_err(SdpPlayerError::invalid_sdp_version)?,
+        ))),
         "o" => Ok(Some(SdpValue::OriginatorAndSessionIdentifier(
             value.to_owned(),
         ))),
         "s" => Ok(Some(SdpValue::SessionName(value.to_owned()))),
+        "t" => Ok(Some(SdpValue::ActiveTime(value.parse()?))),
+        "r" => Ok(Some(SdpValue::RepeatTimes(value.to_owned()))),
+        "z" => Ok(Some(SdpValue::TimeZones(value.to_owned()))),
         "m" => Ok(Some(SdpValue::MediaNameAndTransportAddress(value.parse()?))),
         "i" => Ok(Some(SdpValue::SessionInfo(value.to_owned()))),
         "u" => Ok(Some(SdpValue::SessionDescription(value.to_owned()))),
+        "e" => Ok(Some(SdpValue::EmailAddress(value.to_owned()))),
+        "p" => Ok(Some(SdpValue::PhoneNumber(value.to_owned()))),
         "c" => Ok(Some(SdpValue::ConnectionInformation(value.parse()?))),
+        "b" => Ok(Some(SdpValue::BandwidthInformation(value.parse()?))),
+        "k" => Ok(Some(SdpValue::EncryptionKey(value.to_owned()))),
         "a" => Ok(Some(SdpValue::Attribute(value.to_owned()))),
         _ => Ok(None),
     }
 }
 
+/// Playback relevant values of a single media section, or of the session level defaults that
+/// apply to all media sections.
+#[derive(Debug, Clone, Default)]
+struct PartialSessionDescriptor {
+    media: Option<Media>,
+    multicast_address: Option<Ipv4Addr>,
+    multicast_port: Option<u16>,
+    packet_time: Option<f32>,
+    mid: Option<String>,
+    source_filters: Vec<SourceFilter>,
+    rtpmaps: Vec<RtpMap>,
+    fmtps: Vec<Fmtp>,
+    rtcp: Option<RtcpAttribute>,
+    payload_types: Vec<u8>,
+    ssrc: Option<u32>,
+    session_name: Option<String>,
+}
+
+impl PartialSessionDescriptor {
+    fn apply_attribute(&mut self, attribute: &str) {
+        if let Ok(rtpmap) = attribute.parse::<RtpMap>() {
+            self.rtpmaps.push(rtpmap);
+        }
+        if let Ok(ptime) = parse_packet_time(attribute) {
+            self.packet_time = Some(ptime);
+        }
+        if let Some(mid) = attribute.strip_prefix("mid:") {
+            self.mid = Some(mid.trim().to_owned());
+        }
+        if let Ok(source_filter) = attribute.parse::<SourceFilter>() {
+            self.source_filters.push(source_filter);
+        }
+        if let Ok(rtcp) = attribute.parse::<RtcpAttribute>() {
+            self.rtcp = Some(rtcp);
+        }
+        if let Some(ssrc) = attribute.strip_prefix("ssrc:") {
+            // RFC 5576 lists one line per attribute of a source, only the first source is played
+            if let Some(Ok(ssrc)) = ssrc.split_whitespace().next().map(str::parse) {
+                self.ssrc.get_or_insert(ssrc);
+            }
+        }
+        if let Ok(fmtp) = attribute.parse::<Fmtp>() {
+            self.fmtps.push(fmtp);
+        }
+    }
+
+    fn resolve(self, session: &PartialSessionDescriptor) -> Option<SessionDescriptor> {
+        let multicast_address = self.multicast_address.or(session.multicast_address)?;
+        // media level source filters replace the session level ones (RFC 4570, section 3.1)
+        let source_filters = if self.source_filters.is_empty() {
+            &session.source_filters
+        } else {
+            &self.source_filters
+        };
+        let source_filters = source_filters
+            .iter()
+            .filter(|filter| filter.applies_to(multicast_address))
+            .cloned()
+            .collect();
+        // the first format of the m= line that has an a=rtpmap is played, rtpmap and fmtp lines
+        // of payload types the section does not list don't apply to it
+        let rtpmap = self.payload_types.iter().find_map(|payload_type| {
+            self.rtpmaps
+                .iter()
+                .chain(&session.rtpmaps)
+                .find(|rtpmap| rtpmap.payload_id == *payload_type as u16)
+        })?;
+        let channels = rtpmap.channels;
+        let channel_layout = self
+            .fmtps
+            .iter()
+            .chain(&session.fmtps)
+            .find(|fmtp| fmtp.payload_id == rtpmap.payload_id)
+            .and_then(|fmtp| fmtp.parameter("channel-order"))
+            .and_then(|channel_order| match channel_order.parse::<ChannelLayout>() {
+                Ok(layout) => Some(layout),
+                Err(e) => {
+                    log::warn!("Ignoring channel order: {e}");
+                    None
+                }
+            })
+            .filter(|layout| {
+                let matches = layout.channels() == channels;
+                if !matches {
+                    log::warn!(
+                        "Ignoring channel order {layout}, it does not match the stream's {channels} channels"
+                    );
+                }
+                matches
+            });
+        Some(SessionDescriptor {
+            multicast_address,
+            multicast_port: self.multicast_port?,
+            bit_depth: rtpmap.bit_depth.clone(),
+            channels,
+            sample_rate: rtpmap.sample_rate,
+            packet_time: self.packet_time.or(session.packet_time)?,
+            mid: self.mid,
+            source_filters,
+            channel_layout,
+            rtcp_port: self.rtcp.as_ref().map(|rtcp| rtcp.port),
+            rtcp_address: self.rtcp.and_then(|rtcp| rtcp.address),
+            payload_types: self.payload_types,
+            ssrc: self.ssrc,
+            session_name: session.session_name.clone(),
+        })
+    }
+}
+
+/// Parses all audio media sections of an SDP into separate [`SessionDescriptor`]s, in the
+/// order in which they appear in the SDP. Session level connection info and attributes are
+/// used for every media section that does not override them.
+///
+/// Sections that can't be played, e.g. for lack of connection info or a supported `a=rtpmap`,
+/// are `None`, so that the others keep their index. It's an error only if no section can be
+/// played.
+pub fn parse_session_descriptors(s: &str) -> SdpPlayerResult<Vec<Option<SessionDescriptor>>> {
+    let mut session = PartialSessionDescriptor::default();
+    let mut sections: Vec<PartialSessionDescriptor> = Vec::new();
+
+    for line in s.split('\n') {
+        let parsed = match parse_line(line) {
+            Err(e) if line.trim_start().starts_with("m=") => {
+                // e.g. an application section, which is skipped along with its attributes
+                log::debug!("Skipping media section that is no audio or video: {e}");
+                sections.push(PartialSessionDescriptor::default());
+                continue;
+            }
+            parsed => parsed?,
+        };
+        if let Some((_, value)) = parsed {
+            if let SdpValue::MediaNameAndTransportAddress(m) = value {
+                sections.push(PartialSessionDescriptor {
+                    media: Some(m.media),
+                    multicast_port: Some(m.port),
+                    // dynamic payload types end at 127, anything above can't be in an RTP header
+                    payload_types: m
+                        .payload_ids
+                        .iter()
+                        .filter_map(|id| u8::try_from(*id).ok())
+                        .filter(|pt| *pt < 128)
+                        .collect(),
+                    ..Default::default()
+                });
+                continue;
+            }
+
+            let current = sections.last_mut().unwrap_or(&mut session);
+
+            match value {
+                SdpValue::ProtocolVersion(_) => {}
+                SdpValue::OriginatorAndSessionIdentifier(_) => {}
+                SdpValue::SessionName(name) => current.session_name = Some(name),
+                SdpValue::ActiveTime(_) => {}
+                SdpValue::RepeatTimes(_) => {}
+                SdpValue::TimeZones(_) => {}
+                SdpValue::MediaNameAndTransportAddress(_) => {}
+                SdpValue::SessionInfo(_) => {}
+                SdpValue::SessionDescription(_) => {}
+                SdpValue::EmailAddress(_) => {}
+                SdpValue::PhoneNumber(_) => {}
+                SdpValue::ConnectionInformation(c) => {
+                    current.multicast_address = Some(c.multicast_address)
+                }
+                SdpValue::BandwidthInformation(_) => {}
+                SdpValue::EncryptionKey(_) => {}
+                SdpValue::Attribute(a) => current.apply_attribute(&a),
+            }
+        }
+    }
+
+    let descriptors: Vec<_> = sections
+        .into_iter()
+        .filter(|section| section.media == Some(Media::Audio))
+        .enumerate()
+        .map(|(index, section)| {
+            let label = section.mid.clone().unwrap_or_else(|| index.to_string());
+            let descriptor = section.resolve(&session);
+            if descriptor.is_none() {
+                log::warn!("Skipping media section '{label}', it can't be played");
+            }
+            descriptor
+        })
+        .collect();
+
+    if descriptors.iter().all(Option::is_none) {
+        Err(SdpPlayerError::MalformedSdpFile(s.to_owned()))
+    } else {
+        Ok(descriptors)
+    }
+}
+
 impl FromStr for SessionDescriptor {
     type Err = SdpPlayerError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let lines = s.split("\n");
-
-        let mut bit_depth = None;
-        let mut channels = None;
-        let mut multicast_address = None;
-        let mut multicast_port = None;
-        let mut packet_time = None;
-        let mut sample_rate = None;
-
-        for line in lines {
-            if let Some((_, value)) = parse_line(line)? {
-                match value {
-                    SdpValue::OriginatorAndSessionIdentifier(_) => {}
-                    SdpValue::SessionName(_) => {}
-                    SdpValue::ActiveTime(_) => {}
-                    SdpValue::MediaNameAndTransportAddress(m) => {
-                        multicast_port = Some(m.port);
-                    }
-                    SdpValue::SessionInfo(_) => {}
-                    SdpValue::SessionDescription(_) => {}
-                    SdpValue::ConnectionInformation(c) => {
-                        multicast_address = Some(c.multicast_address)
-                    }
-                    SdpValue::Attribute(a) => {
-                        if let Ok(rtpmap) = a.parse::<RtpMap>() {
-                            sample_rate = Some(rtpmap.sample_rate);
-                            channels = Some(rtpmap.channels);
-                            bit_depth = Some(rtpmap.bit_depth);
-                        }
-                        if let Ok(ptime) = parse_packet_time(&a) {
-                            packet_time = Some(ptime);
-                        }
+        StreamSelector::default().select(parse_session_descriptors(s)?)
+    }
+}
+
+/// Selects one of the audio media sections of an SDP, either by its zero based index among
+/// the audio sections or by its `a=mid` label. A numeric label can be told apart from an
+/// index by a `mid:` prefix, e.g. `mid:1`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum StreamSelector {
+    Index(usize),
+    Mid(String),
+}
+
+impl Default for StreamSelector {
+    fn default() -> Self {
+        StreamSelector::Index(0)
+    }
+}
+
+impl StreamSelector {
+    /// Selects a section of `descriptors` as returned by [`parse_session_descriptors`].
+    pub fn select(
+        &self,
+        descriptors: Vec<Option<SessionDescriptor>>,
+    ) -> SdpPlayerResult<SessionDescriptor> {
+        match self {
+            StreamSelector::Index(index) if *index < descriptors.len() => descriptors
+                .into_iter()
+                .nth(*index)
+                .flatten()
+                .ok_or_else(|| SdpPlayerError::UnusableStream(self.to_string())),
+            // numeric labels are common, so an index without a section may still be a mid
+            StreamSelector::Index(index) => find_mid(descriptors, &index.to_string())
+                .ok_or_else(|| SdpPlayerError::NoSuchStream(self.to_string())),
+            StreamSelector::Mid(mid) => find_mid(descriptors, mid)
+                .ok_or_else(|| SdpPlayerError::NoSuchStream(self.to_string())),
+        }
+    }
+}
+
+fn find_mid(descriptors: Vec<Option<SessionDescriptor>>, mid: &str) -> Option<SessionDescriptor> {
+    descriptors
+        .into_iter()
+        .flatten()
+        .find(|sd| sd.mid.as_deref() == Some(mid))
+}
+
+impl FromStr for StreamSelector {
+    type Err = SdpPlayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            Err(SdpPlayerError::NoSuchStream(s.to_owned()))
+        } else if let Some(mid) = s.strip_prefix("mid:") {
+            Ok(StreamSelector::Mid(mid.to_owned()))
+        } else if let Ok(index) = s.parse() {
+            Ok(StreamSelector::Index(index))
+        } else {
+            Ok(StreamSelector::Mid(s.to_owned()))
+        }
+    }
+}
+
+impl fmt::Display for StreamSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamSelector::Index(index) => write!(f, "{index}"),
+            StreamSelector::Mid(mid) if mid.parse::<usize>().is_ok() => write!(f, "mid:{mid}"),
+            StreamSelector::Mid(mid) => write!(f, "{mid}"),
+        }
+    }
+}
+
+/// Line terminator used when serializing a [`SessionDescription`], as mandated by RFC 8866.
+const SDP_LINE_END: &str = "\r\n";
+
+/// A complete session description as defined by RFC 4566 / RFC 8866.
+///
+/// Unlike [`SessionDescriptor`], which only extracts what is needed for playback, this keeps
+/// every line of the SDP in order, so it can be inspected, edited and serialized again via
+/// its [`Display`](fmt::Display) implementation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionDescription {
+    pub version: u8,
+    pub origin: Origin,
+    pub session_name: String,
+    pub session_information: Option<String>,
+    pub uri: Option<String>,
+    pub emails: Vec<String>,
+    pub phones: Vec<String>,
+    pub connection: Option<Connection>,
+    pub bandwidths: Vec<Bandwidth>,
+    pub times: Vec<TimeDescription>,
+    pub time_zones: Option<String>,
+    pub encryption_key: Option<String>,
+    pub attributes: Vec<Attribute>,
+    pub media_descriptions: Vec<MediaDescription>,
+}
+
+impl SessionDescription {
+    /// Returns the first session level attribute with the given name.
+    pub fn attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|a| a.name == name)
+    }
+}
+
+/// The `o=` line of a session description.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Origin {
+    pub username: String,
+    pub session_id: String,
+    pub session_version: String,
+    pub network_type: String,
+    pub address_type: String,
+    pub unicast_address: String,
+}
+
+/// The `c=` line of a session or media description.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Connection {
+    pub network_type: String,
+    pub address_type: String,
+    /// The connection address including any `/<ttl>` or `/<number of addresses>` suffix.
+    pub address: String,
+}
+
+impl Connection {
+    /// The connection address without the TTL / address count suffix.
+    pub fn base_address(&self) -> &str {
+        self.address.split('/').next().unwrap_or(&self.address)
+    }
+}
+
+/// A `b=` line of a session or media description.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bandwidth {
+    pub bandwidth_type: String,
+    pub bandwidth: u64,
+}
+
+/// A `t=` line together with the `r=` lines that follow it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimeDescription {
+    pub start_time: u64,
+    pub stop_time: u64,
+    pub repeat_times: Vec<String>,
+}
+
+/// An `a=` line, either a property attribute (`a=recvonly`) or a value attribute
+/// (`a=rtpmap:98 L16/48000/8`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attribute {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+/// A media section, starting with an `m=` line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MediaDescription {
+    pub media: String,
+    pub port: u16,
+    pub number_of_ports: Option<u16>,
+    pub protocol: String,
+    pub formats: Vec<String>,
+    pub information: Option<String>,
+    pub connections: Vec<Connection>,
+    pub bandwidths: Vec<Bandwidth>,
+    pub encryption_key: Option<String>,
+    pub attributes: Vec<Attribute>,
+}
+
+impl MediaDescription {
+    /// Returns the first media level attribute with the given name.
+    pub fn attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|a| a.name == name)
+    }
+}
+
+impl FromStr for Origin {
+    type Err = SdpPlayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split(' ').collect();
+        if let [username, session_id, session_version, network_type, address_type, unicast_address] =
+            fields[..]
+        {
+            Ok(Origin {
+                username: username.to_owned(),
+                session_id: session_id.to_owned(),
+                session_version: session_version.to_owned(),
+                network_type: network_type.to_owned(),
+                address_type: address_type.to_owned(),
+                unicast_address: unicast_address.to_owned(),
+            })
+        } else {
+            Err(SdpPlayerError::MalformedOrigin(s.to_owned()))
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {}",
+            self.username,
+            self.session_id,
+            self.session_version,
+            self.network_type,
+            self.address_type,
+            self.unicast_address
+        )
+    }
+}
+
+impl FromStr for Connection {
+    type Err = SdpPlayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split(' ').collect();
+        if let [network_type, address_type, address] = fields[..] {
+            Ok(Connection {
+                network_type: network_type.to_owned(),
+                address_type: address_type.to_owned(),
+                address: address.to_owned(),
+            })
+        } else {
+            Err(SdpPlayerError::MalformedConnectionInfo(s.to_owned()))
+        }
+    }
+}
+
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.network_type, self.address_type, self.address
+        )
+    }
+}
+
+impl FromStr for Bandwidth {
+    type Err = SdpPlayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((bandwidth_type, bandwidth)) = s.split_once(':') {
+            Ok(Bandwidth {
+                bandwidth_type: bandwidth_type.to_owned(),
+                bandwidth: bandwidth
+                    .parse()
+                    .map_err(|_| SdpPlayerError::MalformedBandwidth(s.to_owned()))?,
+            })
+        } else {
+            Err(SdpPlayerError::MalformedBandwidth(s.to_owned()))
+        }
+    }
+}
+
+impl fmt::Display for Bandwidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.bandwidth_type, self.bandwidth)
+    }
+}
+
+impl FromStr for TimeDescription {
+    type Err = SdpPlayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut times = s.split_whitespace();
+        if let (Some(start_time), Some(stop_time), None) =
+            (times.next(), times.next(), times.next())
+        {
+            Ok(TimeDescription {
+                start_time: start_time
+                    .parse()
+                    .map_err(|_| SdpPlayerError::MalformedTiming(s.to_owned()))?,
+                stop_time: stop_time
+                    .parse()
+                    .map_err(|_| SdpPlayerError::MalformedTiming(s.to_owned()))?,
+                repeat_times: Vec::new(),
+            })
+        } else {
+            Err(SdpPlayerError::MalformedTiming(s.to_owned()))
+        }
+    }
+}
+
+impl FromStr for Attribute {
+    type Err = SdpPlayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match s.split_once(':') {
+            Some((name, value)) => (name, Some(value.to_owned())),
+            None => (s, None),
+        };
+        if name.is_empty() {
+            Err(SdpPlayerError::MalformedAttribute(s.to_owned()))
+        } else {
+            Ok(Attribute {
+                name: name.to_owned(),
+                value,
+            })
+        }
+    }
+}
+
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}:{}", self.name, value),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+impl FromStr for MediaDescription {
+    type Err = SdpPlayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split(' ').collect();
+        if let [media, port, protocol, formats @ ..] = &fields[..] {
+            let (port, number_of_ports) = match port.split_once('/') {
+                Some((port, number_of_ports)) => (
+                    port,
+                    Some(
+                        number_of_ports
+                            .parse()
+                            .map_err(SdpPlayerError::invalid_port)?,
+                    ),
+                ),
+                None => (*port, None),
+            };
+            Ok(MediaDescription {
+                media: media.to_string(),
+                port: port.parse().map_err(SdpPlayerError::invalid_port)?,
+                number_of_ports,
+                protocol: protocol.to_string(),
+                formats: formats.iter().map(ToString::to_string).collect(),
+                information: None,
+                connections: Vec::new(),
+                bandwidths: Vec::new(),
+                encryption_key: None,
+                attributes: Vec::new(),
+            })
+        } else {
+            Err(SdpPlayerError::MalformedMediaTransport(s.to_owned()))
+        }
+    }
+}
+
+impl fmt::Display for MediaDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "m={} {}", self.media, self.port)?;
+        if let Some(number_of_ports) = self.number_of_ports {
+            write!(f, "/{number_of_ports}")?;
+        }
+        write!(f, " {}", self.protocol)?;
+        for format in &self.formats {
+            write!(f, " {format}")?;
+        }
+        write!(f, "{SDP_LINE_END}")?;
+        if let Some(information) = &self.information {
+            write!(f, "i={information}{SDP_LINE_END}")?;
+        }
+        for connection in &self.connections {
+            write!(f, "c={connection}{SDP_LINE_END}")?;
+        }
+        for bandwidth in &self.bandwidths {
+            write!(f, "b={bandwidth}{SDP_LINE_END}")?;
+        }
+        if let Some(key) = &self.encryption_key {
+            write!(f, "k={key}{SDP_LINE_END}")?;
+        }
+        for attribute in &self.attributes {
+            write!(f, "a={attribute}{SDP_LINE_END}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SessionDescription {
+    type Err = SdpPlayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut version = None;
+        let mut origin = None;
+        let mut session_name = None;
+        let mut session_information = None;
+        let mut uri = None;
+        let mut emails = Vec::new();
+        let mut phones = Vec::new();
+        let mut connection = None;
+        let mut bandwidths = Vec::new();
+        let mut times: Vec<TimeDescription> = Vec::new();
+        let mut time_zones = None;
+        let mut encryption_key = None;
+        let mut attributes = Vec::new();
+        let mut media_descriptions: Vec<MediaDescription> = Vec::new();
+
+        for line in s.lines() {
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                SdpPlayerError::MalformedSdpFile(format!("line is not a key/value pair: {line}"))
+            })?;
+
+            if key == "m" {
+                media_descriptions.push(value.parse()?);
+                continue;
+            }
+
+            if let Some(media) = media_descriptions.last_mut() {
+                match key {
+                    "i" => media.information = Some(value.to_owned()),
+                    "c" => media.connections.push(value.parse()?),
+                    "b" => media.bandwidths.push(value.parse()?),
+                    "k" => media.encryption_key = Some(value.to_owned()),
+                    "a" => media.attributes.push(value.parse()?),
+                    _ => {
+                        return Err(SdpPlayerError::MalformedSdpFile(format!(
+                            "unexpected line in media description: {line}"
+                        )))
                     }
                 }
+                continue;
+            }
+
+            match key {
+                "v" => version = Some(value.parse().map_err(SdpPlayerError::invalid_sdp_version)?),
+                "o" => origin = Some(value.parse()?),
+                "s" => session_name = Some(value.to_owned()),
+                "i" => session_information = Some(value.to_owned()),
+                "u" => uri = Some(value.to_owned()),
+                "e" => emails.push(value.to_owned()),
+                "p" => phones.push(value.to_owned()),
+                "c" => connection = Some(value.parse()?),
+                "b" => bandwidths.push(value.parse()?),
+                "t" => times.push(value.parse()?),
+                "r" => times
+                    .last_mut()
+                    .ok_or_else(|| {
+                        SdpPlayerError::MalformedSdpFile(format!(
+                            "repeat time without t= line: {line}"
+                        ))
+                    })?
+                    .repeat_times
+                    .push(value.to_owned()),
+                "z" => time_zones = Some(value.to_owned()),
+                "k" => encryption_key = Some(value.to_owned()),
+                "a" => attributes.push(value.parse()?),
+                _ => {
+                    return Err(SdpPlayerError::MalformedSdpFile(format!(
+                        "unknown line type: {line}"
+                    )))
+                }
             }
         }
 
-        if let (
-            Some(bit_depth),
-            Some(channels),
-            Some(multicast_address),
-            Some(multicast_port),
-            Some(packet_time),
-            Some(sample_rate),
-        ) = (
-            bit_depth,
-            channels,
-            multicast_address,
-            multicast_port,
-            packet_time,
-            sample_rate,
-        ) {
-            Ok(SessionDescriptor {
-                bit_depth,
-                channels,
-                multicast_address,
-                multicast_port,
-                packet_time,
-                sample_rate,
+        if let (Some(version), Some(origin), Some(session_name)) = (version, origin, session_name) {
+            Ok(SessionDescription {
+                version,
+                origin,
+                session_name,
+                session_information,
+                uri,
+                emails,
+                phones,
+                connection,
+                bandwidths,
+                times,
+                time_zones,
+                encryption_key,
+                attributes,
+                media_descriptions,
             })
         } else {
-            Err(SdpPlayerError::MalformedSdpFile(s.to_owned()))
+            Err(SdpPlayerError::MalformedSdpFile(
+                "v=, o= and s= lines are mandatory".to_owned(),
+            ))
+        }
+    }
+}
+
+impl fmt::Display for SessionDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v={}{SDP_LINE_END}", self.version)?;
+        write!(f, "o={}{SDP_LINE_END}", self.origin)?;
+        write!(f, "s={}{SDP_LINE_END}", self.session_name)?;
+        if let Some(information) = &self.session_information {
+            write!(f, "i={information}{SDP_LINE_END}")?;
+        }
+        if let Some(uri) = &self.uri {
+            write!(f, "u={uri}{SDP_LINE_END}")?;
+        }
+        for email in &self.emails {
+            write!(f, "e={email}{SDP_LINE_END}")?;
+        }
+        for phone in &self.phones {
+            write!(f, "p={phone}{SDP_LINE_END}")?;
+        }
+        if let Some(connection) = &self.connection {
+            write!(f, "c={connection}{SDP_LINE_END}")?;
+        }
+        for bandwidth in &self.bandwidths {
+            write!(f, "b={bandwidth}{SDP_LINE_END}")?;
+        }
+        for time in &self.times {
+            write!(f, "t={} {}{SDP_LINE_END}", time.start_time, time.stop_time)?;
+            for repeat in &time.repeat_times {
+                write!(f, "r={repeat}{SDP_LINE_END}")?;
+            }
+        }
+        if let Some(time_zones) = &self.time_zones {
+            write!(f, "z={time_zones}{SDP_LINE_END}")?;
         }
+        if let Some(key) = &self.encryption_key {
+            write!(f, "k={key}{SDP_LINE_END}")?;
+        }
+        for attribute in &self.attributes {
+            write!(f, "a={attribute}{SDP_LINE_END}")?;
+        }
+        for media in &self.media_descriptions {
+            write!(f, "{media}")?;
+        }
+        Ok(())
     }
 }
 
@@ -345,7 +1189,7 @@ mod test {
                 media: Media::Audio,
                 port: 5004,
                 protocol: "RTP/AVP".to_owned(),
-                payload_id: 98
+                payload_ids: vec![98]
             })
         );
     }
@@ -375,4 +1219,428 @@ mod test {
             }
         );
     }
+
+    #[test]
+    fn parse_version() {
+        let line = "v=0";
+        let (key, value) = parse_line(line).unwrap().unwrap();
+        assert_eq!(key, "v");
+        assert_eq!(value, SdpValue::ProtocolVersion(0));
+    }
+
+    #[test]
+    fn parse_timing() {
+        let line = "t=0 0";
+        let (key, value) = parse_line(line).unwrap().unwrap();
+        assert_eq!(key, "t");
+        assert_eq!(
+            value,
+            SdpValue::ActiveTime(TimeDescription {
+                start_time: 0,
+                stop_time: 0,
+                repeat_times: Vec::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_attribute_containing_equals_sign() {
+        let line = "a=fmtp:98 channel-order=SMPTE2110.(ST)";
+        let (_, value) = parse_line(line).unwrap().unwrap();
+        assert_eq!(
+            value,
+            SdpValue::Attribute("fmtp:98 channel-order=SMPTE2110.(ST)".to_owned())
+        );
+    }
+
+    const AES67_SDP: &str = "v=0\r\n\
+        o=- 379526672793600 379526672793600 IN IP4 10.1.255.252\r\n\
+        s=CE18707 Send - CE18707 Audio Sender 0\r\n\
+        t=0 0\r\n\
+        m=audio 5004 RTP/AVP 98\r\n\
+        c=IN IP4 239.0.0.1/128\r\n\
+        a=rtcp:5005\r\n\
+        a=source-filter: incl IN IP4 239.0.0.1 10.1.255.252\r\n\
+        a=rtpmap:98 L16/48000/8\r\n\
+        a=fmtp:98 channel-order=SMPTE2110.(U08); measuredsamplerate=47999;\r\n\
+        a=mediaclk:direct=0\r\n\
+        a=ts-refclk:ptp=IEEE1588-2008:00-20-FC-FF-FE-34-73-6E:0\r\n\
+        a=ptime:0.125\r\n";
+
+    #[test]
+    fn parse_session_description() {
+        let sd: SessionDescription = AES67_SDP.parse().unwrap();
+        assert_eq!(sd.version, 0);
+        assert_eq!(sd.origin.unicast_address, "10.1.255.252");
+        assert_eq!(sd.session_name, "CE18707 Send - CE18707 Audio Sender 0");
+        assert_eq!(sd.media_descriptions.len(), 1);
+        let media = &sd.media_descriptions[0];
+        assert_eq!(media.media, "audio");
+        assert_eq!(media.port, 5004);
+        assert_eq!(media.formats, vec!["98".to_owned()]);
+        assert_eq!(media.connections[0].base_address(), "239.0.0.1");
+        assert_eq!(
+            media.attribute("rtpmap").and_then(|a| a.value.as_deref()),
+            Some("98 L16/48000/8")
+        );
+    }
+
+    #[test]
+    fn session_description_round_trip() {
+        let sd: SessionDescription = AES67_SDP.parse().unwrap();
+        assert_eq!(sd.to_string(), AES67_SDP);
+    }
+
+    #[test]
+    fn session_description_round_trip_all_line_types() {
+        let sdp = "v=0\r\n\
+            o=jdoe 2890844526 2890842807 IN IP4 10.47.16.5\r\n\
+            s=SDP Seminar\r\n\
+            i=A Seminar on the session description protocol\r\n\
+            u=http://www.example.com/seminars/sdp.pdf\r\n\
+            e=j.doe@example.com (Jane Doe)\r\n\
+            p=+1 617 555-6011\r\n\
+            c=IN IP4 224.2.17.12/127\r\n\
+            b=AS:128\r\n\
+            t=2873397496 2873404696\r\n\
+            r=7d 1h 0 25h\r\n\
+            z=2882844526 -1h 2898848070 0\r\n\
+            k=prompt\r\n\
+            a=recvonly\r\n\
+            m=audio 49170 RTP/AVP 0\r\n\
+            i=Main audio\r\n\
+            b=AS:64\r\n\
+            m=video 51372/2 RTP/AVP 99 100\r\n\
+            c=IN IP6 ff15::101/3\r\n\
+            a=rtpmap:99 h263-1998/90000\r\n";
+        let sd: SessionDescription = sdp.parse().unwrap();
+        assert_eq!(sd.times[0].repeat_times, vec!["7d 1h 0 25h".to_owned()]);
+        assert_eq!(sd.media_descriptions.len(), 2);
+        assert_eq!(sd.media_descriptions[1].number_of_ports, Some(2));
+        assert_eq!(sd.attribute("recvonly").unwrap().value, None);
+        assert_eq!(sd.to_string(), sdp);
+    }
+
+    const DUAL_SECTION_SDP: &str = "v=0
+        o=- 1 1 IN IP4 10.1.255.252
+        s=Redundant sender
+        t=0 0
+        a=ptime:1
+        m=audio 5004 RTP/AVP 98
+        c=IN IP4 239.0.0.1/32
+        a=rtpmap:98 L24/48000/2
+        a=mid:primary
+        m=audio 5006 RTP/AVP 97
+        c=IN IP4 239.0.1.1/32
+        a=rtpmap:97 L16/48000/8
+        a=ptime:0.125
+        a=mid:secondary
+        ";
+
+    #[test]
+    fn parse_multiple_media_sections() {
+        let descriptors = parse_session_descriptors(DUAL_SECTION_SDP).unwrap();
+        assert_eq!(
+            descriptors,
+            vec![
+                Some(SessionDescriptor {
+                    multicast_address: Ipv4Addr::new(239, 0, 0, 1),
+                    multicast_port: 5004,
+                    bit_depth: BitDepth::L24,
+                    channels: 2,
+                    sample_rate: 48000,
+                    packet_time: 1.0,
+                    mid: Some("primary".to_owned()),
+                    source_filters: Vec::new(),
+                    channel_layout: None,
+                    rtcp_port: None,
+                    rtcp_address: None,
+                    payload_types: vec![98],
+                    ssrc: None,
+                    session_name: Some("Redundant sender".to_owned()),
+                }),
+                Some(SessionDescriptor {
+                    multicast_address: Ipv4Addr::new(239, 0, 1, 1),
+                    multicast_port: 5006,
+                    bit_depth: BitDepth::L16,
+                    channels: 8,
+                    sample_rate: 48000,
+                    packet_time: 0.125,
+                    mid: Some("secondary".to_owned()),
+                    source_filters: Vec::new(),
+                    channel_layout: None,
+                    rtcp_port: None,
+                    rtcp_address: None,
+                    payload_types: vec![97],
+                    ssrc: None,
+                    session_name: Some("Redundant sender".to_owned()),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn select_media_section() {
+        let descriptors = parse_session_descriptors(DUAL_SECTION_SDP).unwrap();
+        let by_index = StreamSelector::Index(1)
+            .select(descriptors.clone())
+            .unwrap();
+        let by_mid: StreamSelector = "secondary".parse().unwrap();
+        assert_eq!(by_index, by_mid.select(descriptors.clone()).unwrap());
+        assert!(StreamSelector::Index(2).select(descriptors).is_err());
+    }
+
+    #[test]
+    fn skip_unusable_media_sections() {
+        let sdp = "v=0
+            o=- 1 1 IN IP4 10.1.255.252
+            s=Partly unusable sender
+            t=0 0
+            m=audio 5004 RTP/AVP 96
+            c=IN IP4 239.0.0.1/32
+            a=rtpmap:96 opus/48000/2
+            a=mid:opus
+            m=audio 5006 RTP/AVP 97
+            c=IN IP4 239.0.1.1/32
+            a=rtpmap:97 L24/48000/2
+            a=ptime:1
+            a=mid:pcm
+            ";
+        let descriptors = parse_session_descriptors(sdp).unwrap();
+        assert_eq!(descriptors.len(), 2);
+        assert_eq!(descriptors[0], None);
+
+        let by_index = StreamSelector::Index(1)
+            .select(descriptors.clone())
+            .unwrap();
+        assert_eq!(by_index.mid.as_deref(), Some("pcm"));
+        let by_mid: StreamSelector = "pcm".parse().unwrap();
+        assert_eq!(by_mid.select(descriptors.clone()).unwrap(), by_index);
+        assert!(matches!(
+            StreamSelector::Index(0).select(descriptors),
+            Err(SdpPlayerError::UnusableStream(_))
+        ));
+
+        let unusable = "v=0
+            s=Unusable sender
+            m=audio 5004 RTP/AVP 96
+            c=IN IP4 239.0.0.1/32
+            a=rtpmap:96 opus/48000/2
+            ";
+        assert!(parse_session_descriptors(unusable).is_err());
+    }
+
+    #[test]
+    fn skip_non_audio_media_sections() {
+        let sdp = "v=0
+            o=- 1 1 IN IP4 10.1.255.252
+            s=Sender with floor control
+            t=0 0
+            m=application 9 UDP/BFCP *
+            c=IN IP4 10.1.255.252
+            a=rtpmap:98 L16/44100/1
+            m=audio 5004 RTP/AVP 98
+            c=IN IP4 239.0.0.1/32
+            a=rtpmap:98 L24/48000/2
+            a=ptime:1
+            ";
+        let descriptors = parse_session_descriptors(sdp).unwrap();
+        assert_eq!(descriptors.len(), 1);
+        let sd = descriptors[0].as_ref().unwrap();
+        assert_eq!(sd.multicast_address, Ipv4Addr::new(239, 0, 0, 1));
+        assert_eq!(sd.bit_depth, BitDepth::L24);
+    }
+
+    #[test]
+    fn select_numeric_mid() {
+        let sdp = "v=0
+            o=- 1 1 IN IP4 10.1.255.252
+            s=Numbered sections
+            t=0 0
+            a=ptime:1
+            m=audio 5004 RTP/AVP 98
+            c=IN IP4 239.0.0.1/32
+            a=rtpmap:98 L24/48000/2
+            a=mid:2
+            m=audio 5006 RTP/AVP 98
+            c=IN IP4 239.0.1.1/32
+            a=rtpmap:98 L24/48000/2
+            a=mid:1
+            ";
+        let descriptors = parse_session_descriptors(sdp).unwrap();
+        let select = |selector: &str| {
+            let selector: StreamSelector = selector.parse().unwrap();
+            selector.select(descriptors.clone()).unwrap().multicast_port
+        };
+        assert_eq!(select("1"), 5006);
+        assert_eq!(select("mid:1"), 5006);
+        assert_eq!(select("mid:2"), 5004);
+        // there is no third section, so this can only be the mid
+        assert_eq!(select("2"), 5004);
+        assert_eq!(
+            "mid:1".parse::<StreamSelector>().unwrap().to_string(),
+            "mid:1"
+        );
+    }
+
+    #[test]
+    fn parse_source_filter() {
+        let attribute = "source-filter: incl IN IP4 239.0.0.1 10.1.255.252 10.1.255.253";
+        let source_filter: SourceFilter = attribute.parse().unwrap();
+        assert_eq!(
+            source_filter,
+            SourceFilter {
+                mode: FilterMode::Include,
+                destination_address: Some(Ipv4Addr::new(239, 0, 0, 1)),
+                source_addresses: vec![
+                    Ipv4Addr::new(10, 1, 255, 252),
+                    Ipv4Addr::new(10, 1, 255, 253)
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn source_filters_apply_to_matching_media_sections() {
+        let sdp = "v=0
+            o=- 1 1 IN IP4 10.1.255.252
+            s=Source filtered sender
+            t=0 0
+            a=source-filter: excl IN IP4 * 10.0.0.66
+            m=audio 5004 RTP/AVP 98
+            c=IN IP4 239.0.0.1/32
+            a=rtpmap:98 L24/48000/2
+            a=ptime:1
+            m=audio 5004 RTP/AVP 98
+            c=IN IP4 239.0.1.1/32
+            a=source-filter: incl IN IP4 239.0.1.1 10.1.255.252
+            a=source-filter: incl IN IP4 239.0.2.1 10.1.255.253
+            a=rtpmap:98 L24/48000/2
+            a=ptime:1
+            ";
+        let descriptors = parse_session_descriptors(sdp).unwrap();
+        let descriptors: Vec<_> = descriptors.into_iter().flatten().collect();
+        assert_eq!(descriptors[0].source_filters[0].mode, FilterMode::Exclude);
+        assert_eq!(
+            descriptors[1].source_filters,
+            vec![SourceFilter {
+                mode: FilterMode::Include,
+                destination_address: Some(Ipv4Addr::new(239, 0, 1, 1)),
+                source_addresses: vec![Ipv4Addr::new(10, 1, 255, 252)],
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_channel_order() {
+        let fmtp: Fmtp = "fmtp:98 channel-order=SMPTE2110.(ST,51,M); measuredsamplerate=47999;"
+            .parse()
+            .unwrap();
+        assert_eq!(fmtp.payload_id, 98);
+        assert_eq!(fmtp.parameter("measuredsamplerate"), Some("47999"));
+
+        let layout: ChannelLayout = fmtp.parameter("channel-order").unwrap().parse().unwrap();
+        assert_eq!(
+            layout.groups,
+            vec![
+                ChannelGroup::new(ChannelGroupKind::Stereo),
+                ChannelGroup::new(ChannelGroupKind::Surround51),
+                ChannelGroup::new(ChannelGroupKind::Mono),
+            ]
+        );
+        assert_eq!(layout.channels(), 9);
+        assert_eq!(layout.to_string(), "SMPTE2110.(ST,51,M)");
+        assert_eq!(layout.channel_labels()[3], "2 51 R");
+        assert_eq!(layout.group_channels(2), Some(vec![3, 4, 5, 6, 7, 8]));
+        assert_eq!(layout.group_channels(4), None);
+        assert_eq!(layout.group_routing(3).unwrap().to_string(), "9:1,9:2");
+
+        let undefined: ChannelLayout = "SMPTE2110.(U08)".parse().unwrap();
+        assert_eq!(undefined.groups, vec![ChannelGroup::undefined(8)]);
+        assert!("SMPTE2110.(XY)".parse::<ChannelLayout>().is_err());
+        assert!("ST,51".parse::<ChannelLayout>().is_err());
+    }
+
+    #[test]
+    fn channel_order_is_added_to_descriptor() {
+        let sd: SessionDescriptor = AES67_SDP.parse().unwrap();
+        assert_eq!(
+            sd.channel_layout,
+            Some(ChannelLayout {
+                groups: vec![ChannelGroup::undefined(8)]
+            })
+        );
+    }
+
+    #[test]
+    fn parse_rtcp_attribute() {
+        assert_eq!(
+            "rtcp:5005".parse::<RtcpAttribute>().unwrap(),
+            RtcpAttribute {
+                port: 5005,
+                address: None
+            }
+        );
+        assert_eq!(
+            "rtcp:53020 IN IP4 239.0.0.2"
+                .parse::<RtcpAttribute>()
+                .unwrap(),
+            RtcpAttribute {
+                port: 53020,
+                address: Some(Ipv4Addr::new(239, 0, 0, 2))
+            }
+        );
+        assert!("rtpmap:98 L16/48000/8".parse::<RtcpAttribute>().is_err());
+
+        let sd: SessionDescriptor = AES67_SDP.parse().unwrap();
+        assert_eq!(sd.rtcp_port, Some(5005));
+        assert_eq!(sd.rtcp_address, None);
+    }
+
+    #[test]
+    fn parse_payload_type_and_ssrc() {
+        let sd: SessionDescriptor = AES67_SDP.parse().unwrap();
+        assert_eq!(sd.payload_types, vec![98]);
+        assert_eq!(sd.ssrc, None);
+        assert_eq!(
+            sd.session_name.as_deref(),
+            Some("CE18707 Send - CE18707 Audio Sender 0")
+        );
+
+        let sdp = AES67_SDP.replace(
+            "a=ptime:0.125\r\n",
+            "a=ptime:0.125\r\na=ssrc:3735928559 cname:sender@10.1.255.252\r\na=ssrc:3735928559 label:main\r\n",
+        );
+        let sd: SessionDescriptor = sdp.parse().unwrap();
+        assert_eq!(sd.ssrc, Some(0xdead_beef));
+    }
+
+    #[test]
+    fn parse_all_formats_of_media_section() {
+        let line = "m=audio 5004 RTP/AVP 96 97";
+        let (_, value) = parse_line(line).unwrap().unwrap();
+        let SdpValue::MediaNameAndTransportAddress(m) = value else {
+            panic!("not an m= line: {value:?}");
+        };
+        assert_eq!(m.payload_ids, vec![96, 97]);
+
+        let sdp = "v=0
+            o=- 1 1 IN IP4 10.1.255.252
+            s=Two formats
+            t=0 0
+            m=audio 5004 RTP/AVP 96 97
+            c=IN IP4 239.0.0.1/32
+            a=rtpmap:97 L24/48000/2
+            a=fmtp:97 channel-order=SMPTE2110.(ST)
+            a=rtpmap:98 L16/44100/1
+            a=fmtp:98 channel-order=SMPTE2110.(M)
+            a=ptime:1
+            ";
+        let sd: SessionDescriptor = sdp.parse().unwrap();
+        assert_eq!(sd.payload_types, vec![96, 97]);
+        assert_eq!(sd.bit_depth, BitDepth::L24);
+        assert_eq!(sd.sample_rate, 48000);
+        assert_eq!(sd.channels, 2);
+        assert_eq!(sd.channel_layout.unwrap().to_string(), "SMPTE2110.(ST)");
+    }
 }
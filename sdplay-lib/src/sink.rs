@@ -0,0 +1,456 @@
+use crate::device::{find_output_device, DeviceSelector};
+use crate::error::{SdpPlayerError, SdpPlayerResult};
+use crate::wav::{WavEncoding, WavFormat, WavWriter};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{FromSample, SampleRate, SizedSample, StreamConfig, SupportedStreamConfig};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Debug};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// Interval at which sinks that aren't driven by a device pull audio.
+const CLOCKED_SINK_INTERVAL: Duration = Duration::from_millis(10);
+/// Interval at which the sizes in the header of a recorded WAV file are brought up to date, so
+/// that the recording stays readable if sdplay is killed.
+const WAV_HEADER_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The format of the audio a sink is fed: interleaved 32 bit float frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SinkFormat {
+    pub channels: u16,
+    pub sample_rate: u32,
+}
+
+/// Fills the given buffer with the next interleaved frames of the played audio.
+pub type Render = Box<dyn FnMut(&mut [f32]) + Send>;
+
+/// Where the played audio goes: an output device, a file, a pipe or nowhere at all.
+///
+/// A sink is first [opened](Self::open) with the format of the stream and picks the format it
+/// is fed in, which playback converts the stream to. It then [runs](Self::run), pulling audio
+/// at its own pace. Sinks that aren't driven by a device clock pull it in real time, so the
+/// jitter buffer behaves the same for all sinks.
+pub trait AudioSink: Send {
+    /// A name for the sink, reported as output device in the playback stats.
+    fn name(&self) -> String;
+
+    /// Picks the format closest to `channels` channels at `sample_rate`.
+    fn open(&mut self, channels: u16, sample_rate: u32) -> SdpPlayerResult<SinkFormat>;
+
+    /// Pulls audio from `render` until `stop` is signalled or dropped, or the sink fails.
+    /// `started` is notified once the sink is running.
+    fn run(
+        self: Box<Self>,
+        render: Render,
+        stop: Receiver<()>,
+        started: Option<oneshot::Sender<()>>,
+    ) -> SdpPlayerResult<()>;
+}
+
+/// Selects the sink to play to.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum SinkSelector {
+    /// the configured output device
+    #[default]
+    Device,
+    /// a 32 bit float WAV file
+    Wav(PathBuf),
+    /// raw interleaved 32 bit float little endian samples on stdout
+    Stdout,
+    /// no output, the audio is only metered
+    Null,
+}
+
+impl FromStr for SinkSelector {
+    type Err = SdpPlayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() || s.eq_ignore_ascii_case("device") {
+            Ok(SinkSelector::Device)
+        } else if s.eq_ignore_ascii_case("stdout") || s == "-" {
+            Ok(SinkSelector::Stdout)
+        } else if s.eq_ignore_ascii_case("null") {
+            Ok(SinkSelector::Null)
+        } else if let Some(path) = s.strip_prefix("wav:").filter(|path| !path.is_empty()) {
+            Ok(SinkSelector::Wav(PathBuf::from(path)))
+        } else {
+            Err(SdpPlayerError::InvalidSink(s.to_owned()))
+        }
+    }
+}
+
+impl fmt::Display for SinkSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkSelector::Device => write!(f, "device"),
+            SinkSelector::Wav(path) => write!(f, "wav:{}", path.display()),
+            SinkSelector::Stdout => write!(f, "stdout"),
+            SinkSelector::Null => write!(f, "null"),
+        }
+    }
+}
+
+/// Creates the sink `selector` refers to, playing to `device` if it selects the output device.
+pub fn open_sink(
+    selector: &SinkSelector,
+    device: &DeviceSelector,
+) -> SdpPlayerResult<Box<dyn AudioSink>> {
+    Ok(match selector {
+        SinkSelector::Device => Box::new(DeviceSink::new(find_output_device(device)?)?),
+        SinkSelector::Wav(path) => Box::new(WavSink::new(path.clone())),
+        SinkSelector::Stdout => Box::<StdoutSink>::default(),
+        SinkSelector::Null => Box::<NullSink>::default(),
+    })
+}
+
+/// Plays to an output device.
+pub struct DeviceSink {
+    device: cpal::Device,
+    name: String,
+    config: Option<SupportedStreamConfig>,
+}
+
+impl DeviceSink {
+    pub fn new(device: cpal::Device) -> SdpPlayerResult<Self> {
+        Ok(DeviceSink {
+            name: device.name()?,
+            device,
+            config: None,
+        })
+    }
+}
+
+impl AudioSink for DeviceSink {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn open(&mut self, channels: u16, sample_rate: u32) -> SdpPlayerResult<SinkFormat> {
+        let default_config = self.device.default_output_config()?;
+        log::info!("Default output config: {:?}", default_config);
+
+        let config = select_output_config(&self.device, &default_config, channels, sample_rate)?;
+        if config.sample_rate().0 != sample_rate {
+            log::info!(
+                "Device does not support {} Hz, resampling to {} Hz",
+                sample_rate,
+                config.sample_rate().0
+            );
+        }
+        let format = SinkFormat {
+            channels: config.channels(),
+            sample_rate: config.sample_rate().0,
+        };
+        self.config = Some(config);
+        Ok(format)
+    }
+
+    fn run(
+        self: Box<Self>,
+        render: Render,
+        stop: Receiver<()>,
+        started: Option<oneshot::Sender<()>>,
+    ) -> SdpPlayerResult<()> {
+        let device_config = self.config.ok_or(SdpPlayerError::NoSupportedOutputConfig)?;
+        let config = StreamConfig {
+            buffer_size: cpal::BufferSize::Default,
+            channels: device_config.channels(),
+            sample_rate: device_config.sample_rate(),
+        };
+        log::info!("Output config: {:?}", config);
+
+        let device = &self.device;
+        match device_config.sample_format() {
+            cpal::SampleFormat::I8 => run_device::<i8>(device, &config, render, stop, started),
+            cpal::SampleFormat::I16 => run_device::<i16>(device, &config, render, stop, started),
+            // cpal::SampleFormat::I24 => run::<I24>(&device, &config),
+            cpal::SampleFormat::I32 => run_device::<i32>(device, &config, render, stop, started),
+            // cpal::SampleFormat::I48 => run::<I48>(&device, &config),
+            cpal::SampleFormat::I64 => run_device::<i64>(device, &config, render, stop, started),
+            cpal::SampleFormat::U8 => run_device::<u8>(device, &config, render, stop, started),
+            cpal::SampleFormat::U16 => run_device::<u16>(device, &config, render, stop, started),
+            // cpal::SampleFormat::U24 => run::<U24>(&device, &config),
+            cpal::SampleFormat::U32 => run_device::<u32>(device, &config, render, stop, started),
+            // cpal::SampleFormat::U48 => run::<U48>(&device, &config),
+            cpal::SampleFormat::U64 => run_device::<u64>(device, &config, render, stop, started),
+            cpal::SampleFormat::F32 => run_device::<f32>(device, &config, render, stop, started),
+            cpal::SampleFormat::F64 => run_device::<f64>(device, &config, render, stop, started),
+            sample_format => Err(SdpPlayerError::UnsupportedSampleFormat(
+                sample_format.to_string(),
+            )),
+        }
+    }
+}
+
+/// Picks the output config of `device` that comes closest to the requested format. Configs with
+/// exactly `channels` channels are preferred, then ones with more channels, and among those the
+/// ones supporting `sample_rate`; if the sample rate is not supported, the closest supported one
+/// is used and the stream is resampled. Among equally suitable configs, the sample format of the
+/// device's default config wins.
+fn select_output_config(
+    device: &cpal::Device,
+    default_config: &SupportedStreamConfig,
+    channels: u16,
+    sample_rate: u32,
+) -> SdpPlayerResult<SupportedStreamConfig> {
+    device
+        .supported_output_configs()?
+        .map(|c| {
+            let rate = sample_rate.clamp(c.min_sample_rate().0, c.max_sample_rate().0);
+            c.with_sample_rate(SampleRate(rate))
+        })
+        .min_by_key(|c| {
+            (
+                c.channels() < channels,
+                c.channels().abs_diff(channels),
+                c.sample_rate().0.abs_diff(sample_rate),
+                // prefer upsampling over downsampling
+                c.sample_rate().0 < sample_rate,
+                c.sample_format() != default_config.sample_format(),
+            )
+        })
+        .ok_or(SdpPlayerError::NoSupportedOutputConfig)
+}
+
+fn run_device<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut render: Render,
+    stop: Receiver<()>,
+    started: Option<oneshot::Sender<()>>,
+) -> SdpPlayerResult<()>
+where
+    T: SizedSample + FromSample<f32> + Send + Debug + 'static,
+{
+    let (error_tx, error_rx) = std::sync::mpsc::channel();
+    let err_fn = move |err| {
+        log::error!("an error occurred on stream: {}", err);
+        if let cpal::StreamError::DeviceNotAvailable = err {
+            error_tx.send(err).ok();
+        }
+    };
+
+    let mut output_samples = Vec::new();
+
+    let data_callback = move |buf: &mut [T], _: &cpal::OutputCallbackInfo| {
+        output_samples.resize(buf.len(), 0.0);
+        render(&mut output_samples);
+
+        for (sample, s) in buf.iter_mut().zip(&output_samples) {
+            *sample = T::from_sample::<f32>(*s);
+        }
+    };
+
+    let stream = device.build_output_stream(config, data_callback, err_fn, None)?;
+    stream.play()?;
+
+    if let Some(started) = started {
+        started.send(()).ok();
+    }
+
+    loop {
+        match stop.recv_timeout(Duration::from_millis(100)) {
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        if let Ok(e) = error_rx.try_recv() {
+            return Err(e.into());
+        }
+    }
+}
+
+/// Records to a 32 bit float WAV file, in the stream's format.
+#[derive(Debug)]
+pub struct WavSink {
+    path: PathBuf,
+    format: Option<SinkFormat>,
+}
+
+impl WavSink {
+    pub fn new(path: PathBuf) -> Self {
+        WavSink { path, format: None }
+    }
+}
+
+impl AudioSink for WavSink {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn open(&mut self, channels: u16, sample_rate: u32) -> SdpPlayerResult<SinkFormat> {
+        let format = SinkFormat {
+            channels,
+            sample_rate,
+        };
+        self.format = Some(format);
+        Ok(format)
+    }
+
+    fn run(
+        self: Box<Self>,
+        render: Render,
+        stop: Receiver<()>,
+        started: Option<oneshot::Sender<()>>,
+    ) -> SdpPlayerResult<()> {
+        let format = self.format.ok_or(SdpPlayerError::OutputFailed)?;
+        let file = BufWriter::new(File::create(&self.path)?);
+        let mut writer = WavWriter::new(
+            file,
+            WavFormat {
+                channels: format.channels,
+                sample_rate: format.sample_rate,
+                encoding: WavEncoding::Float32,
+            },
+        )?;
+        log::info!("Recording to {}", self.path.display());
+
+        let mut header_updated = Instant::now();
+        let result = run_clocked(format, render, stop, started, |samples| {
+            writer.write_samples(samples)?;
+            if header_updated.elapsed() >= WAV_HEADER_UPDATE_INTERVAL {
+                writer.update_header()?;
+                header_updated = Instant::now();
+            }
+            Ok(())
+        });
+        writer.finish()?;
+        result
+    }
+}
+
+/// Writes raw interleaved 32 bit float little endian samples to stdout, in the stream's format,
+/// e.g. to pipe them into `sox -t raw -e floating-point -b 32 -r <rate> -c <channels> -`.
+#[derive(Debug, Default)]
+pub struct StdoutSink {
+    format: Option<SinkFormat>,
+}
+
+impl AudioSink for StdoutSink {
+    fn name(&self) -> String {
+        "stdout".to_owned()
+    }
+
+    fn open(&mut self, channels: u16, sample_rate: u32) -> SdpPlayerResult<SinkFormat> {
+        log::info!("Writing {channels} channels of 32 bit float little endian samples at {sample_rate} Hz to stdout");
+        let format = SinkFormat {
+            channels,
+            sample_rate,
+        };
+        self.format = Some(format);
+        Ok(format)
+    }
+
+    fn run(
+        self: Box<Self>,
+        render: Render,
+        stop: Receiver<()>,
+        started: Option<oneshot::Sender<()>>,
+    ) -> SdpPlayerResult<()> {
+        let format = self.format.ok_or(SdpPlayerError::OutputFailed)?;
+        let mut stdout = io::stdout().lock();
+        let mut bytes = Vec::new();
+        let result = run_clocked(format, render, stop, started, |samples| {
+            bytes.clear();
+            for sample in samples {
+                bytes.extend_from_slice(&sample.to_le_bytes());
+            }
+            stdout.write_all(&bytes)?;
+            stdout.flush()
+        });
+        match result {
+            Err(SdpPlayerError::IoError(e)) if e.kind() == io::ErrorKind::BrokenPipe => {
+                log::info!("Stdout was closed, stopping playback.");
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+/// Discards the audio, which is still metered, in the stream's format.
+#[derive(Debug, Default)]
+pub struct NullSink {
+    format: Option<SinkFormat>,
+}
+
+impl AudioSink for NullSink {
+    fn name(&self) -> String {
+        "null".to_owned()
+    }
+
+    fn open(&mut self, channels: u16, sample_rate: u32) -> SdpPlayerResult<SinkFormat> {
+        let format = SinkFormat {
+            channels,
+            sample_rate,
+        };
+        self.format = Some(format);
+        Ok(format)
+    }
+
+    fn run(
+        self: Box<Self>,
+        render: Render,
+        stop: Receiver<()>,
+        started: Option<oneshot::Sender<()>>,
+    ) -> SdpPlayerResult<()> {
+        let format = self.format.ok_or(SdpPlayerError::OutputFailed)?;
+        run_clocked(format, render, stop, started, |_| Ok(()))
+    }
+}
+
+/// Pulls audio from `render` in real time, every [`CLOCKED_SINK_INTERVAL`], and hands it to
+/// `write`, until `stop` is signalled or dropped.
+fn run_clocked(
+    format: SinkFormat,
+    mut render: Render,
+    stop: Receiver<()>,
+    started: Option<oneshot::Sender<()>>,
+    mut write: impl FnMut(&[f32]) -> io::Result<()>,
+) -> SdpPlayerResult<()> {
+    let start = Instant::now();
+    let mut frames_rendered = 0;
+    let mut samples = Vec::new();
+
+    if let Some(started) = started {
+        started.send(()).ok();
+    }
+
+    loop {
+        match stop.recv_timeout(CLOCKED_SINK_INTERVAL) {
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        let frames_due = (start.elapsed().as_secs_f64() * format.sample_rate as f64) as u64;
+        let frames = frames_due.saturating_sub(frames_rendered) as usize;
+        samples.resize(frames * format.channels as usize, 0.0);
+        render(&mut samples);
+        write(&samples)?;
+        frames_rendered = frames_due;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_sink_selector() {
+        assert_eq!(
+            "device".parse::<SinkSelector>().unwrap(),
+            SinkSelector::Device
+        );
+        assert_eq!("-".parse::<SinkSelector>().unwrap(), SinkSelector::Stdout);
+        assert_eq!("NULL".parse::<SinkSelector>().unwrap(), SinkSelector::Null);
+        let wav = "wav:/tmp/out.wav".parse::<SinkSelector>().unwrap();
+        assert_eq!(wav, SinkSelector::Wav(PathBuf::from("/tmp/out.wav")));
+        assert_eq!(wav.to_string(), "wav:/tmp/out.wav");
+        assert!("wav:".parse::<SinkSelector>().is_err());
+        assert!("speakers".parse::<SinkSelector>().is_err());
+    }
+}
@@ -1,9 +1,25 @@
 use crate::{
     error::{SdpPlayerError, SdpPlayerResult},
-    SessionDescriptor,
+    pcap::{PcapReader, UdpDatagram},
+    rtcp::{goodbye, receiver_report, ReceiverReporter, RtcpPacket, REPORT_INTERVAL},
+    rtp::{Reception, RtpStatistics, RtpStats},
+    FilterMode, SessionDescriptor, SourceFilter,
 };
 use rtp_rs::RtpReader;
-use std::net::Ipv4Addr;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::{
+    collections::hash_map::RandomState,
+    fs::File,
+    future::pending,
+    hash::{BuildHasher, Hasher},
+    io::BufReader,
+    net::{IpAddr, Ipv4Addr, SocketAddrV4},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
 use tokio::{
     net::UdpSocket,
     select, spawn,
@@ -11,12 +27,230 @@ use tokio::{
         broadcast,
         mpsc::{self},
     },
-    time::Instant,
+    task::{spawn_blocking, JoinHandle},
+    time::{interval_at, sleep_until, Instant},
 };
 
+/// Number of sequence numbers the SMPTE ST 2022-7 merger remembers to detect duplicates.
+const MERGE_WINDOW: usize = 1024;
+/// Number of datagrams read ahead of their replay time from a capture.
+const REPLAY_READ_AHEAD: usize = 1024;
+
+/// Packet counters of a single network path ("leg") of a stream.
+#[derive(Debug, Default)]
+pub struct LegStats {
+    received: AtomicU64,
+    lost: AtomicU64,
+    rtp: Mutex<RtpStats>,
+}
+
+impl LegStats {
+    /// Number of RTP packets received on this leg.
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Number of RTP packets detected as missing on this leg, regardless of whether they were
+    /// recovered from the other leg.
+    pub fn lost(&self) -> u64 {
+        self.lost.load(Ordering::Relaxed)
+    }
+
+    /// RFC 3550 reception statistics of this leg.
+    pub fn rtp(&self) -> RtpStats {
+        self.rtp.lock().map(|rtp| rtp.clone()).unwrap_or_default()
+    }
+}
+
+struct Leg {
+    /// the multicast group and port the leg is sent to
+    destination: SocketAddrV4,
+    socket: Option<UdpSocket>,
+    rtcp: Option<RtcpSession>,
+    source_filters: Vec<SourceFilter>,
+    clock_rate: u32,
+    payload_size: usize,
+    payload_types: Vec<u8>,
+    ssrc: Option<u32>,
+    stats: Arc<LegStats>,
+}
+
+impl Leg {
+    fn new(descriptor: &SessionDescriptor, local_address: Ipv4Addr) -> SdpPlayerResult<Self> {
+        let source_filters = leg_source_filters(descriptor);
+        let socket = bind_multicast_socket(
+            descriptor.multicast_address,
+            descriptor.multicast_port,
+            local_address,
+            &source_filters,
+        )?;
+        let rtcp = match descriptor.rtcp_port {
+            Some(port) => {
+                let address = descriptor
+                    .rtcp_address
+                    .unwrap_or(descriptor.multicast_address);
+                match RtcpSession::new(address, port, local_address, &source_filters) {
+                    Ok(rtcp) => Some(rtcp),
+                    Err(e) => {
+                        log::warn!("Could not open RTCP port {address}:{port}, not sending receiver reports: {e}");
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        Ok(Leg {
+            socket: Some(socket),
+            rtcp,
+            ..Leg::replayed(descriptor)
+        })
+    }
+
+    /// A leg whose packets are replayed from a capture, so it has neither socket nor RTCP.
+    fn replayed(descriptor: &SessionDescriptor) -> Self {
+        Leg {
+            destination: SocketAddrV4::new(descriptor.multicast_address, descriptor.multicast_port),
+            socket: None,
+            rtcp: None,
+            source_filters: leg_source_filters(descriptor),
+            clock_rate: descriptor.sample_rate,
+            payload_size: descriptor.buffer_size() as usize / 8,
+            payload_types: descriptor.payload_types.clone(),
+            ssrc: descriptor.ssrc,
+            stats: Arc::default(),
+        }
+    }
+}
+
+/// The source filters of `descriptor` that apply to its multicast group.
+pub(crate) fn leg_source_filters(descriptor: &SessionDescriptor) -> Vec<SourceFilter> {
+    descriptor
+        .source_filters
+        .iter()
+        .filter(|filter| filter.applies_to(descriptor.multicast_address))
+        .cloned()
+        .collect()
+}
+
+/// The RTCP port of a leg, on which sender reports are received and receiver reports are sent.
+struct RtcpSession {
+    socket: UdpSocket,
+    destination: SocketAddrV4,
+    source_filters: Vec<SourceFilter>,
+    ssrc: u32,
+    cname: String,
+}
+
+impl RtcpSession {
+    fn new(
+        address: Ipv4Addr,
+        port: u16,
+        local_address: Ipv4Addr,
+        source_filters: &[SourceFilter],
+    ) -> SdpPlayerResult<Self> {
+        let socket = if address.is_multicast() {
+            bind_multicast_socket(address, port, local_address, source_filters)?
+        } else {
+            let socket = std::net::UdpSocket::bind(SocketAddrV4::new(local_address, port))?;
+            socket.set_nonblocking(true)?;
+            UdpSocket::from_std(socket)?
+        };
+        let ssrc = RandomState::new().build_hasher().finish() as u32;
+        let cname = if local_address.is_unspecified() {
+            format!("sdplay-{ssrc:08x}")
+        } else {
+            format!("sdplay@{local_address}")
+        };
+        Ok(RtcpSession {
+            socket,
+            destination: SocketAddrV4::new(address, port),
+            source_filters: source_filters.to_vec(),
+            ssrc,
+            cname,
+        })
+    }
+
+    /// Sends a receiver report about the leg every [`REPORT_INTERVAL`] and keeps track of the
+    /// sender's reports, until `stop` is signalled. Leaves the session with a BYE.
+    async fn run(self, stats: Arc<LegStats>, clock_rate: u32, mut stop: broadcast::Receiver<()>) {
+        let mut reporter = ReceiverReporter::new(clock_rate);
+        let mut reports = interval_at(Instant::now() + REPORT_INTERVAL, REPORT_INTERVAL);
+        let mut buf = [0; 1500];
+
+        loop {
+            select! {
+                _ = stop.recv() => break,
+                _ = reports.tick() => {
+                    let rtp = stats.rtp();
+                    let block = reporter.report_block(&rtp, std::time::Instant::now());
+                    let report = receiver_report(self.ssrc, block.as_slice(), &self.cname);
+                    if let Err(e) = self.socket.send_to(&report, self.destination).await {
+                        log::warn!("Could not send RTCP receiver report: {e}");
+                    }
+                }
+                recv = self.socket.recv_from(&mut buf) => {
+                    let (len, source) = match recv {
+                        Ok(recv) => recv,
+                        Err(e) => {
+                            log::warn!("Error receiving RTCP, no longer listening for sender reports: {e}");
+                            break;
+                        }
+                    };
+                    if !source_allowed(&self.source_filters, source.ip()) {
+                        continue;
+                    }
+                    let packets = match RtcpPacket::parse_compound(&buf[..len]) {
+                        Ok(packets) => packets,
+                        Err(e) => {
+                            log::debug!("Ignoring RTCP packet from {source}: {e}");
+                            continue;
+                        }
+                    };
+                    let sender = stats.rtp().ssrc;
+                    for packet in packets {
+                        match packet {
+                            RtcpPacket::SenderReport(report) if Some(report.ssrc) == sender => {
+                                log::debug!("RTCP sender report from {source}: {report:?}");
+                                reporter.sender_report(&report, std::time::Instant::now());
+                            }
+                            RtcpPacket::Goodbye { ssrcs } if ssrcs.iter().any(|ssrc| Some(*ssrc) == sender) => {
+                                log::info!("Sender {source} left the RTP session");
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Err(e) = self
+            .socket
+            .send_to(&goodbye(self.ssrc, &self.cname), self.destination)
+            .await
+        {
+            log::warn!("Could not send RTCP BYE: {e}");
+        }
+    }
+}
+
+/// The payload of a received RTP packet together with the header fields needed for playback.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RtpPacket {
+    pub sequence_number: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    pub payload_type: u8,
+    pub payload: Vec<u8>,
+}
+
 pub struct Stream {
     pub descriptor: SessionDescriptor,
-    pub socket: Option<UdpSocket>,
+    legs: Vec<Leg>,
+    stats: Arc<LegStats>,
+    receiver: Option<JoinHandle<SdpPlayerResult<()>>>,
+    rtcp: Vec<JoinHandle<()>>,
+    /// the capture the packets are replayed from instead of being received
+    capture: Option<PcapReader<BufReader<File>>>,
 }
 
 impl Stream {
@@ -24,103 +258,751 @@ impl Stream {
         descriptor: SessionDescriptor,
         local_address: Ipv4Addr,
     ) -> SdpPlayerResult<Self> {
-        let socket = {
-            let socket_addr = format!("{}:{}", local_address, descriptor.multicast_port);
-            log::info!("Binding to local address {socket_addr}");
-            let socket = UdpSocket::bind(socket_addr).await?;
-            log::info!("Joining multicast group {}", descriptor.multicast_address);
-            socket.join_multicast_v4(descriptor.multicast_address, local_address)?;
-            socket
-        };
+        let leg = Leg::new(&descriptor, local_address)?;
 
         Ok(Stream {
             descriptor,
-            socket: Some(socket),
+            legs: vec![leg],
+            stats: Arc::default(),
+            receiver: None,
+            rtcp: Vec::new(),
+            capture: None,
+        })
+    }
+
+    /// Creates a SMPTE ST 2022-7 receiver that joins the multicast groups of both descriptors,
+    /// each on its own local interface, and merges their packets by RTP sequence number so that
+    /// a packet lost on one leg is covered by its copy from the other one.
+    ///
+    /// Both descriptors must describe the same audio format, only their transport may differ.
+    pub async fn new_redundant(
+        primary: SessionDescriptor,
+        primary_local_address: Ipv4Addr,
+        secondary: SessionDescriptor,
+        secondary_local_address: Ipv4Addr,
+    ) -> SdpPlayerResult<Self> {
+        check_redundant(&primary, &secondary)?;
+
+        let primary_leg = Leg::new(&primary, primary_local_address)?;
+        let secondary_leg = Leg::new(&secondary, secondary_local_address)?;
+
+        Ok(Stream {
+            descriptor: primary,
+            legs: vec![primary_leg, secondary_leg],
+            stats: Arc::default(),
+            receiver: None,
+            rtcp: Vec::new(),
+            capture: None,
         })
     }
 
+    /// Creates a stream that replays the packets sent to the multicast address and port of
+    /// `descriptor` from a pcap or pcapng capture, at the timing they were captured with,
+    /// instead of receiving them. The stream ends with the capture.
+    pub fn replay(descriptor: SessionDescriptor, capture: &Path) -> SdpPlayerResult<Self> {
+        let leg = Leg::replayed(&descriptor);
+        Ok(Stream {
+            descriptor,
+            legs: vec![leg],
+            stats: Arc::default(),
+            receiver: None,
+            rtcp: Vec::new(),
+            capture: Some(PcapReader::open(capture)?),
+        })
+    }
+
+    /// Like [`replay`](Self::replay), but replays both legs of a SMPTE ST 2022-7 stream from
+    /// the capture and merges them as [`new_redundant`](Self::new_redundant) does.
+    pub fn replay_redundant(
+        primary: SessionDescriptor,
+        secondary: SessionDescriptor,
+        capture: &Path,
+    ) -> SdpPlayerResult<Self> {
+        check_redundant(&primary, &secondary)?;
+        let legs = vec![Leg::replayed(&primary), Leg::replayed(&secondary)];
+        Ok(Stream {
+            descriptor: primary,
+            legs,
+            stats: Arc::default(),
+            receiver: None,
+            rtcp: Vec::new(),
+            capture: Some(PcapReader::open(capture)?),
+        })
+    }
+
+    /// Packet counters of each leg of the stream, the primary one first. These stay valid after
+    /// the stream has been started and can be polled while it is playing.
+    pub fn leg_stats(&self) -> Vec<Arc<LegStats>> {
+        self.legs.iter().map(|leg| leg.stats.clone()).collect()
+    }
+
+    /// Packet counters of the stream after merging its legs: packets passed on for playback,
+    /// and packets that were not received on any leg. Loss of a redundant stream is only
+    /// counted once a missing packet can no longer arrive in time to be merged, i.e. after
+    /// [`MERGE_WINDOW`] packets; a single leg stream counts it as soon as its leg does.
+    pub fn stats(&self) -> Arc<LegStats> {
+        self.stats.clone()
+    }
+
     pub async fn play(
         &mut self,
         stop: broadcast::Sender<()>,
-    ) -> SdpPlayerResult<mpsc::UnboundedReceiver<Vec<u8>>> {
+    ) -> SdpPlayerResult<mpsc::UnboundedReceiver<RtpPacket>> {
         let mut buf = [0; 102400];
-
-        let mut start = Instant::now();
-        let mut counter = 0;
+        let mut redundant_buf = [0; 102400];
 
         let (tx, rx) = mpsc::unbounded_channel();
 
-        let socket = self
-            .socket
-            .take()
-            .ok_or(SdpPlayerError::ReceiverAlreadystarted)?;
+        let mut leg_states = Vec::new();
+        for leg in &mut self.legs {
+            if let Some(rtcp) = leg.rtcp.take() {
+                self.rtcp.push(spawn(rtcp.run(
+                    leg.stats.clone(),
+                    leg.clock_rate,
+                    stop.subscribe(),
+                )));
+            }
+            leg_states.push(LegState::new(
+                leg.stats.clone(),
+                RtpStatistics::new(leg.clock_rate, Some(leg.payload_size))
+                    .filtered(leg.payload_types.clone(), leg.ssrc),
+            ));
+        }
+        let mut forwarder = Forwarder::new(leg_states, self.stats.clone(), tx);
+
+        if let Some(capture) = self.capture.take() {
+            let legs = self
+                .legs
+                .iter()
+                .map(|leg| (leg.destination, leg.source_filters.clone()))
+                .collect();
+            self.receiver = Some(spawn(replay(capture, legs, forwarder, stop.subscribe())));
+            return Ok(rx);
+        }
+
+        let mut sockets = Vec::new();
+        let mut source_filters = Vec::new();
+        for leg in &mut self.legs {
+            sockets.push(
+                leg.socket
+                    .take()
+                    .ok_or(SdpPlayerError::ReceiverAlreadystarted)?,
+            );
+            source_filters.push(leg.source_filters.clone());
+        }
+        let mut sockets = sockets.into_iter();
+        let socket = sockets.next().expect("a stream has at least one leg");
+        let redundant_socket = sockets.next();
+        let mut source_filters = source_filters.into_iter();
+        let primary_source_filters = source_filters.next().unwrap_or_default();
+        let redundant_source_filters = source_filters.next().unwrap_or_default();
 
         let mut stop = stop.subscribe();
 
-        spawn(async move {
-            let mut previous_sequence_number = None;
+        self.receiver = Some(spawn(async move {
             loop {
-                select! {
+                let (leg, recv) = select! {
                     _ = stop.recv() => { break; },
-                    recv = receive_rtp_payload(&socket, &mut buf) => {
-                        match recv {
-                            Ok(Some((payload,sequence_number))) => {
-
-                                if let Some(previous_sequence_number) = previous_sequence_number {
-                                    let diff = sequence_number - previous_sequence_number;
-                                    if diff < 1 && !(sequence_number == 0 && previous_sequence_number == 65535) {
-                                        log::warn!("Inconsistent RTP sequence number '{sequence_number}', previous was {previous_sequence_number}")
-                                    } else if diff > 1 {
-                                        log::warn!("Detected packet loss, {} packet(s) were not received", diff-1);
-                                    }
-                                }
-                                previous_sequence_number = Some(sequence_number);
-
-                                if start.elapsed().as_secs_f32() >= 1.0 {
-                                    log::debug!(
-                                        "Receiving {} packets/s; payload size: {}",
-                                        counter,
-                                        payload.len()
-                                    );
-                                    counter = 0;
-                                    start = Instant::now();
-                                } else {
-                                    counter += 1;
-                                }
-                                if let Err(e) = tx.send(payload) {
-                                    log::error!("Error forwarding received data: {e}");
-                                    log::warn!("Stopping receiver.");
-                                    break;
-                                }
-                            }
-                            Ok(None) => (),
-                            Err(e) => {
-                                log::error!("Error receiving data: {e}");
-                                log::warn!("Stopping receiver.");
-                                break;
-                            }
-                        }
+                    recv = receive_rtp_payload(&socket, &primary_source_filters, &mut buf) => (0, recv),
+                    recv = receive_optional_rtp_payload(redundant_socket.as_ref(), &redundant_source_filters, &mut redundant_buf) => (1, recv),
+                };
+
+                match forwarder.receive(leg, recv) {
+                    Ok(true) => (),
+                    Ok(false) => break,
+                    Err(e) => {
+                        log::error!("Error receiving data: {e}");
+                        log::warn!("Stopping receiver.");
+                        return Err(e);
                     }
                 }
             }
-        });
+            Ok(())
+        }));
 
         Ok(rx)
     }
+
+    /// Waits for the receiver started by [`play`](Self::play) to end, which happens once
+    /// `stop` was signalled, the packets are no longer consumed, or receiving failed, and
+    /// returns why it ended. Once this returns, the stream's sockets are closed.
+    pub async fn join(&mut self) -> SdpPlayerResult<()> {
+        for rtcp in self.rtcp.drain(..) {
+            rtcp.await?;
+        }
+        match self.receiver.take() {
+            Some(receiver) => receiver.await?,
+            None => Ok(()),
+        }
+    }
+}
+
+struct LegState {
+    stats: Arc<LegStats>,
+    statistics: RtpStatistics,
+}
+
+impl LegState {
+    fn new(stats: Arc<LegStats>, statistics: RtpStatistics) -> Self {
+        LegState { stats, statistics }
+    }
+
+    fn track(&mut self, leg: usize, packet: &RtpPacket, redundant: bool) -> Reception {
+        let reception = self.statistics.track(packet, std::time::Instant::now());
+        self.publish();
+        if reception == Reception::Rejected {
+            return reception;
+        }
+        self.stats.received.fetch_add(1, Ordering::Relaxed);
+
+        let sequence_number = packet.sequence_number;
+        match reception {
+            Reception::InOrder { lost } if lost > 0 => {
+                self.stats.lost.fetch_add(lost, Ordering::Relaxed);
+                if redundant {
+                    log::debug!(
+                        "Detected packet loss on leg {leg}, {lost} packet(s) were not received"
+                    );
+                } else {
+                    log::warn!("Detected packet loss, {lost} packet(s) were not received");
+                }
+            }
+            Reception::Late => {
+                // the packet was counted as lost when it was skipped
+                self.stats
+                    .lost
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |lost| {
+                        Some(lost.saturating_sub(1))
+                    })
+                    .ok();
+                log::debug!("Late RTP packet '{sequence_number}' on leg {leg}");
+            }
+            Reception::Duplicate => {
+                log::debug!("Duplicate RTP packet '{sequence_number}' on leg {leg}");
+            }
+            Reception::Discarded => {
+                let highest = self.statistics.stats().highest_sequence_number as u16;
+                log::warn!("Inconsistent RTP sequence number '{sequence_number}' on leg {leg}, highest so far was {highest}");
+            }
+            Reception::Start | Reception::InOrder { .. } | Reception::Rejected => {}
+        }
+        reception
+    }
+
+    fn reject_malformed(&mut self) {
+        self.statistics.reject_malformed();
+        self.publish();
+    }
+
+    fn publish(&self) {
+        if let Ok(mut rtp) = self.stats.rtp.lock() {
+            rtp.clone_from(self.statistics.stats());
+        }
+    }
+}
+
+/// Passes the packets of all legs of a stream on for playback, tracking them per leg and
+/// merging the legs of a redundant stream.
+struct Forwarder {
+    leg_states: Vec<LegState>,
+    merger: SeamlessMerger,
+    stats: Arc<LegStats>,
+    tx: mpsc::UnboundedSender<RtpPacket>,
+    start: Instant,
+    counter: u32,
+}
+
+impl Forwarder {
+    fn new(
+        leg_states: Vec<LegState>,
+        stats: Arc<LegStats>,
+        tx: mpsc::UnboundedSender<RtpPacket>,
+    ) -> Self {
+        Forwarder {
+            leg_states,
+            merger: SeamlessMerger::default(),
+            stats,
+            tx,
+            start: Instant::now(),
+            counter: 0,
+        }
+    }
+
+    /// Passes `packet`, received on `leg`, on unless it is rejected or a copy of it was passed
+    /// on before. Returns `false` once the packets are no longer consumed.
+    fn forward(&mut self, leg: usize, packet: RtpPacket) -> bool {
+        let redundant = self.leg_states.len() > 1;
+        match self.leg_states[leg].track(leg, &packet, redundant) {
+            // a jump is only followed once the next packet confirms it
+            Reception::Rejected | Reception::Duplicate | Reception::Discarded => return true,
+            // the copy of a restarted sender's first packet may already have come via another leg
+            Reception::Start if !self.merger.has_seen(packet.sequence_number) => {
+                self.merger.restart();
+            }
+            _ => {}
+        }
+
+        if redundant {
+            let accepted = self.merger.accept(packet.sequence_number);
+            self.stats.lost.store(self.merger.lost, Ordering::Relaxed);
+            if !accepted {
+                return true;
+            }
+        } else {
+            let lost = self.leg_states[leg].stats.lost();
+            self.stats.lost.store(lost, Ordering::Relaxed);
+        }
+        self.stats.received.fetch_add(1, Ordering::Relaxed);
+
+        if self.start.elapsed().as_secs_f32() >= 1.0 {
+            log::debug!(
+                "Receiving {} packets/s; payload size: {}",
+                self.counter,
+                packet.payload.len()
+            );
+            self.counter = 0;
+            self.start = Instant::now();
+        } else {
+            self.counter += 1;
+        }
+        if self.tx.send(packet).is_err() {
+            log::debug!("Packets are no longer consumed, stopping receiver.");
+            return false;
+        }
+        true
+    }
+
+    /// Handles what was received on `leg`: passes RTP packets on as [`forward`](Self::forward)
+    /// does and drops datagrams that are no RTP packets. Any other error is returned.
+    fn receive(
+        &mut self,
+        leg: usize,
+        received: SdpPlayerResult<Option<RtpPacket>>,
+    ) -> SdpPlayerResult<bool> {
+        match received {
+            Ok(Some(packet)) => Ok(self.forward(leg, packet)),
+            Ok(None) => Ok(true),
+            Err(SdpPlayerError::RtpReaderError(e)) => {
+                log::debug!("Skipping datagram on leg {leg} that is no RTP packet: {e:?}");
+                self.reject_malformed(leg);
+                Ok(true)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Counts a datagram received on `leg` that is no RTP packet, which is dropped.
+    fn reject_malformed(&mut self, leg: usize) {
+        self.leg_states[leg].reject_malformed();
+    }
+}
+
+/// Merges the packets of the legs of a SMPTE ST 2022-7 stream by only letting through the
+/// first copy of every RTP sequence number.
+struct SeamlessMerger {
+    highest: Option<u16>,
+    seen: Vec<Option<u16>>,
+    /// number of sequence numbers the window has moved past since the first packet
+    advanced: u64,
+    /// sequence numbers that left the window without having been received
+    lost: u64,
+}
+
+impl Default for SeamlessMerger {
+    fn default() -> Self {
+        SeamlessMerger {
+            highest: None,
+            seen: vec![None; MERGE_WINDOW],
+            advanced: 0,
+            lost: 0,
+        }
+    }
+}
+
+impl SeamlessMerger {
+    fn accept(&mut self, sequence_number: u16) -> bool {
+        let slot = sequence_number as usize % MERGE_WINDOW;
+
+        if let Some(highest) = self.highest {
+            let diff = sequence_number.wrapping_sub(highest) as i16;
+            if diff.unsigned_abs() as usize >= MERGE_WINDOW {
+                // a jump beyond the window in either direction means the sequence started over
+                log::debug!("RTP sequence number jumped from {highest} to {sequence_number}");
+                self.restart();
+                self.highest = Some(sequence_number);
+            } else if diff > 0 {
+                for step in 1..=diff as u16 {
+                    let covered = highest.wrapping_add(step);
+                    let leaving = covered.wrapping_sub(MERGE_WINDOW as u16);
+                    // the window shares slots between sequence numbers MERGE_WINDOW apart
+                    if self.advanced + 1 >= MERGE_WINDOW as u64
+                        && self.seen[covered as usize % MERGE_WINDOW] != Some(leaving)
+                    {
+                        self.lost += 1;
+                    }
+                    self.advanced += 1;
+                }
+                self.highest = Some(sequence_number);
+            } else if self.seen[slot] == Some(sequence_number) {
+                return false;
+            }
+        } else {
+            self.highest = Some(sequence_number);
+        }
+
+        self.seen[slot] = Some(sequence_number);
+        true
+    }
+
+    fn has_seen(&self, sequence_number: u16) -> bool {
+        self.seen[sequence_number as usize % MERGE_WINDOW] == Some(sequence_number)
+    }
+
+    /// Forgets the sequence numbers passed so far, but keeps counting the lost ones.
+    fn restart(&mut self) {
+        self.highest = None;
+        self.seen.fill(None);
+        self.advanced = 0;
+    }
+}
+
+fn bind_multicast_socket(
+    multicast_address: Ipv4Addr,
+    multicast_port: u16,
+    local_address: Ipv4Addr,
+    source_filters: &[SourceFilter],
+) -> SdpPlayerResult<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // both legs of a redundant stream may use the same port
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+
+    // binding to the group address keeps sockets of different groups on the same port apart,
+    // which is not supported on windows
+    let bind_address = if cfg!(windows) {
+        local_address
+    } else {
+        multicast_address
+    };
+    let socket_addr = SocketAddrV4::new(bind_address, multicast_port);
+    log::info!("Binding to address {socket_addr}");
+    socket.bind(&SockAddr::from(socket_addr))?;
+
+    let included_sources: Vec<&Ipv4Addr> = source_filters
+        .iter()
+        .filter(|filter| filter.mode == FilterMode::Include)
+        .flat_map(|filter| &filter.source_addresses)
+        .collect();
+
+    if included_sources.is_empty() {
+        log::info!("Joining multicast group {multicast_address} on interface {local_address}");
+        socket.join_multicast_v4(&multicast_address, &local_address)?;
+    } else {
+        for source in included_sources {
+            log::info!("Joining multicast group {multicast_address} from source {source} on interface {local_address}");
+            socket.join_ssm_v4(source, &multicast_address, &local_address)?;
+        }
+    }
+
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// Checks a packet's source address against the source filters of a leg. Excluded sources are
+/// not blocked by the kernel, so they have to be dropped here, included ones are checked again
+/// in case another socket on this host joined the same group for any source.
+pub(crate) fn source_allowed(source_filters: &[SourceFilter], source: IpAddr) -> bool {
+    let IpAddr::V4(source) = source else {
+        return false;
+    };
+
+    let mut included = None;
+    for filter in source_filters {
+        let listed = filter.source_addresses.contains(&source);
+        match filter.mode {
+            FilterMode::Exclude if listed => return false,
+            FilterMode::Exclude => {}
+            FilterMode::Include => included = Some(included.unwrap_or(false) || listed),
+        }
+    }
+    included.unwrap_or(true)
+}
+
+/// Redundant legs must describe the same audio format, only their transport may differ.
+fn check_redundant(
+    primary: &SessionDescriptor,
+    secondary: &SessionDescriptor,
+) -> SdpPlayerResult<()> {
+    if primary.bit_depth != secondary.bit_depth
+        || primary.channels != secondary.channels
+        || primary.sample_rate != secondary.sample_rate
+        || primary.packet_time != secondary.packet_time
+    {
+        return Err(SdpPlayerError::IncompatibleRedundantStreams);
+    }
+    Ok(())
+}
+
+/// Replays the datagrams of `capture` sent to the destination of one of `legs` at their
+/// captured timing, until the capture ends or `stop` is signalled.
+async fn replay(
+    capture: PcapReader<BufReader<File>>,
+    legs: Vec<(SocketAddrV4, Vec<SourceFilter>)>,
+    mut forwarder: Forwarder,
+    mut stop: broadcast::Receiver<()>,
+) -> SdpPlayerResult<()> {
+    let (tx, mut rx) = mpsc::channel::<(usize, UdpDatagram)>(REPLAY_READ_AHEAD);
+    // reading the capture blocks, so it is read ahead on a thread of its own
+    let reader = spawn_blocking(move || {
+        for datagram in capture {
+            let datagram = datagram?;
+            let leg = legs.iter().position(|(destination, source_filters)| {
+                *destination == datagram.destination
+                    && source_allowed(source_filters, IpAddr::V4(*datagram.source.ip()))
+            });
+            if let Some(leg) = leg {
+                if tx.blocking_send((leg, datagram)).is_err() {
+                    break;
+                }
+            }
+        }
+        Ok::<_, SdpPlayerError>(())
+    });
+
+    let mut timing = None;
+    let mut replayed = 0;
+    loop {
+        let (leg, datagram) = select! {
+            _ = stop.recv() => break,
+            received = rx.recv() => match received {
+                Some(received) => received,
+                None => {
+                    log::info!("Replayed {replayed} packets, end of capture reached.");
+                    break;
+                }
+            },
+        };
+
+        let (first, start) = *timing.get_or_insert((datagram.time, Instant::now()));
+        if let Some(offset) = datagram.time.checked_sub(first) {
+            select! {
+                _ = stop.recv() => break,
+                _ = sleep_until(start + offset) => {}
+            }
+        }
+
+        match parse_rtp_packet(&datagram.payload) {
+            Ok(packet) => {
+                replayed += 1;
+                if !forwarder.forward(leg, packet) {
+                    break;
+                }
+            }
+            Err(_) => {
+                log::debug!(
+                    "Skipping datagram from {} that is no RTP packet",
+                    datagram.source
+                );
+                forwarder.reject_malformed(leg);
+            }
+        }
+    }
+
+    // lets the reader stop if it is waiting for the replay to catch up
+    drop(rx);
+    reader.await?
+}
+
+async fn receive_optional_rtp_payload(
+    sock: Option<&UdpSocket>,
+    source_filters: &[SourceFilter],
+    buf: &mut [u8],
+) -> SdpPlayerResult<Option<RtpPacket>> {
+    match sock {
+        Some(sock) => receive_rtp_payload(sock, source_filters, buf).await,
+        None => pending().await,
+    }
 }
 
 async fn receive_rtp_payload(
     sock: &UdpSocket,
+    source_filters: &[SourceFilter],
     buf: &mut [u8],
-) -> SdpPlayerResult<Option<(Vec<u8>, i32)>> {
-    let len = sock.recv(buf).await?;
+) -> SdpPlayerResult<Option<RtpPacket>> {
+    let (len, source) = sock.recv_from(buf).await?;
+    if !source_allowed(source_filters, source.ip()) {
+        log::trace!("Dropping packet from filtered source {source}");
+        return Ok(None);
+    }
     if len > 0 {
-        let rtp = RtpReader::new(&buf[0..len]).map_err(|e| SdpPlayerError::RtpReaderError(e))?;
-        let end = rtp.payload().len() - rtp.padding().unwrap_or(0) as usize;
-        let data = (&rtp.payload()[0..end]).to_owned();
-        let sequence_number: u16 = rtp.sequence_number().into();
-        Ok(Some((data, sequence_number as i32)))
+        parse_rtp_packet(&buf[0..len]).map(Some)
     } else {
         Ok(None)
     }
 }
+
+pub(crate) fn parse_rtp_packet(data: &[u8]) -> SdpPlayerResult<RtpPacket> {
+    let rtp = RtpReader::new(data).map_err(SdpPlayerError::RtpReaderError)?;
+    let end = rtp.payload().len() - rtp.padding().unwrap_or(0) as usize;
+    let payload = rtp.payload()[0..end].to_owned();
+    Ok(RtpPacket {
+        sequence_number: rtp.sequence_number().into(),
+        timestamp: rtp.timestamp(),
+        ssrc: rtp.ssrc(),
+        payload_type: rtp.payload_type(),
+        payload,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn merger_drops_duplicates() {
+        let mut merger = SeamlessMerger::default();
+        assert!(merger.accept(10));
+        assert!(!merger.accept(10));
+        assert!(merger.accept(11));
+        assert!(!merger.accept(11));
+    }
+
+    #[test]
+    fn merger_fills_gaps_from_late_leg() {
+        let mut merger = SeamlessMerger::default();
+        assert!(merger.accept(10));
+        assert!(merger.accept(12));
+        assert!(merger.accept(11));
+        assert!(!merger.accept(12));
+    }
+
+    #[test]
+    fn merger_handles_wraparound() {
+        let mut merger = SeamlessMerger::default();
+        assert!(merger.accept(65534));
+        assert!(merger.accept(0));
+        assert!(merger.accept(65535));
+        assert!(!merger.accept(65534));
+        assert!(merger.accept(1));
+    }
+
+    #[test]
+    fn merger_counts_packets_missing_on_all_legs() {
+        let mut merger = SeamlessMerger::default();
+        for sequence_number in 0..2000u16 {
+            // 100 is missing on both legs, 200 only on the first one
+            if sequence_number != 100 && sequence_number != 200 {
+                assert!(merger.accept(sequence_number));
+            }
+            if sequence_number == 210 {
+                assert!(merger.accept(200));
+            }
+        }
+        assert_eq!(merger.lost, 1);
+    }
+
+    #[test]
+    fn merger_restarts_after_large_jumps() {
+        let mut merger = SeamlessMerger::default();
+        assert!(merger.accept(100));
+        assert!(merger.accept(101));
+        // forward, backward and beyond half the sequence number space
+        for sequence_number in [20000, 5, 40000] {
+            assert!(merger.accept(sequence_number));
+            assert!(merger.accept(sequence_number + 1));
+            assert!(!merger.accept(sequence_number));
+        }
+        assert_eq!(merger.lost, 0);
+    }
+
+    fn forwarder(legs: usize) -> (Forwarder, mpsc::UnboundedReceiver<RtpPacket>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let leg_states = (0..legs)
+            .map(|_| LegState::new(Arc::default(), RtpStatistics::new(48_000, None)))
+            .collect();
+        (Forwarder::new(leg_states, Arc::default(), tx), rx)
+    }
+
+    fn packet(sequence_number: u16) -> RtpPacket {
+        RtpPacket {
+            sequence_number,
+            timestamp: sequence_number as u32 * 48,
+            ssrc: 1,
+            payload_type: 98,
+            payload: vec![0; 192],
+        }
+    }
+
+    fn forwarded(rx: &mut mpsc::UnboundedReceiver<RtpPacket>) -> Vec<u16> {
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|packet| packet.sequence_number)
+            .collect()
+    }
+
+    #[test]
+    fn forwarder_follows_sender_restart() {
+        for legs in [1, 2] {
+            let (mut forwarder, mut rx) = forwarder(legs);
+            for sequence_number in (6000..6010).chain(1000..1010) {
+                for leg in 0..legs {
+                    assert!(forwarder.forward(leg, packet(sequence_number)));
+                }
+            }
+            let forwarded = forwarded(&mut rx);
+            // the first packet after the jump is only accepted once the next one confirms it
+            let expected: Vec<u16> = (6000..6010).chain(1001..1010).collect();
+            assert_eq!(forwarded, expected, "{legs} leg(s)");
+            assert_eq!(forwarder.stats.lost(), 0, "{legs} leg(s)");
+        }
+    }
+
+    #[test]
+    fn forwarder_ignores_stray_sequence_numbers() {
+        for legs in [1, 2] {
+            let (mut forwarder, mut rx) = forwarder(legs);
+            for sequence_number in [100, 101, 20000, 102, 103] {
+                for leg in 0..legs {
+                    assert!(forwarder.forward(leg, packet(sequence_number)));
+                }
+            }
+            assert_eq!(forwarded(&mut rx), [100, 101, 102, 103], "{legs} leg(s)");
+            assert_eq!(forwarder.stats.lost(), 0, "{legs} leg(s)");
+        }
+    }
+
+    #[test]
+    fn source_filters() {
+        let sender = IpAddr::V4(Ipv4Addr::new(10, 1, 255, 252));
+        let rogue = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 66));
+        let include = SourceFilter {
+            mode: FilterMode::Include,
+            destination_address: None,
+            source_addresses: vec![Ipv4Addr::new(10, 1, 255, 252)],
+        };
+        let exclude = SourceFilter {
+            mode: FilterMode::Exclude,
+            destination_address: None,
+            source_addresses: vec![Ipv4Addr::new(10, 0, 0, 66)],
+        };
+        assert!(source_allowed(&[], rogue));
+        let include = [include];
+        let exclude = [exclude];
+        assert!(source_allowed(&include, sender));
+        assert!(!source_allowed(&include, rogue));
+        assert!(source_allowed(&exclude, sender));
+        assert!(!source_allowed(&exclude, rogue));
+    }
+
+    #[test]
+    fn receiver_skips_datagrams_that_are_no_rtp() {
+        let (mut forwarder, mut rx) = forwarder(1);
+        assert!(forwarder
+            .receive(0, parse_rtp_packet(b"no rtp").map(Some))
+            .unwrap());
+        assert!(forwarder.receive(0, Ok(Some(packet(1)))).unwrap());
+        assert_eq!(forwarded(&mut rx), vec![1]);
+        assert_eq!(forwarder.leg_states[0].stats.rtp().rejected_malformed, 1);
+
+        let error = std::io::Error::other("socket closed");
+        assert!(forwarder.receive(0, Err(error.into())).is_err());
+    }
+}
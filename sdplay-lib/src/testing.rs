@@ -0,0 +1,32 @@
+//! Fixtures shared by the tests of the crate.
+
+use crate::{BitDepth, SessionDescriptor};
+use std::{fs, net::Ipv4Addr, path::PathBuf};
+
+/// A stereo L24 stream at 48 kHz with 1 ms packets. Tests override what they depend on with
+/// struct update syntax.
+pub(crate) fn descriptor() -> SessionDescriptor {
+    SessionDescriptor {
+        multicast_address: Ipv4Addr::new(239, 1, 1, 1),
+        multicast_port: 5004,
+        bit_depth: BitDepth::L24,
+        channels: 2,
+        sample_rate: 48_000,
+        packet_time: 1.0,
+        mid: None,
+        source_filters: Vec::new(),
+        channel_layout: None,
+        rtcp_port: None,
+        rtcp_address: None,
+        payload_types: Vec::new(),
+        ssrc: None,
+        session_name: None,
+    }
+}
+
+/// A directory for a test to write files to, which does not exist yet.
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sdplay-{name}-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    dir
+}
@@ -0,0 +1,205 @@
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Arc,
+};
+
+/// Time in milliseconds a gain change is spread over, so it doesn't click.
+const RAMP_TIME_MS: f32 = 20.0;
+/// Gain below which the output is considered silent, in dB.
+const MIN_GAIN_DB: f32 = -96.0;
+/// Highest gain that can be set, in dB.
+pub const MAX_GAIN_DB: f32 = 12.0;
+
+/// A volume control shared between the playback engine and whoever adjusts it. Clones refer to
+/// the same volume, changes take effect while playing.
+#[derive(Debug, Clone)]
+pub struct Volume {
+    /// linear gain as `f32` bits
+    gain: Arc<AtomicU32>,
+    muted: Arc<AtomicBool>,
+}
+
+/// A snapshot of a [`Volume`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Object)]
+pub struct VolumeState {
+    /// linear gain, 1.0 being unity gain
+    pub gain: f32,
+    /// gain in dB, 0.0 being unity gain
+    pub gain_db: f32,
+    pub muted: bool,
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Volume {
+            gain: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            muted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl Volume {
+    pub fn new() -> Self {
+        Volume::default()
+    }
+
+    /// The linear gain, regardless of whether the volume is muted.
+    pub fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+
+    /// Sets the linear gain, limited to the range of 0.0 to [`MAX_GAIN_DB`].
+    pub fn set_gain(&self, gain: f32) {
+        let gain = if gain.is_nan() { 0.0 } else { gain };
+        let gain = gain.clamp(0.0, db_to_gain(MAX_GAIN_DB));
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn gain_db(&self) -> f32 {
+        gain_to_db(self.gain())
+    }
+
+    pub fn set_gain_db(&self, gain_db: f32) {
+        if gain_db <= MIN_GAIN_DB {
+            self.set_gain(0.0);
+        } else {
+            self.set_gain(db_to_gain(gain_db));
+        }
+    }
+
+    /// Changes the gain by `delta_db`, starting from the lowest gain if the volume is silent.
+    pub fn adjust_db(&self, delta_db: f32) {
+        self.set_gain_db(self.gain_db().max(MIN_GAIN_DB) + delta_db);
+    }
+
+    pub fn muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    /// Toggles mute and returns whether the volume is muted now.
+    pub fn toggle_mute(&self) -> bool {
+        !self.muted.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    /// The gain that is currently applied to the output, 0.0 if muted.
+    pub fn effective_gain(&self) -> f32 {
+        if self.muted() {
+            0.0
+        } else {
+            self.gain()
+        }
+    }
+
+    pub fn state(&self) -> VolumeState {
+        VolumeState {
+            gain: self.gain(),
+            gain_db: self.gain_db(),
+            muted: self.muted(),
+        }
+    }
+}
+
+/// Applies a [`Volume`] to interleaved audio, ramping linearly to a new gain over
+/// [`RAMP_TIME_MS`] whenever it changes.
+#[derive(Debug)]
+pub struct GainStage {
+    volume: Volume,
+    channels: usize,
+    current: f32,
+    /// largest gain change per frame
+    step: f32,
+}
+
+impl GainStage {
+    pub fn new(volume: Volume, channels: u16, sample_rate: u32) -> Self {
+        let ramp_frames = (sample_rate as f32 * RAMP_TIME_MS / 1_000.0).max(1.0);
+        GainStage {
+            current: volume.effective_gain(),
+            volume,
+            channels: channels.max(1) as usize,
+            step: 1.0 / ramp_frames,
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let target = self.volume.effective_gain();
+
+        if self.current == target {
+            if target != 1.0 {
+                samples.iter_mut().for_each(|s| *s *= target);
+            }
+            return;
+        }
+
+        // ramp in steps relative to unity gain, but at least fast enough to reach any target
+        // within the ramp time
+        let step = self.step * self.current.max(target).max(1.0);
+        for frame in samples.chunks_mut(self.channels) {
+            if self.current < target {
+                self.current = (self.current + step).min(target);
+            } else if self.current > target {
+                self.current = (self.current - step).max(target);
+            }
+            frame.iter_mut().for_each(|s| *s *= self.current);
+        }
+    }
+}
+
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+pub fn gain_to_db(gain: f32) -> f32 {
+    if gain > 0.0 {
+        (20.0 * gain.log10()).max(MIN_GAIN_DB)
+    } else {
+        MIN_GAIN_DB
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_volume_in_db() {
+        let volume = Volume::new();
+        volume.set_gain_db(-6.0);
+        assert!((volume.gain() - 0.501).abs() < 1e-3);
+        volume.set_gain_db(40.0);
+        assert_eq!(volume.gain_db(), MAX_GAIN_DB);
+        volume.set_gain(0.0);
+        volume.adjust_db(6.0);
+        assert_eq!(volume.gain_db(), MIN_GAIN_DB + 6.0);
+        assert!(volume.toggle_mute());
+        assert_eq!(volume.effective_gain(), 0.0);
+        assert!(!volume.toggle_mute());
+    }
+
+    #[test]
+    fn gain_changes_are_ramped() {
+        let volume = Volume::new();
+        let mut stage = GainStage::new(volume.clone(), 1, 1000);
+        volume.set_muted(true);
+
+        let mut samples = vec![1.0; 40];
+        stage.process(&mut samples);
+        // 20 ms at 1 kHz
+        for pair in samples[..20].windows(2) {
+            assert!(pair[1] < pair[0]);
+        }
+        assert!(samples[20..].iter().all(|s| *s == 0.0));
+
+        volume.set_muted(false);
+        let mut samples = vec![1.0; 40];
+        stage.process(&mut samples);
+        assert!(samples[0] > 0.0 && samples[0] < 0.1);
+        assert_eq!(samples[39], 1.0);
+    }
+}
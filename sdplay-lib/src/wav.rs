@@ -0,0 +1,347 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+/// Offset of the RIFF chunk size, which is patched once all data is written.
+const RIFF_SIZE_OFFSET: u64 = 4;
+/// Version of the EBU Tech 3285 `bext` chunk written, the one without loudness fields.
+const BEXT_VERSION: u16 = 1;
+
+/// How samples are stored in a WAV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavEncoding {
+    Pcm16,
+    Pcm24,
+    Pcm32,
+    Float32,
+}
+
+impl WavEncoding {
+    pub fn bytes_per_sample(&self) -> u16 {
+        match self {
+            WavEncoding::Pcm16 => 2,
+            WavEncoding::Pcm24 => 3,
+            WavEncoding::Pcm32 | WavEncoding::Float32 => 4,
+        }
+    }
+
+    fn format_tag(&self) -> u16 {
+        match self {
+            WavEncoding::Float32 => WAVE_FORMAT_IEEE_FLOAT,
+            _ => WAVE_FORMAT_PCM,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavFormat {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub encoding: WavEncoding,
+}
+
+impl WavFormat {
+    pub fn block_align(&self) -> u16 {
+        self.channels * self.encoding.bytes_per_sample()
+    }
+}
+
+/// The broadcast audio extension chunk of a Broadcast Wave Format file (EBU Tech 3285).
+///
+/// Text fields are stored as ASCII and cut to the length the format allows.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BextChunk {
+    /// free description of the sound sequence, up to 256 characters
+    pub description: String,
+    /// name of the originator, up to 32 characters
+    pub originator: String,
+    /// unambiguous reference allocated by the originating organisation, up to 32 characters
+    pub originator_reference: String,
+    /// `yyyy-mm-dd`
+    pub origination_date: String,
+    /// `hh:mm:ss`
+    pub origination_time: String,
+    /// position of the first sample in samples since midnight
+    pub time_reference: u64,
+    /// coding history lines as defined by EBU R98, each ending in `\r\n`
+    pub coding_history: String,
+}
+
+impl BextChunk {
+    fn to_bytes(&self) -> Vec<u8> {
+        fn text(bytes: &mut Vec<u8>, text: &str, len: usize) {
+            let start = bytes.len();
+            bytes.extend(
+                text.chars()
+                    .filter(char::is_ascii)
+                    .take(len)
+                    .map(|c| c as u8),
+            );
+            bytes.resize(start + len, 0);
+        }
+
+        let mut bytes = Vec::new();
+        text(&mut bytes, &self.description, 256);
+        text(&mut bytes, &self.originator, 32);
+        text(&mut bytes, &self.originator_reference, 32);
+        text(&mut bytes, &self.origination_date, 10);
+        text(&mut bytes, &self.origination_time, 8);
+        bytes.extend_from_slice(&(self.time_reference as u32).to_le_bytes());
+        bytes.extend_from_slice(&((self.time_reference >> 32) as u32).to_le_bytes());
+        bytes.extend_from_slice(&BEXT_VERSION.to_le_bytes());
+        // UMID and reserved bytes
+        bytes.resize(bytes.len() + 64 + 190, 0);
+        bytes.extend(
+            self.coding_history
+                .chars()
+                .filter(char::is_ascii)
+                .map(|c| c as u8),
+        );
+        bytes
+    }
+}
+
+/// Writes interleaved samples to a RIFF WAVE file.
+///
+/// The chunk sizes in the header are only known once all samples are written and are filled
+/// in by [`finish`](Self::finish). Files larger than 4 GiB can't be described by a WAV header,
+/// their sizes are capped.
+#[derive(Debug)]
+pub struct WavWriter<W: Write + Seek> {
+    inner: W,
+    format: WavFormat,
+    /// position of the header in `inner`
+    start: u64,
+    /// offset of the data chunk's size field from `start`
+    data_size_offset: u64,
+    data_len: u64,
+    encoded: Vec<u8>,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Writes the header for `format` and returns a writer for the samples.
+    pub fn new(inner: W, format: WavFormat) -> io::Result<Self> {
+        Self::with_bext(inner, format, None)
+    }
+
+    /// Writes the header of a Broadcast Wave Format file with the given `bext` chunk, or of a
+    /// plain WAV file without one, and returns a writer for the samples.
+    pub fn with_bext(
+        mut inner: W,
+        format: WavFormat,
+        bext: Option<&BextChunk>,
+    ) -> io::Result<Self> {
+        let start = inner.stream_position()?;
+        inner.write_all(b"RIFF")?;
+        inner.write_all(&0u32.to_le_bytes())?;
+        inner.write_all(b"WAVE")?;
+
+        if let Some(bext) = bext {
+            let mut bytes = bext.to_bytes();
+            inner.write_all(b"bext")?;
+            inner.write_all(&(bytes.len() as u32).to_le_bytes())?;
+            if bytes.len() % 2 == 1 {
+                bytes.push(0);
+            }
+            inner.write_all(&bytes)?;
+        }
+
+        inner.write_all(b"fmt ")?;
+        inner.write_all(&16u32.to_le_bytes())?;
+        inner.write_all(&format.encoding.format_tag().to_le_bytes())?;
+        inner.write_all(&format.channels.to_le_bytes())?;
+        inner.write_all(&format.sample_rate.to_le_bytes())?;
+        let byte_rate = format.sample_rate * format.block_align() as u32;
+        inner.write_all(&byte_rate.to_le_bytes())?;
+        inner.write_all(&format.block_align().to_le_bytes())?;
+        inner.write_all(&(format.encoding.bytes_per_sample() * 8).to_le_bytes())?;
+
+        inner.write_all(b"data")?;
+        let data_size_offset = inner.stream_position()? - start;
+        inner.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            inner,
+            format,
+            start,
+            data_size_offset,
+            data_len: 0,
+            encoded: Vec::new(),
+        })
+    }
+
+    pub fn format(&self) -> &WavFormat {
+        &self.format
+    }
+
+    /// Number of bytes of sample data written so far.
+    pub fn data_len(&self) -> u64 {
+        self.data_len
+    }
+
+    /// Size of the file written so far, including the header.
+    pub fn file_size(&self) -> u64 {
+        self.data_size_offset + 4 + self.data_len
+    }
+
+    /// Writes samples in the range -1.0 to 1.0, clipping the ones beyond it.
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut encoded = std::mem::take(&mut self.encoded);
+        encoded.clear();
+        for sample in samples {
+            let sample = sample.clamp(-1.0, 1.0);
+            match self.format.encoding {
+                WavEncoding::Pcm16 => {
+                    let value = (sample * i16::MAX as f32).round() as i16;
+                    encoded.extend_from_slice(&value.to_le_bytes());
+                }
+                WavEncoding::Pcm24 => {
+                    let value = (sample as f64 * 8_388_607.0).round() as i32;
+                    encoded.extend_from_slice(&value.to_le_bytes()[..3]);
+                }
+                WavEncoding::Pcm32 => {
+                    let value = (sample as f64 * i32::MAX as f64).round() as i32;
+                    encoded.extend_from_slice(&value.to_le_bytes());
+                }
+                WavEncoding::Float32 => encoded.extend_from_slice(&sample.to_le_bytes()),
+            }
+        }
+        let result = self.write_data(&encoded);
+        self.encoded = encoded;
+        result
+    }
+
+    /// Writes sample data that is already encoded as little endian samples of the writer's
+    /// encoding.
+    pub fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        self.inner.write_all(data)?;
+        self.data_len += data.len() as u64;
+        Ok(())
+    }
+
+    /// Fills in the chunk sizes for the samples written so far, so that the file is readable
+    /// even if it is never finished.
+    pub fn update_header(&mut self) -> io::Result<()> {
+        let end = self.inner.stream_position()?;
+
+        let data_size = self.data_len.min(u32::MAX as u64) as u32;
+        let riff_size = (end - self.start - 8).min(u32::MAX as u64) as u32;
+        self.inner
+            .seek(SeekFrom::Start(self.start + RIFF_SIZE_OFFSET))?;
+        self.inner.write_all(&riff_size.to_le_bytes())?;
+        self.inner
+            .seek(SeekFrom::Start(self.start + self.data_size_offset))?;
+        self.inner.write_all(&data_size.to_le_bytes())?;
+        self.inner.seek(SeekFrom::Start(end))?;
+        self.inner.flush()
+    }
+
+    /// Fills in the chunk sizes and returns the underlying writer, positioned at the end of the
+    /// file.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.data_len % 2 == 1 {
+            // chunks are padded to an even size
+            self.inner.write_all(&[0])?;
+        }
+        self.update_header()?;
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    #[test]
+    fn writes_pcm24_header_and_samples() {
+        let format = WavFormat {
+            channels: 2,
+            sample_rate: 48_000,
+            encoding: WavEncoding::Pcm24,
+        };
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), format).unwrap();
+        writer.write_samples(&[0.0, 1.0, -1.0, 2.0]).unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u16_at(&bytes, 20), WAVE_FORMAT_PCM);
+        assert_eq!(u16_at(&bytes, 22), 2);
+        assert_eq!(u32_at(&bytes, 24), 48_000);
+        assert_eq!(u32_at(&bytes, 28), 48_000 * 6);
+        assert_eq!(u16_at(&bytes, 32), 6);
+        assert_eq!(u16_at(&bytes, 34), 24);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32_at(&bytes, 40), 12);
+        assert_eq!(
+            &bytes[44..],
+            &[0, 0, 0, 0xff, 0xff, 0x7f, 0x01, 0x00, 0x80, 0xff, 0xff, 0x7f]
+        );
+    }
+
+    #[test]
+    fn pads_odd_data_chunk() {
+        let format = WavFormat {
+            channels: 1,
+            sample_rate: 8_000,
+            encoding: WavEncoding::Pcm24,
+        };
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), format).unwrap();
+        writer.write_samples(&[0.5]).unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 4);
+        assert_eq!(u32_at(&bytes, 40), 3);
+        assert_eq!(u32_at(&bytes, 4), 40);
+    }
+
+    #[test]
+    fn writes_bext_chunk() {
+        let format = WavFormat {
+            channels: 1,
+            sample_rate: 48_000,
+            encoding: WavEncoding::Pcm16,
+        };
+        let bext = BextChunk {
+            description: "Studio 1".to_owned(),
+            originator: "sdplay".to_owned(),
+            originator_reference: "ref".to_owned(),
+            origination_date: "2023-11-14".to_owned(),
+            origination_time: "22:13:20".to_owned(),
+            time_reference: 0x1_0000_0002,
+            coding_history: "A=PCM,F=48000,W=16,M=mono\r\n".to_owned(),
+        };
+        let mut writer =
+            WavWriter::with_bext(Cursor::new(Vec::new()), format, Some(&bext)).unwrap();
+        writer.write_data(&[1, 2]).unwrap();
+        assert_eq!(writer.file_size(), 12 + 8 + 630 + 8 + 16 + 8 + 2);
+        let bytes = writer.finish().unwrap().into_inner();
+
+        assert_eq!(&bytes[12..16], b"bext");
+        // the odd chunk is padded, but its size excludes the pad byte
+        assert_eq!(u32_at(&bytes, 16), 602 + 27);
+        let bext_data = &bytes[20..];
+        assert_eq!(&bext_data[..8], b"Studio 1");
+        assert_eq!(bext_data[8], 0);
+        assert_eq!(&bext_data[256..262], b"sdplay");
+        assert_eq!(&bext_data[320..330], b"2023-11-14");
+        assert_eq!(&bext_data[330..338], b"22:13:20");
+        assert_eq!(u32_at(bext_data, 338), 2);
+        assert_eq!(u32_at(bext_data, 342), 1);
+        assert_eq!(u16_at(bext_data, 346), 1);
+        assert_eq!(&bext_data[602..629], b"A=PCM,F=48000,W=16,M=mono\r\n");
+        assert_eq!(&bytes[650..654], b"fmt ");
+        assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+        assert_eq!(&bytes[bytes.len() - 2..], &[1, 2]);
+    }
+}
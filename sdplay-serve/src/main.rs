@@ -1,4 +1,5 @@
 mod poem;
+mod state;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -1,42 +1,70 @@
+use crate::state::{IncidentRequest, Players, RecordingRequest, SourceKind, Status, StreamSource};
+use futures_util::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
 use poem::{listener::TcpListener, web::Data, EndpointExt, Result, Route};
 use poem_openapi::{
-    payload::{Json, PlainText},
-    Object, OpenApi, OpenApiService,
+    param::{Path, Query},
+    payload::{EventStream, Json, PlainText},
+    OpenApi, OpenApiService,
 };
 use sdplay_lib::{
-    audio::play,
-    error::ToSdpPlayerResult,
-    sdp::{session_descriptor_from_sdp_str, session_descriptor_from_sdp_url},
-    stream::Stream,
+    audio::PlaybackConfig,
+    device::{list_output_devices, DeviceSelector, OutputDevice},
+    error::{SdpPlayerError, SdpPlayerResult},
+    incident::Incident,
+    loudness::Loudness,
+    meter::MeterLevels,
+    recorder::RecordingStatus,
+    routing::ChannelRouting,
+    rtp::RtpStats,
+    sdp::{session_descriptor_from_sdp_str, session_descriptor_from_sdp_url, StreamSelector},
+    volume::VolumeState,
     SessionDescriptor,
 };
-use std::{net::Ipv4Addr, time::Duration};
-use tokio::{spawn, sync::broadcast, time::sleep};
+use std::{env, net::Ipv4Addr, path::PathBuf, sync::Arc, time::Duration};
+use tokio::sync::broadcast::error::RecvError;
 use url::Url;
 
-struct Api;
+/// Environment variable naming the directory recordings are written to.
+const RECORDINGS_DIR_VAR: &str = "SDPLAY_RECORDINGS_DIR";
+const DEFAULT_RECORDINGS_DIR: &str = "recordings";
 
-#[derive(Debug, Clone, Object)]
-pub struct Status {
-    playing: bool,
-}
+struct Api;
 
+// endpoints take one argument per query parameter
+#[allow(clippy::too_many_arguments)]
 #[OpenApi]
 impl Api {
     #[oai(path = "/play/descriptor", method = "post")]
     async fn play_sd(
         &self,
-        Data(stop): Data<&broadcast::Sender<()>>,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
         Json(sd): Json<SessionDescriptor>,
+        /// jitter buffer latency in milliseconds
+        Query(latency): Query<Option<f32>>,
+        /// channel routing as comma separated 'input:output[@gain_db]' routes
+        Query(route): Query<Option<String>>,
+        /// only play this channel group (starting at 1) of the stream's channel order
+        Query(group): Query<Option<usize>>,
+        /// output device, either its index or (part of) its name as listed by /devices
+        Query(device): Query<Option<String>>,
     ) -> Result<Json<&'static str>> {
-        stop.send(()).convert()?;
-        sleep(Duration::from_millis(100)).await;
+        let state = players.get(player.as_deref())?;
+        let config = playback_config(latency, route, group, device)?;
 
-        log::info!("Playing SessionDescriptor from URL: {sd:?}");
+        log::info!("Playing SessionDescriptor: {sd:?}");
 
-        let local_address = Ipv4Addr::UNSPECIFIED;
-        let stream = Stream::new(sd, local_address).await?;
-        spawn(play(stream, stop.clone()));
+        let source = StreamSource {
+            kind: SourceKind::Descriptor,
+            url: None,
+            sdp: None,
+            stream: None,
+        };
+        state.switch(sd, source, config).await?;
 
         Ok(Json("Ok"))
     }
@@ -44,18 +72,35 @@ impl Api {
     #[oai(path = "/play/url", method = "post")]
     async fn play_url(
         &self,
-        Data(stop): Data<&broadcast::Sender<()>>,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
         Json(url): Json<Url>,
+        /// media section to play, either its index or its a=mid label, e.g. mid:1
+        Query(stream): Query<Option<String>>,
+        /// jitter buffer latency in milliseconds
+        Query(latency): Query<Option<f32>>,
+        /// channel routing as comma separated 'input:output[@gain_db]' routes
+        Query(route): Query<Option<String>>,
+        /// only play this channel group (starting at 1) of the stream's channel order
+        Query(group): Query<Option<usize>>,
+        /// output device, either its index or (part of) its name as listed by /devices
+        Query(device): Query<Option<String>>,
     ) -> Result<Json<&'static str>> {
-        stop.send(()).convert()?;
-        sleep(Duration::from_millis(100)).await;
+        let state = players.get(player.as_deref())?;
+        let stream = parse_stream_selector(stream)?;
+        let config = playback_config(latency, route, group, device)?;
 
-        log::info!("Playing SDP from URL: {url}");
+        log::info!("Playing stream '{stream}' of SDP from URL: {url}");
 
-        let local_address = Ipv4Addr::UNSPECIFIED;
-        let sd = session_descriptor_from_sdp_url(&url).await?;
-        let stream = Stream::new(sd, local_address).await?;
-        spawn(play(stream, stop.clone()));
+        let sd = session_descriptor_from_sdp_url(&url, &stream).await?;
+        let source = StreamSource {
+            kind: SourceKind::Url,
+            url: Some(url.to_string()),
+            sdp: None,
+            stream: Some(stream.to_string()),
+        };
+        state.switch(sd, source, config).await?;
 
         Ok(Json("Ok"))
     }
@@ -63,52 +108,355 @@ impl Api {
     #[oai(path = "/play/sdp", method = "post")]
     async fn play_sdp(
         &self,
-        Data(stop): Data<&broadcast::Sender<()>>,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
         PlainText(sdp): PlainText<String>,
+        /// media section to play, either its index or its a=mid label, e.g. mid:1
+        Query(stream): Query<Option<String>>,
+        /// jitter buffer latency in milliseconds
+        Query(latency): Query<Option<f32>>,
+        /// channel routing as comma separated 'input:output[@gain_db]' routes
+        Query(route): Query<Option<String>>,
+        /// only play this channel group (starting at 1) of the stream's channel order
+        Query(group): Query<Option<usize>>,
+        /// output device, either its index or (part of) its name as listed by /devices
+        Query(device): Query<Option<String>>,
     ) -> Result<Json<&'static str>> {
-        stop.send(()).convert()?;
-        sleep(Duration::from_millis(100)).await;
+        let state = players.get(player.as_deref())?;
+        let stream = parse_stream_selector(stream)?;
+        let config = playback_config(latency, route, group, device)?;
 
-        log::info!("Playing SDP: {sdp}");
+        log::info!("Playing stream '{stream}' of SDP: {sdp}");
 
-        let local_address = Ipv4Addr::UNSPECIFIED;
-        let sd = session_descriptor_from_sdp_str(&sdp).await?;
-        let stream = Stream::new(sd, local_address).await?;
-        spawn(play(stream, stop.clone()));
+        let sd = session_descriptor_from_sdp_str(&sdp, &stream).await?;
+        let source = StreamSource {
+            kind: SourceKind::Sdp,
+            url: None,
+            sdp: Some(sdp),
+            stream: Some(stream.to_string()),
+        };
+        state.switch(sd, source, config).await?;
 
         Ok(Json("Ok"))
     }
 
     #[oai(path = "/status", method = "get")]
-    async fn status(&self) -> Result<Json<Status>> {
+    async fn status(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
+    ) -> Result<Json<Status>> {
+        let state = players.get(player.as_deref())?;
         log::info!("Getting status");
-        // TODO
-        Ok(Json(Status { playing: true }))
+        Ok(Json(state.status().await))
+    }
+
+    #[oai(path = "/devices", method = "get")]
+    async fn devices(&self) -> Result<Json<Vec<OutputDevice>>> {
+        log::info!("Listing output devices");
+        Ok(Json(list_output_devices()?))
     }
 
     #[oai(path = "/stop", method = "post")]
-    async fn stop(&self, Data(stop): Data<&broadcast::Sender<()>>) -> Result<Json<&'static str>> {
+    async fn stop(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
+    ) -> Result<Json<&'static str>> {
+        let state = players.get(player.as_deref())?;
         log::info!("Stopping receiver");
-        stop.send(()).convert()?;
+        state.stop().await?;
         Ok(Json("Ok"))
     }
 
+    /// Live per-channel levels of the playing stream as server-sent events, about 20 per second.
+    #[oai(path = "/meters", method = "get")]
+    async fn meters(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
+    ) -> Result<EventStream<BoxStream<'static, MeterLevels>>> {
+        let state = players.get(player.as_deref())?;
+        log::info!("Streaming meters of player '{}'", state.id());
+        let levels = stream::unfold(state.meters().subscribe(), |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(levels) => return Some((levels, rx)),
+                    // a slow client just misses some readings
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(EventStream::new(levels.boxed()).keep_alive(Duration::from_secs(5)))
+    }
+
+    /// RFC 3550 reception statistics of the playing stream, one entry per leg with the primary
+    /// one first; empty if nothing is playing.
+    #[oai(path = "/stats", method = "get")]
+    async fn stats(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
+    ) -> Result<Json<Vec<RtpStats>>> {
+        let state = players.get(player.as_deref())?;
+        log::info!("Getting RTP statistics");
+        Ok(Json(state.rtp_stats().await.unwrap_or_default()))
+    }
+
+    /// Momentary, short-term and integrated loudness and loudness range of the playing stream;
+    /// empty if nothing is playing.
+    #[oai(path = "/loudness", method = "get")]
+    async fn loudness(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
+    ) -> Result<Json<Option<Loudness>>> {
+        let state = players.get(player.as_deref())?;
+        log::info!("Getting loudness");
+        Ok(Json(state.loudness().await))
+    }
+
+    /// Starts integrated loudness and loudness range of the playing stream over.
+    #[oai(path = "/loudness/reset", method = "post")]
+    async fn reset_loudness(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
+    ) -> Result<Json<Option<Loudness>>> {
+        let state = players.get(player.as_deref())?;
+        log::info!("Resetting loudness");
+        Ok(Json(state.reset_loudness().await))
+    }
+
+    /// Status of the recording of the player's stream, or of its most recent recording.
+    #[oai(path = "/recording", method = "get")]
+    async fn recording(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
+    ) -> Result<Json<RecordingStatus>> {
+        let state = players.get(player.as_deref())?;
+        log::info!("Getting recording status");
+        Ok(Json(state.recorder().status()))
+    }
+
+    /// Starts recording the player's stream bit-exactly to Broadcast WAV files in the server's
+    /// recordings directory. Recording goes on across streams until stopped.
+    #[oai(path = "/recording/start", method = "post")]
+    async fn start_recording(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
+        Json(request): Json<RecordingRequest>,
+    ) -> Result<Json<RecordingStatus>> {
+        let state = players.get(player.as_deref())?;
+        log::info!("Starting recording of player '{}'", state.id());
+        Ok(Json(state.start_recording(
+            request,
+            players.recordings_dir().clone(),
+        )?))
+    }
+
+    /// Stops recording and returns the final status of the recording.
+    #[oai(path = "/recording/stop", method = "post")]
+    async fn stop_recording(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
+    ) -> Result<Json<RecordingStatus>> {
+        let state = players.get(player.as_deref())?;
+        log::info!("Stopping recording of player '{}'", state.id());
+        let recorder = state.recorder().clone();
+        // finishing the last file may take a moment
+        let status = tokio::task::spawn_blocking(move || {
+            recorder.stop().unwrap_or_else(|| recorder.status())
+        })
+        .await
+        .map_err(SdpPlayerError::from)?;
+        Ok(Json(status))
+    }
+
+    /// Incidents captured for the player so far, oldest first.
+    #[oai(path = "/incidents", method = "get")]
+    async fn incidents(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
+    ) -> Result<Json<Vec<Incident>>> {
+        let state = players.get(player.as_deref())?;
+        log::info!("Listing incidents");
+        Ok(Json(state.incidents().incidents()))
+    }
+
+    /// Starts capturing the audio around packet loss, silence and clipping of the player's
+    /// stream to WAV files in the server's recordings directory. Arming an armed player
+    /// replaces its settings.
+    #[oai(path = "/incidents/arm", method = "post")]
+    async fn arm_incidents(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
+        Json(request): Json<IncidentRequest>,
+    ) -> Result<Json<Vec<Incident>>> {
+        let state = players.get(player.as_deref())?;
+        log::info!("Arming incident capture of player '{}'", state.id());
+        Ok(Json(state.arm_incidents(
+            request,
+            players.recordings_dir().clone(),
+        )?))
+    }
+
+    /// Stops capturing incidents; an incident being captured is still written.
+    #[oai(path = "/incidents/disarm", method = "post")]
+    async fn disarm_incidents(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
+    ) -> Result<Json<Vec<Incident>>> {
+        let state = players.get(player.as_deref())?;
+        log::info!("Disarming incident capture of player '{}'", state.id());
+        state.incidents().disarm();
+        Ok(Json(state.incidents().incidents()))
+    }
+
     #[oai(path = "/volume", method = "get")]
-    async fn get_volume(&self) -> Result<Json<f32>> {
+    async fn get_volume(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
+    ) -> Result<Json<VolumeState>> {
+        let state = players.get(player.as_deref())?;
         log::info!("Getting volume");
-        // TODO
-        Ok(Json(0.5))
+        Ok(Json(state.volume().state()))
     }
 
     #[oai(path = "/volume/set", method = "post")]
-    async fn set_volume(&self, Json(volume): Json<f32>) -> Result<Json<&'static str>> {
-        log::info!("Setting volume to: {volume}");
-        // TODO
+    async fn set_volume(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
+        Json(value): Json<f32>,
+        /// interpret the value as gain in dB instead of linear gain
+        Query(db): Query<Option<bool>>,
+    ) -> Result<Json<VolumeState>> {
+        let state = players.get(player.as_deref())?;
+        if db.unwrap_or(false) {
+            log::info!("Setting volume to: {value} dB");
+            state.volume().set_gain_db(value);
+        } else {
+            log::info!("Setting volume to: {value}");
+            state.volume().set_gain(value);
+        }
+        Ok(Json(state.volume().state()))
+    }
+
+    #[oai(path = "/volume/mute", method = "post")]
+    async fn set_mute(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// player to use, the default player if omitted
+        Query(player): Query<Option<String>>,
+        Json(muted): Json<bool>,
+    ) -> Result<Json<VolumeState>> {
+        let state = players.get(player.as_deref())?;
+        log::info!("Setting mute to: {muted}");
+        state.volume().set_muted(muted);
+        Ok(Json(state.volume().state()))
+    }
+
+    #[oai(path = "/players", method = "get")]
+    async fn players(&self, Data(players): Data<&Arc<Players>>) -> Result<Json<Vec<Status>>> {
+        log::info!("Listing players");
+        let mut statuses = vec![];
+        for state in players.all() {
+            statuses.push(state.status().await);
+        }
+        Ok(Json(statuses))
+    }
+
+    #[oai(path = "/players", method = "post")]
+    async fn create_player(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        /// id of the new player, generated if omitted
+        Query(id): Query<Option<String>>,
+    ) -> Result<Json<Status>> {
+        let state = players.create(id)?;
+        log::info!("Created player '{}'", state.id());
+        Ok(Json(state.status().await))
+    }
+
+    #[oai(path = "/players/:id", method = "get")]
+    async fn player(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        Path(id): Path<String>,
+    ) -> Result<Json<Status>> {
+        log::info!("Getting status of player '{id}'");
+        let state = players.get(Some(&id))?;
+        Ok(Json(state.status().await))
+    }
+
+    #[oai(path = "/players/:id", method = "delete")]
+    async fn delete_player(
+        &self,
+        Data(players): Data<&Arc<Players>>,
+        Path(id): Path<String>,
+    ) -> Result<Json<&'static str>> {
+        log::info!("Removing player '{id}'");
+        players.remove(&id).await?;
         Ok(Json("Ok"))
     }
 }
 
+fn parse_stream_selector(stream: Option<String>) -> SdpPlayerResult<StreamSelector> {
+    Ok(stream
+        .map(|s| s.parse::<StreamSelector>())
+        .transpose()?
+        .unwrap_or_default())
+}
+
+fn playback_config(
+    latency: Option<f32>,
+    route: Option<String>,
+    group: Option<usize>,
+    device: Option<String>,
+) -> SdpPlayerResult<PlaybackConfig> {
+    let mut config = PlaybackConfig::default();
+    if let Some(latency) = latency {
+        config.latency_ms = latency;
+    }
+    if let Some(route) = route {
+        config.routing = route.parse::<ChannelRouting>()?;
+    }
+    config.group = group;
+    if let Some(device) = device {
+        config.device = device.parse::<DeviceSelector>()?;
+    }
+    Ok(config)
+}
+
 pub async fn start() -> anyhow::Result<()> {
+    let recordings_dir = env::var_os(RECORDINGS_DIR_VAR)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_RECORDINGS_DIR));
+
     let public_addr = Ipv4Addr::LOCALHOST;
 
     let bind_addr = Ipv4Addr::UNSPECIFIED;
@@ -125,9 +473,6 @@ pub async fn start() -> anyhow::Result<()> {
 
     log::info!("Starting openapi service at {}", public_url);
 
-    // TODO pass this around as state
-    let (tx_stop, _rx_stop) = broadcast::channel::<()>(1);
-
     let openapi_explorer = api_service.swagger_ui();
     let oapi_spec_json = api_service.spec_endpoint();
     let oapi_spec_yaml = api_service.spec_endpoint_yaml();
@@ -137,7 +482,7 @@ pub async fn start() -> anyhow::Result<()> {
         .nest("/doc", openapi_explorer)
         .nest("/openapi/json", oapi_spec_json)
         .nest("/openapi/yaml", oapi_spec_yaml)
-        .data(tx_stop);
+        .data(Arc::new(Players::new(recordings_dir)));
 
     poem::Server::new(TcpListener::bind(addr)).run(app).await?;
 
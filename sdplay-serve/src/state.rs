@@ -0,0 +1,352 @@
+use poem_openapi::{Enum, Object};
+use sdplay_lib::{
+    audio::PlaybackConfig,
+    error::{SdpPlayerError, SdpPlayerResult},
+    incident::{Incident, IncidentCapture, IncidentConfig},
+    loudness::Loudness,
+    meter::MeterFeed,
+    monitor::PlaybackStats,
+    player::Player,
+    recorder::{Recorder, RecordingConfig, RecordingStatus},
+    rtp::RtpStats,
+    stream::Stream,
+    volume::Volume,
+    SessionDescriptor,
+};
+use std::{
+    collections::BTreeMap,
+    net::Ipv4Addr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+
+/// How the played stream was described when playback was requested.
+#[derive(Debug, Clone, Object)]
+pub struct StreamSource {
+    pub kind: SourceKind,
+    /// the SDP URL, if played from a URL
+    pub url: Option<String>,
+    /// the raw SDP, if played from one
+    pub sdp: Option<String>,
+    /// the selected media section, if played from an SDP
+    pub stream: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum SourceKind {
+    Descriptor,
+    Url,
+    Sdp,
+}
+
+#[derive(Debug, Clone, Object)]
+pub struct Status {
+    /// id of the player
+    id: String,
+    /// whether a stream is currently being played
+    playing: bool,
+    descriptor: Option<SessionDescriptor>,
+    source: Option<StreamSource>,
+    /// seconds since playback of the current stream started
+    uptime_seconds: Option<f64>,
+    /// figures of the current playback, including output device, packet rate, packet loss and
+    /// jitter buffer fill
+    stats: Option<PlaybackStats>,
+    /// the error that stopped the most recent playback, if any
+    last_error: Option<String>,
+}
+
+/// How to record a player's stream. Recordings are written to the server's recordings
+/// directory.
+#[derive(Debug, Clone, Default, Object)]
+pub struct RecordingRequest {
+    /// start of the file names, the player id if omitted
+    pub prefix: Option<String>,
+    /// start a new file every this many seconds
+    pub max_duration_seconds: Option<f64>,
+    /// start a new file before a file grows beyond this many bytes
+    pub max_file_size: Option<u64>,
+}
+
+/// When to capture a player's incidents and how much audio around them. Captured audio is
+/// written to the server's recordings directory.
+#[derive(Debug, Clone, Default, Object)]
+pub struct IncidentRequest {
+    /// start of the file names, '<player>-incident' if omitted
+    pub prefix: Option<String>,
+    /// seconds of audio captured before an incident, 5 if omitted
+    pub pre_roll_seconds: Option<f64>,
+    /// seconds of audio captured after an incident, 5 if omitted
+    pub post_roll_seconds: Option<f64>,
+    /// level in dBFS below which the stream counts as silent, -60 if omitted
+    pub silence_threshold_db: Option<f32>,
+    /// seconds the stream has to be silent to count as an incident, 2 if omitted
+    pub silence_seconds: Option<f64>,
+}
+
+/// Id of the player that requests address if they don't name one. It exists when the server
+/// starts.
+pub const DEFAULT_PLAYER: &str = "default";
+
+/// The named players of the server, each of which can play one stream to its own output.
+#[derive(Debug)]
+pub struct Players {
+    players: Mutex<BTreeMap<String, Arc<PlayerState>>>,
+    next_id: AtomicUsize,
+    /// where the players record to
+    recordings_dir: PathBuf,
+}
+
+impl Players {
+    pub fn new(recordings_dir: PathBuf) -> Self {
+        let default_player = Arc::new(PlayerState::new(DEFAULT_PLAYER.to_owned()));
+        Players {
+            players: Mutex::new(BTreeMap::from([(
+                DEFAULT_PLAYER.to_owned(),
+                default_player,
+            )])),
+            next_id: AtomicUsize::new(1),
+            recordings_dir,
+        }
+    }
+
+    /// The directory all players record to.
+    pub fn recordings_dir(&self) -> &PathBuf {
+        &self.recordings_dir
+    }
+
+    /// The player with the given id, or the default player if `id` is `None`.
+    pub fn get(&self, id: Option<&str>) -> SdpPlayerResult<Arc<PlayerState>> {
+        let id = id.unwrap_or(DEFAULT_PLAYER);
+        self.lock()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| SdpPlayerError::NoSuchPlayer(id.to_owned()))
+    }
+
+    pub fn all(&self) -> Vec<Arc<PlayerState>> {
+        self.lock().values().cloned().collect()
+    }
+
+    /// Adds a player with the given id, or with a generated one if `id` is `None`.
+    pub fn create(&self, id: Option<String>) -> SdpPlayerResult<Arc<PlayerState>> {
+        let mut players = self.lock();
+        let id = match id {
+            Some(id) => {
+                if id.is_empty()
+                    || !id
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                {
+                    return Err(SdpPlayerError::InvalidPlayerId(id));
+                }
+                if players.contains_key(&id) {
+                    return Err(SdpPlayerError::PlayerExists(id));
+                }
+                id
+            }
+            None => loop {
+                let id = format!("player-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+                if !players.contains_key(&id) {
+                    break id;
+                }
+            },
+        };
+        let player = Arc::new(PlayerState::new(id.clone()));
+        players.insert(id, player.clone());
+        Ok(player)
+    }
+
+    /// Stops the player with the given id and removes it.
+    pub async fn remove(&self, id: &str) -> SdpPlayerResult<()> {
+        let player = self
+            .lock()
+            .remove(id)
+            .ok_or_else(|| SdpPlayerError::NoSuchPlayer(id.to_owned()))?;
+        if let Err(e) = player.stop().await {
+            log::warn!("Player '{id}' ended with error: {e}");
+        }
+        let recorder = player.recorder().clone();
+        let incidents = player.incidents().clone();
+        // finishing the last recording and incident files may take a moment
+        tokio::task::spawn_blocking(move || {
+            recorder.stop();
+            incidents.disarm();
+        })
+        .await
+        .map_err(SdpPlayerError::from)?;
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, Arc<PlayerState>>> {
+        // a panic while holding the lock can't leave the map inconsistent
+        self.players
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// A player of the server together with where its stream came from, shared between the request
+/// handlers.
+#[derive(Debug)]
+pub struct PlayerState {
+    id: String,
+    player: tokio::sync::Mutex<Player>,
+    /// the player's volume and meters, reachable without waiting for a start or stop to finish
+    volume: Volume,
+    meters: MeterFeed,
+    recorder: Recorder,
+    incidents: IncidentCapture,
+    source: Mutex<Option<StreamSource>>,
+}
+
+impl PlayerState {
+    pub fn new(id: String) -> Self {
+        let volume = Volume::new();
+        let player = Player::new(volume.clone());
+        PlayerState {
+            id,
+            meters: player.meters().clone(),
+            recorder: player.recorder().clone(),
+            incidents: player.incidents().clone(),
+            player: tokio::sync::Mutex::new(player),
+            volume,
+            source: Mutex::default(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn volume(&self) -> &Volume {
+        &self.volume
+    }
+
+    pub fn meters(&self) -> &MeterFeed {
+        &self.meters
+    }
+
+    pub fn recorder(&self) -> &Recorder {
+        &self.recorder
+    }
+
+    pub fn incidents(&self) -> &IncidentCapture {
+        &self.incidents
+    }
+
+    /// Starts capturing the incidents of the player's stream, the current one and those played
+    /// later, into `directory`.
+    pub fn arm_incidents(
+        &self,
+        request: IncidentRequest,
+        directory: PathBuf,
+    ) -> SdpPlayerResult<Vec<Incident>> {
+        let defaults = IncidentConfig::default();
+        self.incidents.arm(IncidentConfig {
+            directory,
+            prefix: request
+                .prefix
+                .unwrap_or_else(|| format!("{}-incident", self.id)),
+            pre_roll: duration(request.pre_roll_seconds)?.unwrap_or(defaults.pre_roll),
+            post_roll: duration(request.post_roll_seconds)?.unwrap_or(defaults.post_roll),
+            silence_threshold_db: request
+                .silence_threshold_db
+                .unwrap_or(defaults.silence_threshold_db),
+            silence_duration: duration(request.silence_seconds)?
+                .unwrap_or(defaults.silence_duration),
+        })?;
+        Ok(self.incidents.incidents())
+    }
+
+    /// Starts recording the player's stream, the current one and those played later, into
+    /// `directory`.
+    pub fn start_recording(
+        &self,
+        request: RecordingRequest,
+        directory: PathBuf,
+    ) -> SdpPlayerResult<RecordingStatus> {
+        self.recorder.start(RecordingConfig {
+            directory,
+            prefix: request.prefix.unwrap_or_else(|| self.id.clone()),
+            max_duration: duration(request.max_duration_seconds)?,
+            max_file_size: request.max_file_size,
+        })?;
+        Ok(self.recorder.status())
+    }
+
+    /// Stops the current stream, if any, and starts playing `descriptor` instead.
+    pub async fn switch(
+        &self,
+        descriptor: SessionDescriptor,
+        source: StreamSource,
+        config: PlaybackConfig,
+    ) -> SdpPlayerResult<()> {
+        let mut player = self.player.lock().await;
+        let local_address = Ipv4Addr::UNSPECIFIED;
+        let result = player
+            .switch(Stream::new(descriptor, local_address), config)
+            .await;
+        self.set_source(result.is_ok().then_some(source));
+        result
+    }
+
+    /// Stops the current stream and returns the error that ended it, if it failed before.
+    pub async fn stop(&self) -> SdpPlayerResult<()> {
+        let result = self.player.lock().await.stop().await;
+        self.set_source(None);
+        result
+    }
+
+    pub async fn status(&self) -> Status {
+        let player = self.player.lock().await;
+        let playing = player.is_playing();
+        Status {
+            id: self.id.clone(),
+            playing,
+            descriptor: player.descriptor().cloned(),
+            source: if playing { self.source() } else { None },
+            uptime_seconds: player.uptime().map(|uptime| uptime.as_secs_f64()),
+            stats: player.stats(),
+            last_error: player.last_error(),
+        }
+    }
+
+    /// RFC 3550 reception statistics of each leg of the playing stream.
+    pub async fn rtp_stats(&self) -> Option<Vec<RtpStats>> {
+        self.player.lock().await.stats().map(|stats| stats.rtp)
+    }
+
+    pub async fn loudness(&self) -> Option<Loudness> {
+        self.player.lock().await.loudness()
+    }
+
+    pub async fn reset_loudness(&self) -> Option<Loudness> {
+        let player = self.player.lock().await;
+        player.reset_loudness();
+        player.loudness()
+    }
+
+    fn source(&self) -> Option<StreamSource> {
+        self.source.lock().ok().and_then(|source| source.clone())
+    }
+
+    fn set_source(&self, source: Option<StreamSource>) {
+        if let Ok(mut current) = self.source.lock() {
+            *current = source;
+        }
+    }
+}
+
+fn duration(seconds: Option<f64>) -> SdpPlayerResult<Option<Duration>> {
+    seconds
+        .map(|seconds| {
+            Duration::try_from_secs_f64(seconds)
+                .map_err(|_| SdpPlayerError::InvalidRecordingDuration(seconds))
+        })
+        .transpose()
+}
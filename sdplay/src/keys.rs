@@ -0,0 +1,107 @@
+use crate::log_recording;
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    terminal,
+    tty::IsTty,
+};
+use sdplay_lib::{
+    recorder::{Recorder, RecordingConfig},
+    volume::Volume,
+};
+use std::{io::stdin, thread, time::Duration};
+use tokio::sync::broadcast;
+
+/// Volume change per key press in dB.
+const VOLUME_STEP_DB: f32 = 1.0;
+
+/// Puts the terminal into raw mode while it is alive, so single key presses can be read.
+pub struct RawMode;
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+/// Controls `volume` and `recorder` with the keyboard while playing:
+///
+/// - `+` / arrow up: volume up
+/// - `-` / arrow down: volume down
+/// - `m`: toggle mute
+/// - `r`: start recording with `recording`, or stop recording
+/// - `q` / Ctrl+C: stop playback
+///
+/// Does nothing if stdin is not a terminal. Playback is interactive for as long as the returned
+/// guard is kept.
+pub fn handle_keys(
+    volume: Volume,
+    recorder: Recorder,
+    recording: RecordingConfig,
+    stop: broadcast::Sender<()>,
+) -> Option<RawMode> {
+    if !stdin().is_tty() {
+        return None;
+    }
+    if let Err(e) = terminal::enable_raw_mode() {
+        log::warn!("Could not read keys from terminal: {e}");
+        return None;
+    }
+
+    eprint!("+/-: volume, m: mute, r: record, q: quit\r\n");
+
+    let mut stopped = stop.subscribe();
+    thread::spawn(move || loop {
+        if stopped.try_recv().is_ok() {
+            break;
+        }
+        match event::poll(Duration::from_millis(100)) {
+            Ok(false) => continue,
+            Ok(true) => {}
+            Err(e) => {
+                log::error!("Error reading keys: {e}");
+                break;
+            }
+        }
+        let Ok(Event::Key(KeyEvent {
+            code,
+            modifiers,
+            kind: KeyEventKind::Press,
+            ..
+        })) = event::read()
+        else {
+            continue;
+        };
+        match code {
+            KeyCode::Char('+') | KeyCode::Up => volume.adjust_db(VOLUME_STEP_DB),
+            KeyCode::Char('-') | KeyCode::Down => volume.adjust_db(-VOLUME_STEP_DB),
+            KeyCode::Char('m') => {
+                volume.toggle_mute();
+            }
+            KeyCode::Char('r') => {
+                match recorder.stop() {
+                    Some(status) => log_recording(&status),
+                    None => {
+                        if let Err(e) = recorder.start(recording.clone()) {
+                            log::error!("Could not start recording: {e}");
+                        }
+                    }
+                }
+                continue;
+            }
+            KeyCode::Char('q') => {
+                stop.send(()).ok();
+                break;
+            }
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
+                stop.send(()).ok();
+                break;
+            }
+            _ => continue,
+        }
+        let state = volume.state();
+        let muted = if state.muted { " (muted)" } else { "" };
+        eprint!("Volume: {:.1} dB{muted}\r\n", state.gain_db);
+    });
+
+    Some(RawMode)
+}
@@ -1,24 +1,42 @@
+mod keys;
 mod preset;
 
 use crate::preset::{load_presets, save_preset, Preset};
 use anyhow::{anyhow, Ok};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use sdplay_lib::{
-    audio::play,
-    sdp::{session_descriptor_from_sdp_file, session_descriptor_from_sdp_url},
+    audio::{PlaybackConfig, DEFAULT_LATENCY_MS},
+    device::{list_output_devices, DeviceSelector},
+    extract::{extract, ExtractConfig, FlowReport},
+    incident::{IncidentCapture, IncidentConfig},
+    loudness::Loudness,
+    player::Player,
+    recorder::{Recorder, RecordingConfig, RecordingStatus, DEFAULT_RECORDING_PREFIX},
+    routing::ChannelRouting,
+    sdp::{session_descriptors_from_sdp_file, session_descriptors_from_sdp_url, StreamSelector},
+    sink::SinkSelector,
     stream::Stream,
-    BitDepth, SessionDescriptor,
+    volume::Volume,
+    BitDepth, FilterMode, SessionDescriptor, SourceFilter,
 };
 use std::{
+    io::Write,
     net::{Ipv4Addr, SocketAddrV4},
     path::{Path, PathBuf},
+    time::Duration,
 };
-use tokio::sync::broadcast;
+use tokio::{select, sync::broadcast, time::interval};
 use url::Url;
 
+/// How often the loudness readout is printed.
+const LOUDNESS_READOUT_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// SDP URL
     #[arg(short, long)]
     url: Option<Url>,
@@ -27,10 +45,120 @@ struct Args {
     #[arg(short, long)]
     file: Option<PathBuf>,
 
+    /// media section of the SDP to play, either its index or its a=mid label, e.g. mid:1
+    #[arg(long, default_value_t = StreamSelector::default())]
+    stream: StreamSelector,
+
+    /// media section of the SDP to receive as SMPTE ST 2022-7 redundant copy of the played one
+    #[arg(long)]
+    redundant_stream: Option<StreamSelector>,
+
+    /// local interface address to receive the stream on
+    #[arg(long, default_value_t = Ipv4Addr::UNSPECIFIED)]
+    interface: Ipv4Addr,
+
+    /// replay the stream from a pcap or pcapng capture at its captured timing instead of
+    /// receiving it from the network
+    #[arg(long)]
+    pcap: Option<PathBuf>,
+
+    /// local interface address to receive the redundant stream on
+    #[arg(long, default_value_t = Ipv4Addr::UNSPECIFIED)]
+    redundant_interface: Ipv4Addr,
+
+    /// jitter buffer latency in milliseconds
+    #[arg(long, env = "SDPLAY_LATENCY", default_value_t = DEFAULT_LATENCY_MS)]
+    latency: f32,
+
+    /// channel routing as comma separated 'input:output[@gain_db]' routes, e.g. '3:1,4:2' to
+    /// play channels 3 and 4 in stereo or '1+2:1@-6' to sum channels 1 and 2 to mono
+    #[arg(long)]
+    route: Option<ChannelRouting>,
+
+    /// only play this channel group (starting at 1) of the stream's SMPTE 2110 channel order,
+    /// overrides --route
+    #[arg(long)]
+    group: Option<usize>,
+
+    /// output device, either its index or (part of) its name as shown by --list-devices
+    #[arg(long, env = "SDPLAY_DEVICE", default_value_t = DeviceSelector::default())]
+    device: DeviceSelector,
+
+    /// where to play the stream: 'device' for the output device, 'wav:<path>' to record it to a
+    /// WAV file, 'stdout' to write raw 32 bit float little endian samples to stdout or 'null' to
+    /// only meter it
+    #[arg(long, default_value_t = SinkSelector::default())]
+    sink: SinkSelector,
+
+    /// record the received stream bit-exactly to Broadcast WAV files from the start; recording
+    /// can also be toggled with r while playing
+    #[arg(long)]
+    record: bool,
+
+    /// directory recordings are written to
+    #[arg(long, env = "SDPLAY_RECORD_DIR", default_value = ".")]
+    record_dir: PathBuf,
+
+    /// start of the names of recorded files, followed by their UTC start time
+    #[arg(long, default_value = DEFAULT_RECORDING_PREFIX)]
+    record_prefix: String,
+
+    /// start a new recording file every this many seconds
+    #[arg(long)]
+    record_max_duration: Option<f64>,
+
+    /// start a new recording file before a file grows beyond this many bytes
+    #[arg(long)]
+    record_max_size: Option<u64>,
+
+    /// capture the audio around packet loss, silence and clipping to WAV files
+    #[arg(long)]
+    incidents: bool,
+
+    /// directory captured incidents are written to
+    #[arg(long, env = "SDPLAY_INCIDENT_DIR", default_value = ".")]
+    incident_dir: PathBuf,
+
+    /// seconds of audio captured before an incident
+    #[arg(long, default_value_t = 5.0)]
+    pre_roll: f64,
+
+    /// seconds of audio captured after an incident
+    #[arg(long, default_value_t = 5.0)]
+    post_roll: f64,
+
+    /// level in dBFS below which the stream counts as silent
+    #[arg(long, default_value_t = -60.0, allow_hyphen_values = true)]
+    silence_threshold: f32,
+
+    /// seconds the stream has to be silent to count as an incident
+    #[arg(long, default_value_t = 2.0)]
+    silence_duration: f64,
+
+    /// initial volume in dB, adjustable with +/- while playing
+    #[arg(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    volume: f32,
+
+    /// print momentary, short-term and integrated loudness and loudness range every second
+    #[arg(long)]
+    loudness: bool,
+
     /// multicast address
     #[arg(short, long)]
     multicast_address: Option<SocketAddrV4>,
 
+    /// only receive the multicast stream from these senders (source-specific multicast)
+    #[arg(long = "source")]
+    sources: Vec<Ipv4Addr>,
+
+    /// RTP payload types of the multicast stream, packets of other payload types are dropped
+    #[arg(long = "payload-type")]
+    payload_types: Vec<u8>,
+
+    /// only play packets of this RTP SSRC, instead of the first sender heard
+    #[arg(long)]
+    ssrc: Option<u32>,
+
     /// bit depth
     #[arg(short, long, default_value_t = BitDepth::L16)]
     bit_depth: BitDepth,
@@ -58,15 +186,57 @@ struct Args {
     /// list presets and exit
     #[clap(long)]
     ls: bool,
+
+    /// list output devices and exit
+    #[clap(long)]
+    list_devices: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// extract all RTP streams of a pcap or pcapng capture to WAV files and report on their
+    /// packet loss, reordering, jitter and levels
+    Extract(ExtractArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct ExtractArgs {
+    /// pcap or pcapng capture to extract
+    capture: PathBuf,
+
+    /// SDP file describing streams in the capture, may be given several times; the format of
+    /// streams no SDP describes is guessed
+    #[arg(long = "sdp")]
+    sdp_files: Vec<PathBuf>,
+
+    /// directory the WAV files are written to
+    #[arg(long, default_value = ".")]
+    output_dir: PathBuf,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
-    env_logger::init();
+    env_logger::Builder::from_default_env()
+        // the terminal is in raw mode while playing, which needs explicit carriage returns
+        .format(|buf, record| {
+            write!(
+                buf,
+                "[{} {} {}] {}\r\n",
+                buf.timestamp(),
+                buf.default_styled_level(record.level()),
+                record.target(),
+                record.args()
+            )
+        })
+        .init();
 
     let args = Args::parse();
 
+    if let Some(Command::Extract(args)) = args.command {
+        return extract_capture(args).await;
+    }
+
     if args.ls {
         let presets = load_presets().await?;
         for preset in presets.keys() {
@@ -75,40 +245,114 @@ async fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
-    let (tx_stop, _rx_stop) = broadcast::channel(1);
+    if args.list_devices {
+        for device in list_output_devices()? {
+            let default = if device.default { " (default)" } else { "" };
+            println!("{}: {}/{}{default}", device.index, device.host, device.name);
+            for config in device.supported_configs {
+                println!("    {config}");
+            }
+        }
+        return Ok(());
+    }
+
+    // subscribed before keys are read, so that a stop requested while the stream is still being
+    // set up is not missed
+    let (tx_stop, rx_stop) = broadcast::channel(1);
+
+    let volume = Volume::new();
+    volume.set_gain_db(args.volume);
+    let recorder = Recorder::new();
+    let recording = RecordingConfig {
+        directory: args.record_dir,
+        prefix: args.record_prefix,
+        max_duration: args
+            .record_max_duration
+            .map(Duration::try_from_secs_f64)
+            .transpose()?,
+        max_file_size: args.record_max_size,
+    };
+    if args.record {
+        recorder.start(recording.clone())?;
+    }
+    let incidents = IncidentCapture::new();
+    if args.incidents {
+        incidents.arm(IncidentConfig {
+            directory: args.incident_dir,
+            pre_roll: Duration::try_from_secs_f64(args.pre_roll)?,
+            post_roll: Duration::try_from_secs_f64(args.post_roll)?,
+            silence_threshold_db: args.silence_threshold,
+            silence_duration: Duration::try_from_secs_f64(args.silence_duration)?,
+            ..Default::default()
+        })?;
+    }
+    let _raw_mode = keys::handle_keys(volume.clone(), recorder.clone(), recording, tx_stop.clone());
+
+    let receiver = ReceiverOptions {
+        stream: args.stream,
+        redundant_stream: args.redundant_stream,
+        interface: args.interface,
+        redundant_interface: args.redundant_interface,
+        pcap: args.pcap,
+        playback: PlaybackConfig {
+            latency_ms: args.latency,
+            routing: args.route.unwrap_or_default(),
+            group: args.group,
+            device: args.device,
+            sink: args.sink,
+        },
+        volume,
+        loudness: args.loudness,
+        ssrc: args.ssrc,
+        recorder: recorder.clone(),
+        incidents,
+    };
 
     if let Some(preset) = args.preset {
-        play_preset(preset, tx_stop).await?;
+        play_preset(preset, receiver, rx_stop).await?;
     } else if let Some(sdp_url) = args.url {
         if let Some(name) = args.save {
             let preset = Preset {
                 name,
                 sdp_url: Some(sdp_url.to_owned()),
+                stream: Some(receiver.stream.clone()),
+                redundant_stream: receiver.redundant_stream.clone(),
                 ..Default::default()
             };
             if let Err(e) = save_preset(preset).await {
                 log::error!("Could not save preset: {e}");
             }
         }
-        play_sdp_url(&sdp_url, tx_stop).await?;
+        play_sdp_url(&sdp_url, &receiver, rx_stop).await?;
     } else if let Some(sdp_file) = args.file {
         let sdp_file = sdp_file.canonicalize()?;
         if let Some(name) = args.save {
             let preset = Preset {
                 name,
                 local_sdp_file: Some(sdp_file.to_owned()),
+                stream: Some(receiver.stream.clone()),
+                redundant_stream: receiver.redundant_stream.clone(),
                 ..Default::default()
             };
             if let Err(e) = save_preset(preset).await {
                 log::error!("Could not save preset: {e}");
             }
         }
-        play_sdp_file(&sdp_file, tx_stop).await?;
+        play_sdp_file(&sdp_file, &receiver, rx_stop).await?;
     } else if let Some(multicast_address) = args.multicast_address {
         let channels = args.channels;
         let bit_depth = args.bit_depth;
         let sample_rate = args.sample_rate;
         let packet_time = args.time;
+        let source_filters = if args.sources.is_empty() {
+            Vec::new()
+        } else {
+            vec![SourceFilter {
+                mode: FilterMode::Include,
+                destination_address: Some(*multicast_address.ip()),
+                source_addresses: args.sources,
+            }]
+        };
         if let Some(name) = args.save {
             let preset = Preset {
                 name,
@@ -119,6 +363,14 @@ async fn main() -> anyhow::Result<()> {
                     multicast_port: multicast_address.port(),
                     sample_rate,
                     packet_time,
+                    mid: None,
+                    source_filters: source_filters.clone(),
+                    channel_layout: None,
+                    rtcp_port: None,
+                    rtcp_address: None,
+                    payload_types: args.payload_types.clone(),
+                    ssrc: None,
+                    session_name: None,
                 }),
                 ..Default::default()
             };
@@ -134,25 +386,61 @@ async fn main() -> anyhow::Result<()> {
                 channels,
                 sample_rate,
                 packet_time,
+                mid: None,
+                source_filters,
+                channel_layout: None,
+                rtcp_port: None,
+                rtcp_address: None,
+                payload_types: args.payload_types,
+                ssrc: None,
+                session_name: None,
             },
-            tx_stop,
+            &receiver,
+            rx_stop,
         )
         .await?;
     }
 
+    if let Some(status) = recorder.stop() {
+        log_recording(&status);
+    }
+
     Ok(())
 }
 
-async fn play_preset(preset: String, stop: broadcast::Sender<()>) -> anyhow::Result<()> {
+/// Selects which media section(s) of an SDP to play, where to receive them and how to play
+/// them back.
+#[derive(Debug, Clone)]
+struct ReceiverOptions {
+    stream: StreamSelector,
+    redundant_stream: Option<StreamSelector>,
+    interface: Ipv4Addr,
+    redundant_interface: Ipv4Addr,
+    pcap: Option<PathBuf>,
+    playback: PlaybackConfig,
+    volume: Volume,
+    loudness: bool,
+    ssrc: Option<u32>,
+    recorder: Recorder,
+    incidents: IncidentCapture,
+}
+
+async fn play_preset(
+    preset: String,
+    mut receiver: ReceiverOptions,
+    stopped: broadcast::Receiver<()>,
+) -> anyhow::Result<()> {
     log::info!("Playing stream from preset '{preset}'");
     let presets = load_presets().await?;
     if let Some(preset) = presets.get(&preset) {
+        receiver.stream = preset.stream.clone().unwrap_or_default();
+        receiver.redundant_stream = preset.redundant_stream.clone();
         if let Some(sdp_url) = &preset.sdp_url {
-            play_sdp_url(sdp_url, stop).await?;
+            play_sdp_url(sdp_url, &receiver, stopped).await?;
         } else if let Some(sdp_file) = &preset.local_sdp_file {
-            play_sdp_file(&sdp_file, stop).await?;
+            play_sdp_file(sdp_file, &receiver, stopped).await?;
         } else if let Some(sd) = preset.custom_stream.clone() {
-            play_descriptor(sd, stop).await?;
+            play_descriptor(sd, &receiver, stopped).await?;
         }
         Ok(())
     } else {
@@ -160,24 +448,51 @@ async fn play_preset(preset: String, stop: broadcast::Sender<()>) -> anyhow::Res
     }
 }
 
-async fn play_sdp_url(url: &Url, stop: broadcast::Sender<()>) -> anyhow::Result<()> {
-    log::info!("Playing stream from SDP url '{url}'");
+async fn play_sdp_url(
+    url: &Url,
+    receiver: &ReceiverOptions,
+    stopped: broadcast::Receiver<()>,
+) -> anyhow::Result<()> {
+    log::info!("Playing stream '{}' from SDP url '{url}'", receiver.stream);
 
-    let sd = session_descriptor_from_sdp_url(url).await?;
-    do_play_descriptor(sd, stop).await
+    let sds = session_descriptors_from_sdp_url(url).await?;
+    play_selected_descriptors(sds, receiver, stopped).await
 }
 
-async fn play_sdp_file(sdp_file: &Path, stop: broadcast::Sender<()>) -> anyhow::Result<()> {
+async fn play_sdp_file(
+    sdp_file: &Path,
+    receiver: &ReceiverOptions,
+    stopped: broadcast::Receiver<()>,
+) -> anyhow::Result<()> {
     log::info!(
-        "Playing stream from SDP file '{}'",
+        "Playing stream '{}' from SDP file '{}'",
+        receiver.stream,
         sdp_file.as_os_str().to_string_lossy()
     );
 
-    let sd = session_descriptor_from_sdp_file(sdp_file).await?;
-    do_play_descriptor(sd, stop).await
+    let sds = session_descriptors_from_sdp_file(sdp_file).await?;
+    play_selected_descriptors(sds, receiver, stopped).await
+}
+
+async fn play_selected_descriptors(
+    sds: Vec<Option<SessionDescriptor>>,
+    receiver: &ReceiverOptions,
+    stopped: broadcast::Receiver<()>,
+) -> anyhow::Result<()> {
+    let sd = receiver.stream.select(sds.clone())?;
+    let redundant_sd = receiver
+        .redundant_stream
+        .as_ref()
+        .map(|redundant_stream| redundant_stream.select(sds))
+        .transpose()?;
+    do_play_descriptor(sd, redundant_sd, receiver, stopped).await
 }
 
-async fn play_descriptor(sd: SessionDescriptor, stop: broadcast::Sender<()>) -> anyhow::Result<()> {
+async fn play_descriptor(
+    sd: SessionDescriptor,
+    receiver: &ReceiverOptions,
+    stopped: broadcast::Receiver<()>,
+) -> anyhow::Result<()> {
     log::info!(
         "Playing custom stream '{} {}/{}/{}'",
         sd.multicast_address,
@@ -186,16 +501,162 @@ async fn play_descriptor(sd: SessionDescriptor, stop: broadcast::Sender<()>) ->
         sd.channels
     );
 
-    do_play_descriptor(sd, stop).await
+    do_play_descriptor(sd, None, receiver, stopped).await
 }
 
 async fn do_play_descriptor(
-    sd: SessionDescriptor,
-    stop: broadcast::Sender<()>,
+    mut sd: SessionDescriptor,
+    mut redundant_sd: Option<SessionDescriptor>,
+    receiver: &ReceiverOptions,
+    mut stopped: broadcast::Receiver<()>,
 ) -> anyhow::Result<()> {
-    let local_address = Ipv4Addr::UNSPECIFIED;
-    let stream = Stream::new(sd, local_address).await?;
-    play(stream, stop).await?;
+    if let Some(ssrc) = receiver.ssrc {
+        sd.ssrc = Some(ssrc);
+        if let Some(redundant_sd) = &mut redundant_sd {
+            redundant_sd.ssrc = Some(ssrc);
+        }
+    }
+    let stream = if let Some(pcap) = &receiver.pcap {
+        log::info!("Replaying stream from capture '{}'", pcap.display());
+        match redundant_sd {
+            Some(redundant_sd) => Stream::replay_redundant(sd, redundant_sd, pcap)?,
+            None => Stream::replay(sd, pcap)?,
+        }
+    } else if let Some(redundant_sd) = redundant_sd {
+        log::info!(
+            "Receiving redundant copy of stream on {}",
+            redundant_sd.multicast_address
+        );
+        Stream::new_redundant(
+            sd,
+            receiver.interface,
+            redundant_sd,
+            receiver.redundant_interface,
+        )
+        .await?
+    } else {
+        Stream::new(sd, receiver.interface).await?
+    };
+    let mut player = Player::new(receiver.volume.clone())
+        .with_recorder(receiver.recorder.clone())
+        .with_incidents(receiver.incidents.clone());
+    player.start(stream, receiver.playback.clone()).await?;
+
+    let mut readout = interval(LOUDNESS_READOUT_INTERVAL);
+    loop {
+        select! {
+            result = player.wait() => return Ok(result?),
+            _ = stopped.recv() => return Ok(player.stop().await?),
+            _ = readout.tick(), if receiver.loudness => {
+                if let Some(loudness) = player.loudness() {
+                    eprint!("{}\r\n", format_loudness(&loudness));
+                }
+            }
+        }
+    }
+}
+
+async fn extract_capture(args: ExtractArgs) -> anyhow::Result<()> {
+    let mut descriptors = Vec::new();
+    for sdp_file in &args.sdp_files {
+        descriptors.extend(
+            session_descriptors_from_sdp_file(sdp_file)
+                .await?
+                .into_iter()
+                .flatten(),
+        );
+    }
+    let config = ExtractConfig {
+        directory: args.output_dir,
+        descriptors,
+    };
 
+    let capture = args.capture;
+    let reports = tokio::task::spawn_blocking(move || extract(&capture, &config)).await??;
+    if reports.is_empty() {
+        println!("No RTP streams found.");
+    }
+    for (i, report) in reports.iter().enumerate() {
+        println!("{}", format_flow_report(i + 1, report));
+    }
     Ok(())
 }
+
+fn format_flow_report(number: usize, report: &FlowReport) -> String {
+    let mut lines = vec![format!(
+        "#{number} {} -> {} SSRC {:#010x} PT {}",
+        report.source, report.destination, report.ssrc, report.payload_type
+    )];
+    if let Some(sd) = &report.descriptor {
+        let origin = match (report.guessed, &sd.mid) {
+            (true, _) => " (guessed)".to_owned(),
+            (false, Some(mid)) => format!(" (SDP, mid {mid})"),
+            (false, None) => " (SDP)".to_owned(),
+        };
+        lines.push(format!(
+            "  format: {}/{}/{}, {} ms packets{origin}",
+            sd.bit_depth, sd.sample_rate, sd.channels, sd.packet_time
+        ));
+    }
+    if let Some(file) = &report.file {
+        lines.push(format!(
+            "  file: {} ({:.3} s)",
+            file.display(),
+            report.duration.as_secs_f64()
+        ));
+    }
+    let stats = &report.stats;
+    if report.descriptor.is_some() {
+        lines.push(format!(
+            "  packets: {} received, {} lost, {} out of order, {} late, {} duplicates, jitter {:.3} ms",
+            stats.packets_received,
+            stats.packets_lost,
+            stats.out_of_order,
+            report.late_packets,
+            stats.duplicates,
+            stats.jitter_ms
+        ));
+    }
+    for (channel, level) in report.levels.iter().enumerate() {
+        let label = match &level.label {
+            Some(label) => format!(" ({label})"),
+            None => String::new(),
+        };
+        lines.push(format!(
+            "  channel {}{label}: peak {:.1} dBFS, RMS {:.1} dBFS, true peak {:.1} dBTP",
+            channel + 1,
+            level.peak_db,
+            level.rms_db,
+            level.true_peak_db
+        ));
+    }
+    if let Some(error) = &report.error {
+        lines.push(format!("  error: {error}"));
+    }
+    lines.join("\n")
+}
+
+fn log_recording(status: &RecordingStatus) {
+    log::info!(
+        "Recorded {:.1} s to {}",
+        status.duration_seconds,
+        status.files.join(", ")
+    );
+    if let Some(error) = &status.error {
+        log::error!("Recording failed: {error}");
+    }
+}
+
+fn format_loudness(loudness: &Loudness) -> String {
+    let value = |value: Option<f64>| match value {
+        Some(value) => format!("{value:.1}"),
+        None => "-".to_owned(),
+    };
+    format!(
+        "M: {} LUFS  S: {} LUFS  I: {} LUFS  LRA: {} LU",
+        value(loudness.momentary_lufs),
+        value(loudness.short_term_lufs),
+        value(loudness.integrated_lufs),
+        value(loudness.loudness_range_lu)
+    )
+}
@@ -1,5 +1,8 @@
 use crate::SessionDescriptor;
-use sdplay_lib::error::{SdpPlayerError, SdpPlayerResult};
+use sdplay_lib::{
+    error::{SdpPlayerError, SdpPlayerResult},
+    sdp::StreamSelector,
+};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, path::PathBuf};
 use tokio::fs;
@@ -17,6 +20,10 @@ pub struct Preset {
     pub sdp_url: Option<Url>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub custom_stream: Option<SessionDescriptor>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub stream: Option<StreamSelector>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub redundant_stream: Option<StreamSelector>,
 }
 
 pub async fn load_presets() -> SdpPlayerResult<HashMap<String, Preset>> {